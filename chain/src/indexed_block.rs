@@ -11,15 +11,36 @@ use std::cmp::PartialEq;
 use primitives::H256;
 
 use block::Block;
+use indexed_transaction::IndexedTransaction;
 
+#[derive(Debug, Clone)]
 pub struct IndexedBlock {
     pub id: H256,
     pub raw: Block,
+    /// The block transactions (without the miner transaction) in the same
+    /// order as `raw.tx_hashes`, it's empty if they weren't loaded.
+    pub transactions: Vec<IndexedTransaction>,
 }
 
 impl IndexedBlock {
     pub fn new(block: Block, id: H256) -> IndexedBlock {
-        IndexedBlock { id, raw: block }
+        IndexedBlock {
+            id,
+            raw: block,
+            transactions: Vec::new(),
+        }
+    }
+
+    /// Creates an `IndexedBlock` carrying the given transactions.
+    pub fn with_transactions(block: Block,
+                             id: H256,
+                             transactions: Vec<IndexedTransaction>)
+                             -> IndexedBlock {
+        IndexedBlock {
+            id,
+            raw: block,
+            transactions,
+        }
     }
 
     pub fn id(&self) -> &H256 {
@@ -32,6 +53,7 @@ impl From<Block> for IndexedBlock {
         IndexedBlock {
             id: block.id(),
             raw: block,
+            transactions: Vec::new(),
         }
     }
 }
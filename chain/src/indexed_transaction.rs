@@ -0,0 +1,56 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp::PartialEq;
+
+use primitives::H256;
+use format::Error;
+
+use transaction::Transaction;
+
+/// A transaction with it's id already calculated.
+#[derive(Debug, Clone)]
+pub struct IndexedTransaction {
+    pub id: H256,
+    pub raw: Transaction,
+}
+
+impl IndexedTransaction {
+    pub fn new(transaction: Transaction, id: H256) -> IndexedTransaction {
+        IndexedTransaction {
+            id,
+            raw: transaction,
+        }
+    }
+
+    /// Parses a transaction blob and calculates it's id.
+    pub fn from_bytes<T: AsRef<[u8]>>(v: T) -> Result<IndexedTransaction, Error> {
+        Transaction::from_bytes(v).map(IndexedTransaction::from)
+    }
+
+    pub fn id(&self) -> &H256 {
+        &self.id
+    }
+}
+
+impl From<Transaction> for IndexedTransaction {
+    fn from(transaction: Transaction) -> IndexedTransaction {
+        IndexedTransaction {
+            id: transaction.id(),
+            raw: transaction,
+        }
+    }
+}
+
+impl PartialEq for IndexedTransaction {
+    fn eq(&self, other: &IndexedTransaction) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for IndexedTransaction {}
@@ -19,7 +19,9 @@ pub mod transaction;
 mod block;
 mod block_header;
 mod indexed_block;
+mod indexed_transaction;
 
 pub use block::Block;
 pub use block_header::BlockHeader;
 pub use indexed_block::IndexedBlock;
+pub use indexed_transaction::IndexedTransaction;
@@ -63,7 +63,7 @@ impl Deserialize for Transaction {
 
                 SignatureType::Normal(signatures)
             }
-            2 => unimplemented!(),
+            2 => return Err(Error::custom("RingCT transactions aren't supported (yet)")),
             n => return Err(Error::custom(format!("invalid transaction version ({})", n))),
         };
 
@@ -21,18 +21,23 @@ use sanakirja;
 use parking_lot::RwLock;
 use bytes::{Buf, IntoBuf, LittleEndian};
 
-use chain::IndexedBlock;
+use chain::{IndexedBlock, IndexedTransaction};
+use chain::transaction::TxIn;
 use format::to_binary;
+use keys::KeyImage;
 use primitives::H256;
 use storage::{BestBlock, BlockChain, BlockProvider, IndexedBlockProvider, BlockRef, Store,
-              CanonStore};
+              CanonStore, TransactionProvider, KeyImageProvider};
 
-use kv::{Key, Value, KeyValue, KeyState, KeyValueDatabase, DiskDb, Transaction};
+use kv::{Key, Value, KeyValue, KeyState, KeyValueDatabase, DiskDb, MemoryDb, Transaction};
 use error::Error;
 
 const KEY_BEST_BLOCK_HEIGHT: &'static str = "best_block_height";
 const KEY_BEST_BLOCK_ID: &'static str = "best_block_id";
 
+/// How many cumulative difficulties are written at once when backfilling.
+const BACKFILL_BATCH_SIZE: u64 = 10_000;
+
 /// A blockchain database.
 #[derive(Debug)]
 pub struct BlockChainDatabase<DB: KeyValueDatabase> {
@@ -55,6 +60,16 @@ impl BlockChainDatabase<DiskDb> {
     }
 }
 
+impl BlockChainDatabase<MemoryDb> {
+    /// Creates an empty database that lives in memory.
+    pub fn in_memory() -> BlockChainDatabase<MemoryDb> {
+        BlockChainDatabase {
+            db: MemoryDb::new(),
+            best_block: RwLock::new(BestBlock::default()),
+        }
+    }
+}
+
 impl<DB> BlockChainDatabase<DB>
     where DB: KeyValueDatabase
 {
@@ -87,20 +102,28 @@ impl<DB> BlockChainDatabase<DB>
             .into_option()
     }
 
-    pub fn insert(&self, block: IndexedBlock) -> Result<(), Error> {
+    pub fn insert(&self, block: IndexedBlock, difficulty: u64) -> Result<(), Error> {
         if self.contains_block(block.id().clone().into()) {
             return Ok(());
         }
 
         let parent_id = block.raw.header.prev_id.clone();
-        if !self.contains_block(parent_id.clone().into()) && !parent_id.is_zero() {
-            return Err(Error::UnknownParent);
-        }
+        let cumulative_difficulty = if parent_id.is_zero() {
+            difficulty
+        } else {
+            match self.cumulative_difficulty(&parent_id) {
+                Some(parent_difficulty) => parent_difficulty.saturating_add(difficulty),
+                None => return Err(Error::UnknownParent),
+            }
+        };
 
         let mut update = Transaction::new();
         update.insert(KeyValue::Block(block.id().clone(), block.raw.clone()));
+        update.insert(KeyValue::BlockDifficulty(block.id().clone(), cumulative_difficulty));
 
-        // TODO: transactions?
+        for tx in block.transactions.iter() {
+            update.insert(KeyValue::Transaction(tx.id().clone(), tx.raw.clone()));
+        }
 
         self.db.write(update).map_err(Error::DatabaseError)
     }
@@ -138,13 +161,134 @@ impl<DB> BlockChainDatabase<DB>
         update.insert(KeyValue::Meta(KEY_BEST_BLOCK_ID, to_binary(&new_best_block.id)));
         update.insert(KeyValue::Meta(KEY_BEST_BLOCK_HEIGHT, to_binary(&new_best_block.height)));
 
-        // TODO: transactions
+        for tx in self.block_transactions(&block)?.iter() {
+            for key_image in Self::key_images(tx) {
+                update.insert(KeyValue::KeyImage(key_image.clone(), tx.id().clone()));
+            }
+        }
 
         self.db.write(update).map_err(Error::DatabaseError)?;
         *best_block = new_best_block;
         Ok(())
     }
 
+    pub fn decanonize(&self) -> Result<H256, Error> {
+        let mut best_block = self.best_block.write();
+        let block = match self.indexed_block(best_block.id.clone().into()) {
+            Some(block) => block,
+            None => return Err(Error::CannotDecanonize),
+        };
+
+        // The genesis block can't be decanonized.
+        if block.raw.header.prev_id.is_zero() {
+            return Err(Error::CannotDecanonize);
+        }
+
+        let new_best_block = BestBlock {
+            id: block.raw.header.prev_id.clone(),
+            height: best_block.height - 1,
+        };
+
+        let mut update = Transaction::new();
+        update.delete(Key::BlockId(best_block.height));
+        update.delete(Key::BlockHeight(best_block.id.clone()));
+        update.insert(KeyValue::Meta(KEY_BEST_BLOCK_ID, to_binary(&new_best_block.id)));
+        update.insert(KeyValue::Meta(KEY_BEST_BLOCK_HEIGHT, to_binary(&new_best_block.height)));
+
+        for tx in self.block_transactions(&block)?.iter() {
+            for key_image in Self::key_images(tx) {
+                update.delete(Key::KeyImage(key_image.clone()));
+            }
+        }
+
+        self.db.write(update).map_err(Error::DatabaseError)?;
+        *best_block = new_best_block;
+        Ok(block.id)
+    }
+
+    /// Stores the cumulative difficulty of the canonical blocks that don't
+    /// have one, databases created before it was stored lack them and
+    /// nothing can be inserted on top of such blocks.
+    ///
+    /// `next_difficulty` is given the timestamps and cumulative difficulties
+    /// of the last `window` blocks before a block, oldest first, and the
+    /// major version of the newest of them, and returns the difficulty of
+    /// the block. Returns how many blocks were updated.
+    pub fn backfill_cumulative_difficulties<F>(&self,
+                                               window: usize,
+                                               mut next_difficulty: F)
+                                               -> Result<u64, Error>
+        where F: FnMut(&[u64], &[u64], u8) -> u64
+    {
+        let best_block = self.best_block.read().clone();
+        if best_block.id.is_zero() || self.cumulative_difficulty(&best_block.id).is_some() {
+            return Ok(0);
+        }
+
+        let mut timestamps = Vec::with_capacity(window);
+        let mut cumulative_difficulties = Vec::with_capacity(window);
+        let mut top_version = None;
+        let mut update = Transaction::new();
+        let mut updated = 0;
+        for height in 0..best_block.height + 1 {
+            let block = self.block_id(height)
+                .and_then(|id| self.indexed_block(id.into()))
+                .ok_or_else(|| Error::DatabaseError(format!("no block at height {}", height)))?;
+
+            let cumulative_difficulty = match self.cumulative_difficulty(block.id()) {
+                Some(cumulative_difficulty) => cumulative_difficulty,
+                None => {
+                    let version = top_version.unwrap_or(block.raw.header.major_version);
+                    let difficulty = next_difficulty(&timestamps,
+                                                     &cumulative_difficulties,
+                                                     version);
+                    let cumulative_difficulty = cumulative_difficulties.last()
+                        .map_or(difficulty, |parent: &u64| parent.saturating_add(difficulty));
+
+                    update.insert(KeyValue::BlockDifficulty(block.id().clone(),
+                                                            cumulative_difficulty));
+                    updated += 1;
+                    if updated % BACKFILL_BATCH_SIZE == 0 {
+                        let batch = ::std::mem::replace(&mut update, Transaction::new());
+                        self.db.write(batch).map_err(Error::DatabaseError)?;
+                    }
+
+                    cumulative_difficulty
+                }
+            };
+
+            timestamps.push(block.raw.header.timestamp);
+            cumulative_difficulties.push(cumulative_difficulty);
+            if timestamps.len() > window {
+                timestamps.remove(0);
+                cumulative_difficulties.remove(0);
+            }
+            top_version = Some(block.raw.header.major_version);
+        }
+
+        self.db.write(update).map_err(Error::DatabaseError)?;
+        Ok(updated)
+    }
+
+    fn block_transactions(&self, block: &IndexedBlock) -> Result<Vec<IndexedTransaction>, Error> {
+        block.raw
+            .tx_hashes
+            .iter()
+            .map(|id| self.transaction(id).ok_or(Error::UnknownTransaction))
+            .collect()
+    }
+
+    fn key_images<'a>(tx: &'a IndexedTransaction) -> Box<Iterator<Item = &'a KeyImage> + 'a> {
+        Box::new(tx.raw
+                     .prefix
+                     .vin
+                     .iter()
+                     .filter_map(|txin| match *txin {
+                                     TxIn::ToKey(ref txin) => Some(&txin.k_image),
+                                     _ => None,
+                                 }))
+    }
+
 
     fn resolve_id(&self, block_ref: BlockRef) -> Option<H256> {
         match block_ref {
@@ -157,13 +301,17 @@ impl<DB> BlockChainDatabase<DB>
 impl<DB> BlockChain for BlockChainDatabase<DB>
     where DB: KeyValueDatabase
 {
-    fn insert(&self, block: IndexedBlock) -> Result<(), String> {
-        BlockChainDatabase::insert(self, block).map_err(|e| format!("{}", e))
+    fn insert(&self, block: IndexedBlock, difficulty: u64) -> Result<(), String> {
+        BlockChainDatabase::insert(self, block, difficulty).map_err(|e| format!("{}", e))
     }
 
     fn canonize(&self, id: &H256) -> Result<(), String> {
         BlockChainDatabase::canonize(self, id).map_err(|e| format!("{}", e))
     }
+
+    fn decanonize(&self) -> Result<H256, String> {
+        BlockChainDatabase::decanonize(self).map_err(|e| format!("{}", e))
+    }
 }
 
 impl<DB> Store for BlockChainDatabase<DB>
@@ -228,6 +376,11 @@ impl<DB> BlockProvider for BlockChainDatabase<DB>
         self.get(Key::BlockId(height))
             .and_then(Value::as_block_id)
     }
+
+    fn cumulative_difficulty(&self, id: &H256) -> Option<u64> {
+        self.get(Key::BlockDifficulty(id.clone()))
+            .and_then(Value::as_block_difficulty)
+    }
 }
 
 impl<DB> IndexedBlockProvider for BlockChainDatabase<DB>
@@ -242,3 +395,95 @@ impl<DB> IndexedBlockProvider for BlockChainDatabase<DB>
                       })
     }
 }
+
+impl<DB> TransactionProvider for BlockChainDatabase<DB>
+    where DB: KeyValueDatabase
+{
+    fn transaction(&self, id: &H256) -> Option<IndexedTransaction> {
+        self.get(Key::Transaction(id.clone()))
+            .and_then(Value::as_transaction)
+            .map(|tx| IndexedTransaction::new(tx, id.clone()))
+    }
+}
+
+impl<DB> KeyImageProvider for BlockChainDatabase<DB>
+    where DB: KeyValueDatabase
+{
+    fn contains_key_image(&self, key_image: &KeyImage) -> bool {
+        self.get(Key::KeyImage(key_image.clone()))
+            .and_then(Value::as_key_image)
+            .is_some()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use chain::{Block, BlockHeader};
+    use chain::transaction::{SignatureType, Transaction as ChainTransaction, TransactionPrefix,
+                             TxInGen};
+
+    fn block(height: u64, prev_id: H256) -> IndexedBlock {
+        Block {
+            header: BlockHeader {
+                major_version: 1,
+                minor_version: 0,
+                timestamp: height * 60,
+                prev_id,
+                nonce: 0,
+            },
+            miner_tx: ChainTransaction {
+                prefix: TransactionPrefix {
+                    version: 1,
+                    unlock_time: height + 60,
+                    vin: vec![TxInGen { height }.into()],
+                    vout: vec![],
+                    extra: vec![],
+                },
+                signature_type: SignatureType::Normal(vec![]),
+            },
+            tx_hashes: vec![],
+        }.into()
+    }
+
+    #[test]
+    fn missing_cumulative_difficulties_are_backfilled() {
+        let db = BlockChainDatabase::in_memory();
+        let mut prev_id = H256::default();
+        for height in 0..5 {
+            let block = block(height, prev_id);
+            prev_id = block.id().clone();
+            db.insert(block, 1).unwrap();
+            db.canonize(&prev_id).unwrap();
+        }
+
+        // Databases created before the difficulties were stored.
+        let mut update = Transaction::new();
+        for height in 2..5 {
+            update.delete(Key::BlockDifficulty(db.block_id(height).unwrap()));
+        }
+        db.db.write(update).unwrap();
+        match db.insert(block(5, prev_id.clone()), 1) {
+            Err(Error::UnknownParent) => {}
+            result => panic!("inserted on top of an unknown difficulty: {:?}", result),
+        }
+
+        let mut windows = Vec::new();
+        let updated = db.backfill_cumulative_difficulties(2, |timestamps, difficulties, _| {
+                windows.push((timestamps.to_vec(), difficulties.to_vec()));
+                10
+            })
+            .unwrap();
+
+        assert_eq!(updated, 3);
+        assert_eq!(windows,
+                   vec![(vec![0, 60], vec![1, 2]),
+                        (vec![60, 120], vec![2, 12]),
+                        (vec![120, 180], vec![12, 22])]);
+        assert_eq!(db.cumulative_difficulty(&prev_id), Some(32));
+
+        db.insert(block(5, prev_id), 1).unwrap();
+        assert_eq!(db.backfill_cumulative_difficulties(2, |_, _, _| unreachable!()).unwrap(), 0);
+    }
+}
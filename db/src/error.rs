@@ -29,4 +29,8 @@ pub enum Error {
     UnknownParent,
     #[fail(display = "can't canonize block")]
     CannotCanonize,
+    #[fail(display = "can't decanonize block")]
+    CannotDecanonize,
+    #[fail(display = "unknown transaction")]
+    UnknownTransaction,
 }
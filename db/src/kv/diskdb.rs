@@ -102,7 +102,7 @@ impl KeyValueDatabase for DiskDb {
         let val = txn.get::<_, UnsafeValue>(&db, key_val, None)
             .ok_or("key doesn't exists".to_owned());
         if let Ok(val) = val {
-            Ok(KeyState::Insert(Value::for_key(key, unsafe { val.as_slice() })))
+            Ok(KeyState::Insert(Value::for_key(key, unsafe { val.as_slice() })?))
         } else {
             Ok(KeyState::Delete)
         }
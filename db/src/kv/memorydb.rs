@@ -0,0 +1,106 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use parking_lot::RwLock;
+
+use kv::{KeyValueDatabase, KeyState, Key, Value, Transaction};
+use kv::transaction::{RawOperation, RawKey};
+
+/// A database stored in memory, nothing is persisted.
+#[derive(Debug, Default)]
+pub struct MemoryDb {
+    columns: RwLock<HashMap<usize, HashMap<Bytes, Bytes>>>,
+}
+
+impl MemoryDb {
+    pub fn new() -> MemoryDb {
+        MemoryDb::default()
+    }
+}
+
+impl KeyValueDatabase for MemoryDb {
+    fn write(&self, tx: Transaction) -> Result<(), String> {
+        let mut columns = self.columns.write();
+        for op in tx.operations.iter() {
+            match op.into() {
+                RawOperation::Insert(kv) => {
+                    columns
+                        .entry(kv.location)
+                        .or_default()
+                        .insert(kv.key, kv.value);
+                }
+                RawOperation::Delete(k) => {
+                    if let Some(column) = columns.get_mut(&k.location) {
+                        column.remove(&k.key);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, key: &Key) -> Result<KeyState<Value>, String> {
+        let raw_key: RawKey = key.into();
+        let columns = self.columns.read();
+        let column = match columns.get(&raw_key.location) {
+            Some(column) => column,
+            None => return Ok(KeyState::Unknown),
+        };
+
+        match column.get(&raw_key.key) {
+            Some(value) => Ok(KeyState::Insert(Value::for_key(key, value)?)),
+            None => Ok(KeyState::Delete),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use super::super::*;
+
+    #[test]
+    fn insert_and_delete() {
+        let db = MemoryDb::new();
+        let id = [1u8; 32];
+
+        let mut tx = Transaction::new();
+        tx.insert(KeyValue::BlockHeight(id.into(), 5));
+        db.write(tx).unwrap();
+
+        match db.get(&Key::BlockHeight(id.into())).unwrap() {
+            KeyState::Insert(Value::BlockHeight(5)) => {}
+            _ => panic!("value not inserted"),
+        }
+
+        let mut tx = Transaction::new();
+        tx.delete(Key::BlockHeight(id.into()));
+        db.write(tx).unwrap();
+
+        assert!(db.get(&Key::BlockHeight(id.into())).unwrap().into_option().is_none());
+    }
+
+    #[test]
+    fn malformed_value_is_an_error() {
+        let id = [1u8; 32];
+        assert!(Value::for_key(&Key::BlockDifficulty(id.into()), &[1, 2, 3]).is_err());
+        assert!(Value::for_key(&Key::BlockHeight(id.into()), &[]).is_err());
+    }
+}
@@ -22,5 +22,7 @@ pub use self::transaction::{Transaction, KeyState, Key, Value, KeyValue};
 
 
 mod diskdb;
+mod memorydb;
 
 pub use self::diskdb::DiskDb;
+pub use self::memorydb::MemoryDb;
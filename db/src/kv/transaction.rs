@@ -17,12 +17,17 @@
 use bytes::{BytesMut, Bytes, Buf, BufMut, IntoBuf, LittleEndian};
 use primitives::H256;
 use chain::Block;
+use chain::transaction::Transaction as ChainTransaction;
 use format::{to_binary, from_binary};
+use keys::KeyImage;
 
 pub const COL_META: usize = 0;
 pub const COL_BLOCKS: usize = 1;
 pub const COL_BLOCK_HEIGHTS: usize = 2;
 pub const COL_BLOCK_IDS: usize = 3;
+pub const COL_TRANSACTIONS: usize = 4;
+pub const COL_KEY_IMAGES: usize = 5;
+pub const COL_BLOCK_DIFFICULTIES: usize = 6;
 
 #[derive(Debug)]
 pub enum Operation {
@@ -36,6 +41,9 @@ pub enum KeyValue {
     Block(H256, Block),
     BlockHeight(H256, u64),
     BlockId(u64, H256),
+    Transaction(H256, ChainTransaction),
+    KeyImage(KeyImage, H256),
+    BlockDifficulty(H256, u64),
 }
 
 #[derive(Debug)]
@@ -44,6 +52,9 @@ pub enum Key {
     Block(H256),
     BlockHeight(H256),
     BlockId(u64),
+    Transaction(H256),
+    KeyImage(KeyImage),
+    BlockDifficulty(H256),
 }
 
 #[derive(Debug, Clone)]
@@ -52,23 +63,27 @@ pub enum Value {
     Block(Block),
     BlockHeight(u64),
     BlockId(H256),
+    Transaction(ChainTransaction),
+    KeyImage(H256),
+    BlockDifficulty(u64),
 }
 
 impl Value {
-    pub fn for_key(key: &Key, bytes: &[u8]) -> Value {
-        match *key {
+    /// Decodes the value of `key` from its stored bytes.
+    pub fn for_key(key: &Key, bytes: &[u8]) -> Result<Value, String> {
+        let value = match *key {
             Key::Meta(_) => Value::Meta(bytes.into()),
-            Key::Block(_) => Value::Block(from_binary(bytes).unwrap()),
-            Key::BlockHeight(_) => {
-                if bytes.len() != 8 {
-                    unimplemented!();
-                }
-
-                let mut buf = bytes.into_buf();
-                Value::BlockHeight(buf.get_u64::<LittleEndian>())
-            }
+            Key::Block(_) => Value::Block(from_binary(bytes).map_err(|e| e.to_string())?),
+            Key::BlockHeight(_) => Value::BlockHeight(read_u64(bytes)?),
             Key::BlockId(_) => Value::BlockId(H256::from_bytes(&bytes)),
-        }
+            Key::Transaction(_) => {
+                Value::Transaction(from_binary(bytes).map_err(|e| e.to_string())?)
+            }
+            Key::KeyImage(_) => Value::KeyImage(H256::from_bytes(&bytes)),
+            Key::BlockDifficulty(_) => Value::BlockDifficulty(read_u64(bytes)?),
+        };
+
+        Ok(value)
     }
 
     pub fn as_meta(self) -> Option<Bytes> {
@@ -98,6 +113,36 @@ impl Value {
             _ => None,
         }
     }
+
+    pub fn as_transaction(self) -> Option<ChainTransaction> {
+        match self {
+            Value::Transaction(tx) => Some(tx),
+            _ => None,
+        }
+    }
+
+    pub fn as_key_image(self) -> Option<H256> {
+        match self {
+            Value::KeyImage(tx_id) => Some(tx_id),
+            _ => None,
+        }
+    }
+
+    pub fn as_block_difficulty(self) -> Option<u64> {
+        match self {
+            Value::BlockDifficulty(difficulty) => Some(difficulty),
+            _ => None,
+        }
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> Result<u64, String> {
+    if bytes.len() != 8 {
+        return Err(format!("expected 8 bytes, found {}", bytes.len()));
+    }
+
+    let mut buf = bytes.into_buf();
+    Ok(buf.get_u64::<LittleEndian>())
 }
 
 #[derive(Debug, Clone)]
@@ -177,6 +222,17 @@ impl<'a> From<&'a KeyValue> for RawKeyValue {
                 buf.put_u64::<LittleEndian>(*k);
                 (COL_BLOCK_IDS, buf.freeze(), Bytes::from(v.as_bytes()))
             }
+            KeyValue::Transaction(ref k, ref v) => {
+                (COL_TRANSACTIONS, Bytes::from(k.as_bytes()), to_binary(v))
+            }
+            KeyValue::KeyImage(ref k, ref v) => {
+                (COL_KEY_IMAGES, Bytes::from(k.as_bytes()), Bytes::from(v.as_bytes()))
+            }
+            KeyValue::BlockDifficulty(ref k, ref v) => {
+                let mut buf = BytesMut::with_capacity(8);
+                buf.put_u64::<LittleEndian>(*v);
+                (COL_BLOCK_DIFFICULTIES, Bytes::from(k.as_bytes()), buf.freeze())
+            }
         };
 
         RawKeyValue {
@@ -204,6 +260,9 @@ impl<'a> From<&'a Key> for RawKey {
                 buf.put_u64::<LittleEndian>(*k);
                 (COL_BLOCK_IDS, buf.freeze())
             }
+            Key::Transaction(ref k) => (COL_TRANSACTIONS, Bytes::from(k.as_bytes())),
+            Key::KeyImage(ref k) => (COL_KEY_IMAGES, Bytes::from(k.as_bytes())),
+            Key::BlockDifficulty(ref k) => (COL_BLOCK_DIFFICULTIES, Bytes::from(k.as_bytes())),
         };
 
         RawKey { location, key }
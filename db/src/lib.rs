@@ -25,6 +25,7 @@ extern crate failure_derive;
 
 extern crate xmr_chain as chain;
 extern crate xmr_format as format;
+extern crate xmr_keys as keys;
 extern crate xmr_primitives as primitives;
 extern crate xmr_storage as storage;
 
@@ -14,23 +14,35 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::ArgMatches;
 use failure::Error;
-use network::Network;
+use levin::net::{RateLimits, Socks5Auth, Socks5Proxy};
+use network::{Checkpoints, Network};
+use p2p::types::NetworkAddress;
 use peers::default_peers;
 use storage::SharedStore;
 use utils;
 
 pub struct Config {
     pub network: Network,
-    pub peers: Vec<SocketAddr>,
+    pub peers: Vec<NetworkAddress>,
     pub threads: usize,
+    pub bind_addrs: Vec<IpAddr>,
     pub listen_port: Option<u16>,
     pub hide_my_port: bool,
     pub out_peers: u32,
     pub in_peers: u32,
+    pub ban_time: Duration,
+    pub checkpoints: Checkpoints,
+    pub capture: Option<PathBuf>,
+    pub tls: bool,
+    pub proxy: Option<Socks5Proxy>,
+    pub rate_limits: RateLimits,
+    pub peer_rate_limits: RateLimits,
     pub db: SharedStore,
 }
 
@@ -40,17 +52,33 @@ pub fn parse(matches: &ArgMatches) -> Result<Config, Error> {
         false => Network::Mainnet,
     };
 
-    let peers = match value_t!(matches.value_of("connect"), SocketAddr) {
-        Ok(addr) => {
-            let mut peers = Vec::with_capacity(1);
-            peers.push(addr);
-            peers
+    // onion and I2P peers are given as `host:port`.
+    let peers = match matches.value_of("connect") {
+        Some(addr) => {
+            let addr = addr.parse::<NetworkAddress>()
+                .map_err(|e| format_err!("invalid --connect {}: {}", addr, e))?;
+            vec![addr]
         }
-        Err(_e) => default_peers(network),
+        None => default_peers(network).into_iter().map(Into::into).collect(),
     };
 
     let threads = value_t!(matches.value_of("threads"), usize).unwrap_or(1);
 
+    let mut bind_addrs = Vec::with_capacity(2);
+    let bind_ip = match matches.value_of("p2p_bind_ip") {
+        Some(ip) => {
+            ip.parse::<Ipv4Addr>()
+                .map_err(|e| format_err!("invalid --p2p-bind-ip {}: {}", ip, e))?
+        }
+        None => Ipv4Addr::new(0, 0, 0, 0),
+    };
+    bind_addrs.push(IpAddr::V4(bind_ip));
+    if let Some(ip) = matches.value_of("p2p_bind_ipv6") {
+        let ip = ip.parse::<Ipv6Addr>()
+            .map_err(|e| format_err!("invalid --p2p-bind-ipv6 {}: {}", ip, e))?;
+        bind_addrs.push(IpAddr::V6(ip));
+    }
+
     let listen_port = value_t!(matches.value_of("listenport"), u16).ok();
 
     let hide_my_port = matches.is_present("hidemyport");
@@ -58,16 +86,78 @@ pub fn parse(matches: &ArgMatches) -> Result<Config, Error> {
     let out_peers = value_t!(matches.value_of("outpeers"), u32).unwrap_or(10);
     let in_peers = value_t!(matches.value_of("inpeers"), u32).unwrap_or(10);
 
+    let ban_time = value_t!(matches.value_of("bantime"), u64).unwrap_or(86400);
+    let ban_time = Duration::from_secs(ban_time);
+
+    let mut checkpoints = network.checkpoints();
+    if let Some(path) = matches.value_of("checkpoints") {
+        checkpoints
+            .load(path)
+            .map_err(|e| format_err!("couldn't load checkpoints: {}", e))?;
+    }
+
+    let capture = matches.value_of("capture").map(PathBuf::from);
+
+    let tls = matches.is_present("p2p_tls");
+
+    let proxy = match matches.value_of("proxy") {
+        Some(addr) => {
+            let addr = addr.parse::<SocketAddr>()
+                .map_err(|e| format_err!("invalid --proxy {}: {}", addr, e))?;
+            let auth = match matches.value_of("proxy_auth") {
+                Some(auth) => {
+                    Some(auth.parse::<Socks5Auth>()
+                             .map_err(|e| format_err!("invalid --proxy-auth: {}", e))?)
+                }
+                None => None,
+            };
+            Some(Socks5Proxy { addr, auth })
+        }
+        None => None,
+    };
+
+    let rate_limits = RateLimits {
+        up: rate_limit(matches, "limit_rate_up")?,
+        down: rate_limit(matches, "limit_rate_down")?,
+    };
+    let peer_rate_limits = RateLimits {
+        up: rate_limit(matches, "limit_rate_up_peer")?,
+        down: rate_limit(matches, "limit_rate_down_peer")?,
+    };
+
     let db = utils::open_db();
 
     Ok(Config {
            network,
            peers,
            threads,
+           bind_addrs,
            listen_port,
            hide_my_port,
            out_peers,
            in_peers,
+           ban_time,
+           checkpoints,
+           capture,
+           tls,
+           proxy,
+           rate_limits,
+           peer_rate_limits,
            db,
        })
 }
+
+/// Parses a rate limit in kB/s, like monerod, zero is unlimited.
+fn rate_limit(matches: &ArgMatches, name: &str) -> Result<Option<u64>, Error> {
+    match matches.value_of(name) {
+        Some(value) => {
+            let limit = value
+                .parse::<u64>()
+                .map_err(|e| format_err!("invalid rate limit {}: {}", value, e))?
+                .checked_mul(1024)
+                .ok_or_else(|| format_err!("rate limit {} is too big", value))?;
+            Ok(Some(limit).filter(|&limit| limit > 0))
+        }
+        None => Ok(None),
+    }
+}
@@ -19,24 +19,36 @@ extern crate app_dirs;
 extern crate clap;
 
 extern crate env_logger;
+#[macro_use]
 extern crate log;
 
+#[macro_use]
 extern crate failure;
 
+extern crate futures;
+extern crate tokio_core;
+
 extern crate rand;
 
 extern crate xmr_chain as chain;
 extern crate xmr_db as db;
+extern crate xmr_levin as levin;
 extern crate xmr_network as network;
 extern crate xmr_p2p as p2p;
 extern crate xmr_storage as storage;
 extern crate xmr_sync as sync;
+extern crate xmr_verification as verification;
 
 mod config;
 mod peers;
 mod utils;
 
+use std::process;
+use std::time::Duration;
+
 use failure::Error;
+use futures::{Future, Stream};
+use tokio_core::reactor::Interval;
 use app_dirs::AppInfo;
 
 pub const APP_INFO: AppInfo = AppInfo {
@@ -55,12 +67,35 @@ fn main() {
         (@arg testnet: --testnet "Use the test network")
         (@arg connect: --connect +takes_value "Connect only to the given peer")
         (@arg listenport: --listenport +takes_value )
+        (@arg p2p_bind_ip: --("p2p-bind-ip") +takes_value "IPv4 address to listen on")
+        (@arg p2p_bind_ipv6: --("p2p-bind-ipv6") +takes_value "IPv6 address to listen on")
         (@arg hidemyport: --hidemyport)
         (@arg outpeers: --outpeers +takes_value "Maximum of outbound peers")
         (@arg inpeers: --inpeers +takes_value "Maximum of outbound peers")
+        (@arg bantime: --("ban-time") +takes_value "Seconds a misbehaving peer stays banned")
+        (@arg checkpoints: --checkpoints +takes_value "File with additional `height:id` checkpoints")
+        (@arg capture: --capture +takes_value "Record the p2p traffic to a file")
+        (@arg p2p_tls: --("p2p-tls") "Encrypt the p2p connections with TLS when possible")
+        (@arg proxy: --proxy +takes_value "SOCKS5 proxy for the outbound connections")
+        (@arg proxy_auth: --("proxy-auth") +takes_value "`username:password` of the proxy")
+        (@arg limit_rate_up: --("limit-rate-up") +takes_value "Upload limit in kB/s")
+        (@arg limit_rate_down: --("limit-rate-down") +takes_value "Download limit in kB/s")
+        (@arg limit_rate_up_peer: --("limit-rate-up-peer") +takes_value
+            "Upload limit of each peer in kB/s")
+        (@arg limit_rate_down_peer: --("limit-rate-down-peer") +takes_value
+            "Download limit of each peer in kB/s")
+        (@arg decode_capture: --("decode-capture") +takes_value "Print a capture and exit")
     )
             .get_matches();
 
+    if let Some(path) = matches.value_of("decode_capture") {
+        if let Err(e) = utils::print_capture(path) {
+            println!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
     // TODO: no unwrap
     let cfg = config::parse(&matches).unwrap();
 
@@ -75,7 +110,7 @@ fn start(cfg: config::Config) -> Result<(), Error> {
 
     let mut el = p2p::event_loop();
 
-    let local_node = sync::create_local_node(cfg.db.clone(), cfg.network);
+    let local_node = sync::create_local_node(cfg.db.clone(), cfg.network, cfg.checkpoints.clone());
     let local_sync_node = sync::create_local_sync_node(local_node.clone());
 
     let mut rng = rand::OsRng::new().expect("couldn't open OS random");
@@ -84,17 +119,47 @@ fn start(cfg: config::Config) -> Result<(), Error> {
         threads: cfg.threads,
         network: cfg.network,
         peers: cfg.peers,
+        bind_addrs: cfg.bind_addrs,
         listen_port: cfg.listen_port,
         hide_my_port: cfg.hide_my_port,
         out_peers: cfg.out_peers,
         in_peers: cfg.in_peers,
         peer_id: p2p::types::PeerId::random(&mut rng),
+        ban_time: cfg.ban_time,
+        ban_list: Some(utils::ban_list_path()),
+        peer_store: Some(utils::peer_store_path()),
+        capture: cfg.capture,
+        tls: cfg.tls,
+        proxy: cfg.proxy,
+        rate_limits: cfg.rate_limits,
+        peer_rate_limits: cfg.peer_rate_limits,
     };
 
     let p2p = p2p::P2P::new(config, el.handle(), cfg.db.clone(), local_sync_node);
 
     p2p.run().expect("couldn't start p2p");
 
+    let traffic_stats = p2p.traffic_stats();
+    let status = Interval::new(Duration::from_secs(30), &el.handle())?
+        .for_each({
+                      let local_node = local_node.clone();
+                      move |_| {
+                          info!("sync status: {}", local_node.status());
+                          utils::log_traffic(&traffic_stats);
+                          Ok(())
+                      }
+                  })
+        .map_err(|_| ());
+    el.handle().spawn(status);
+
+    let timer = Interval::new(Duration::from_secs(1), &el.handle())?
+        .for_each(move |_| {
+                      local_node.on_timer();
+                      Ok(())
+                  })
+        .map_err(|_| ());
+    el.handle().spawn(timer);
+
     el.run(p2p::forever()).expect("couldn't run event loop");
 
     Ok(())
@@ -14,13 +14,18 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use app_dirs::{AppDataType, app_dir};
 
 use chain::IndexedBlock;
 use db::BlockChainDatabase;
+use failure::Error;
+use levin::net::{CaptureReader, Flow, TrafficStats};
+use p2p::types::{command_name, decode};
 use storage::SharedStore;
+use verification::{DIFFICULTY_BLOCKS_COUNT, difficulty_target, next_difficulty};
 
 use config::Config;
 
@@ -32,9 +37,71 @@ pub fn open_db() -> SharedStore {
 
     let db = BlockChainDatabase::open(path).expect("couldn't open blockchain database");
 
+    let difficulty = |timestamps: &[u64], cumulative_difficulties: &[u64], version: u8| {
+        next_difficulty(timestamps, cumulative_difficulties, difficulty_target(version)).0
+    };
+    let backfilled = db.backfill_cumulative_difficulties(DIFFICULTY_BLOCKS_COUNT, difficulty)
+        .expect("couldn't compute the cumulative difficulties");
+    if backfilled != 0 {
+        info!("stored the cumulative difficulty of {} blocks", backfilled);
+    }
+
     Arc::new(db)
 }
 
+pub fn ban_list_path() -> PathBuf {
+    use APP_INFO;
+
+    app_dir(AppDataType::UserData, &APP_INFO, "p2p")
+        .expect("couldn't get user data location")
+        .join("ban_list")
+}
+
+pub fn peer_store_path() -> PathBuf {
+    use APP_INFO;
+
+    app_dir(AppDataType::UserData, &APP_INFO, "p2p")
+        .expect("couldn't get user data location")
+        .join("peers")
+}
+
+pub fn print_capture(path: &str) -> Result<(), Error> {
+    let reader = CaptureReader::open(path)
+        .map_err(|e| format_err!("couldn't open capture {}: {}", path, e))?;
+
+    for record in reader {
+        let record = record.map_err(|e| format_err!("invalid capture {}: {}", path, e))?;
+        let flow = match record.flow {
+            Flow::Received => "<-",
+            Flow::Sent => "->",
+        };
+
+        println!("[{}] {} {} {}",
+                 record.timestamp,
+                 flow,
+                 record.addr,
+                 decode(&record.bucket));
+    }
+
+    Ok(())
+}
+
+pub fn log_traffic(traffic_stats: &TrafficStats) {
+    let total = traffic_stats.total();
+    info!("p2p traffic: received {} kB in {} messages, sent {} kB in {} messages",
+          total.bytes_received / 1024,
+          total.messages_received,
+          total.bytes_sent / 1024,
+          total.messages_sent);
+
+    for (id, traffic) in traffic_stats.commands() {
+        debug!("p2p traffic of {}: received {} bytes, sent {} bytes",
+               command_name(id).unwrap_or("unknown command"),
+               traffic.bytes_received,
+               traffic.bytes_sent);
+    }
+}
+
 pub fn init_db(cfg: &Config) {
     let genesis_block: IndexedBlock = cfg.network.genesis_block().into();
 
@@ -47,7 +114,7 @@ pub fn init_db(cfg: &Config) {
         None => {
             let id = genesis_block.id().clone();
             cfg.db
-                .insert(genesis_block)
+                .insert(genesis_block, 1)
                 .expect("couldn't insert genesis block");
 
             cfg.db
@@ -15,7 +15,7 @@ use utils::fmt_byte_slice;
 /// Key image length.
 pub const KEY_IMAGE_LENGTH: usize = 32;
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, PartialEq, Eq, Hash)]
 pub struct KeyImage(pub [u8; KEY_IMAGE_LENGTH]);
 
 impl KeyImage {
@@ -6,7 +6,9 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::fmt::Debug;
 use std::io;
+use std::sync::Arc;
 
 use futures::{Future, Poll};
 use tokio_io::AsyncRead;
@@ -17,10 +19,11 @@ use bytes::{Bytes, BytesMut, IntoBuf};
 use portable_storage::{self, Section};
 
 use bucket::bucket_head::{BucketHead, LEVIN_SIGNATURE, LEVIN_PROTOCOL_VER_1, LEVIN_OK,
-                          LEVIN_PACKET_REQUEST, LEVIN_PACKET_RESPONSE, BUCKET_HEAD_LENGTH};
+                          LEVIN_PACKET_REQUEST, LEVIN_PACKET_RESPONSE, LEVIN_PACKET_BEGIN,
+                          LEVIN_PACKET_END, LEVIN_NOISE_COMMAND, BUCKET_HEAD_LENGTH};
 
 use command::Id;
-use error::Result;
+use error::{BucketHeadError, Result};
 
 /// A levin bucket, this is the packet of information
 /// that carries commands in the levin protocol.
@@ -115,12 +118,83 @@ impl Bucket {
         }
     }
 
+    /// Create a noise bucket with `size` bytes of padding, the receiver
+    /// discards it.
+    pub fn noise(size: usize) -> Bucket {
+        Bucket::fragment_bucket(LEVIN_PACKET_BEGIN | LEVIN_PACKET_END, vec![0u8; size].into())
+    }
+
+    /// Splits this bucket into fragments with a body of `size` bytes each,
+    /// the last one is padded with zeros.
+    ///
+    /// A bucket that fits in one fragment is returned as is.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `size` is zero.
+    pub fn fragment(self, size: usize) -> Vec<Bucket> {
+        assert!(size > 0, "the fragment size must be greater than zero");
+
+        if self.body.len() + BUCKET_HEAD_LENGTH <= size {
+            return vec![self];
+        }
+
+        let message = self.to_bytes();
+        let last = (message.len() - 1) / size;
+        message
+            .chunks(size)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut flags = 0;
+                if i == 0 {
+                    flags |= LEVIN_PACKET_BEGIN;
+                }
+                if i == last {
+                    flags |= LEVIN_PACKET_END;
+                }
+
+                let mut body = BytesMut::with_capacity(size);
+                body.extend_from_slice(chunk);
+                body.extend_from_slice(&vec![0u8; size - chunk.len()]);
+                Bucket::fragment_bucket(flags, body)
+            })
+            .collect()
+    }
+
+    fn fragment_bucket(flags: u32, body: BytesMut) -> Bucket {
+        Bucket {
+            head: BucketHead {
+                signature: LEVIN_SIGNATURE,
+                cb: body.len() as u64,
+                have_to_return_data: false,
+                command: LEVIN_NOISE_COMMAND,
+                return_code: LEVIN_OK,
+                protocol_version: LEVIN_PROTOCOL_VER_1,
+                flags,
+            },
+            body,
+        }
+    }
+
     /// Creates a future that will read a bucket from the provided stream.
     pub fn receive_future<A>(a: A) -> Receive<A>
         where A: AsyncRead
     {
         let buf = vec![0u8; BUCKET_HEAD_LENGTH];
-        Receive { state: ReceiveState::ReadBucket { reader: read_exact(a, buf) } }
+        Receive {
+            state: ReceiveState::ReadBucket { reader: read_exact(a, buf) },
+            limit: None,
+        }
+    }
+
+    /// Creates a future that will read a bucket from the provided stream,
+    /// a bucket bigger than `limit` allows fails without reading its body.
+    pub fn receive_future_with_limit<A>(a: A, limit: SizeLimitRef) -> Receive<A>
+        where A: AsyncRead
+    {
+        let mut receive = Bucket::receive_future(a);
+        receive.limit = Some(limit);
+        receive
     }
 
     /// Convert the body of this bucket into a portable storage section.
@@ -145,10 +219,20 @@ impl Bucket {
     }
 }
 
+/// Decides the maximum body size of the received buckets.
+pub trait SizeLimit: Debug + Send + Sync {
+    /// The maximum size in bytes of the body of a bucket with this head.
+    fn max_size(&self, head: &BucketHead) -> u64;
+}
+
+/// A reference to a `SizeLimit`.
+pub type SizeLimitRef = Arc<SizeLimit>;
+
 /// A future that will receive a bucket.
 #[derive(Debug)]
 pub struct Receive<A: AsyncRead> {
     state: ReceiveState<A>,
+    limit: Option<SizeLimitRef>,
 }
 
 #[derive(Debug)]
@@ -183,6 +267,13 @@ impl<A> Future for Receive<A>
 
                     trace!("receive poll - bucket received: {:?}", bucket_head);
 
+                    if let Some(ref limit) = self.limit {
+                        if bucket_head.cb > limit.max_size(&bucket_head) {
+                            let e = BucketHeadError::TooBig(bucket_head.cb);
+                            return Ok((stream, Err(e.into())).into());
+                        }
+                    }
+
                     let buf = vec![0u8; bucket_head.cb as usize];
                     ReceiveState::ReadStorage {
                         bucket_head,
@@ -17,10 +17,20 @@ pub const LEVIN_SIGNATURE: u64 = 0x0101010101012101;
 pub const LEVIN_PACKET_REQUEST: u32 = 0x00000001;
 /// Identifies a command as a response.
 pub const LEVIN_PACKET_RESPONSE: u32 = 0x00000002;
+/// Marks the first fragment of a message.
+pub const LEVIN_PACKET_BEGIN: u32 = 0x00000004;
+/// Marks the last fragment of a message.
+pub const LEVIN_PACKET_END: u32 = 0x00000008;
+
+/// The command ID used by fragments and noise buckets.
+pub const LEVIN_NOISE_COMMAND: u32 = 0;
 
 /// Levin maximum packet size. It's default is 100 *MB*.
 pub const LEVIN_DEFAULT_MAX_PACKET_SIZE: u64 = 100000000;
 
+/// Maximum packet size before the handshake is done, 256 *KB*.
+pub const LEVIN_INITIAL_MAX_PACKET_SIZE: u64 = 256 * 1024;
+
 /// Current levin protocol version.
 pub const LEVIN_PROTOCOL_VER_1: u32 = 1;
 
@@ -90,10 +100,6 @@ impl BucketHead {
             return Err(BucketHeadError::TooBig(bucket_head.cb).into());
         }
 
-        if bucket_head.return_code < 0 {
-            return Err(BucketHeadError::ReturnCode(bucket_head.return_code).into());
-        }
-
         Ok(bucket_head)
     }
 
@@ -120,4 +126,31 @@ impl BucketHead {
     pub fn is_request(&self) -> bool {
         self.flags & LEVIN_PACKET_REQUEST == LEVIN_PACKET_REQUEST
     }
+
+    /// Checks if this bucket is a response, returns `true` if it is.
+    pub fn is_response(&self) -> bool {
+        self.flags & LEVIN_PACKET_RESPONSE == LEVIN_PACKET_RESPONSE
+    }
+
+    /// Checks if this bucket carries a piece of a fragmented message or
+    /// noise instead of a command.
+    pub fn is_fragment(&self) -> bool {
+        !self.is_request() && !self.is_response()
+    }
+
+    /// Checks if this bucket starts a fragmented message.
+    pub fn is_fragment_start(&self) -> bool {
+        self.is_fragment() && self.flags & LEVIN_PACKET_BEGIN == LEVIN_PACKET_BEGIN
+    }
+
+    /// Checks if this bucket ends a fragmented message.
+    pub fn is_fragment_end(&self) -> bool {
+        self.is_fragment() && self.flags & LEVIN_PACKET_END == LEVIN_PACKET_END
+    }
+
+    /// Checks if this bucket is noise, a message that starts and ends in
+    /// the same fragment is discarded.
+    pub fn is_noise(&self) -> bool {
+        self.is_fragment_start() && self.is_fragment_end()
+    }
 }
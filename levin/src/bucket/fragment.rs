@@ -0,0 +1,243 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use bytes::{BytesMut, IntoBuf};
+
+use bucket::bucket::Bucket;
+use bucket::bucket_head::{BucketHead, BUCKET_HEAD_LENGTH};
+use error::{FragmentError, Result};
+
+/// Reassembles fragmented messages.
+#[derive(Debug)]
+pub struct Defragmenter {
+    buffer: Option<BytesMut>,
+    max_size: u64,
+}
+
+impl Defragmenter {
+    /// Creates a `Defragmenter` that rejects messages bigger than
+    /// `max_size` bytes.
+    pub fn new(max_size: u64) -> Defragmenter {
+        Defragmenter {
+            buffer: None,
+            max_size,
+        }
+    }
+
+    /// Changes the maximum size of the reassembled messages.
+    pub fn set_max_size(&mut self, max_size: u64) {
+        self.max_size = max_size;
+    }
+
+    /// Adds a received bucket. Returns the bucket if it isn't a fragment,
+    /// the reassembled message on the last fragment and `None` otherwise.
+    ///
+    /// The partial message is discarded on errors.
+    pub fn push(&mut self, bucket: Bucket) -> Result<Option<Bucket>> {
+        if !bucket.head.is_fragment() {
+            if self.buffer.take().is_some() {
+                return Err(FragmentError::Interrupted.into());
+            }
+
+            return Ok(Some(bucket));
+        }
+
+        if bucket.head.is_fragment_start() {
+            if self.buffer.take().is_some() {
+                return Err(FragmentError::Interrupted.into());
+            }
+
+            if bucket.head.is_noise() {
+                trace!("discarding noise bucket of {} bytes", bucket.body.len());
+                return Ok(None);
+            }
+
+            self.buffer = Some(BytesMut::new());
+        }
+
+        let mut buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => return Err(FragmentError::Unexpected.into()),
+        };
+
+        let size = (buffer.len() + bucket.body.len()) as u64;
+        if size > self.max_size {
+            return Err(FragmentError::TooBig(size).into());
+        }
+
+        buffer.extend_from_slice(&bucket.body);
+        if !bucket.head.is_fragment_end() {
+            self.buffer = Some(buffer);
+            return Ok(None);
+        }
+
+        Defragmenter::reassemble(buffer).map(Some)
+    }
+
+    /// Reads the message of the fragments, the padding after it is
+    /// ignored.
+    fn reassemble(mut buffer: BytesMut) -> Result<Bucket> {
+        if buffer.len() < BUCKET_HEAD_LENGTH {
+            return Err(FragmentError::Truncated.into());
+        }
+
+        let mut body = buffer.split_off(BUCKET_HEAD_LENGTH);
+        let head = BucketHead::read(&mut buffer.freeze().into_buf())?;
+        if head.is_fragment() {
+            return Err(FragmentError::Nested.into());
+        }
+
+        if (body.len() as u64) < head.cb {
+            return Err(FragmentError::Truncated.into());
+        }
+
+        body.truncate(head.cb as usize);
+        Ok(Bucket { head, body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use futures::{Future, Stream};
+
+    use portable_storage::Section;
+
+    use bucket::{LEVIN_DEFAULT_MAX_PACKET_SIZE, LEVIN_INITIAL_MAX_PACKET_SIZE};
+    use error::Error;
+    use net::bucket_stream;
+    use super::*;
+
+    /// The portable-storage encoding of an empty section.
+    const EMPTY_SECTION: [u8; 10] = [0x01, 0x11, 0x01, 0x01, 0x01, 0x01, 0x02, 0x01, 0x01, 0x00];
+
+    /// A notification with the command 1001 and an empty section.
+    const NOTIFICATION_HEAD: [u8; 33] = [
+        0x01, 0x21, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, // signature
+        0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // cb = 10
+        0x00, // have_to_return_data
+        0xe9, 0x03, 0x00, 0x00, // command = 1001
+        0x00, 0x00, 0x00, 0x00, // return_code
+        0x01, 0x00, 0x00, 0x00, // flags = LEVIN_PACKET_REQUEST
+        0x01, 0x00, 0x00, 0x00, // protocol_version
+    ];
+
+    /// Head of a fragment with a body of 32 bytes.
+    fn fragment_head(flags: u8) -> Vec<u8> {
+        vec![0x01, 0x21, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, // signature
+             0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // cb = 32
+             0x00, // have_to_return_data
+             0x00, 0x00, 0x00, 0x00, // command = 0
+             0x00, 0x00, 0x00, 0x00, // return_code
+             flags, 0x00, 0x00, 0x00, // flags
+             0x01, 0x00, 0x00, 0x00 /* protocol_version */]
+    }
+
+    /// The notification split in two fragments of 32 bytes.
+    fn fragmented_notification() -> Vec<u8> {
+        let mut bytes = fragment_head(0x04);
+        bytes.extend_from_slice(&NOTIFICATION_HEAD[..32]);
+        bytes.extend(fragment_head(0x08));
+        bytes.extend_from_slice(&NOTIFICATION_HEAD[32..]);
+        bytes.extend_from_slice(&EMPTY_SECTION);
+        bytes.extend_from_slice(&[0u8; 21]);
+        bytes
+    }
+
+    fn receive(bytes: Vec<u8>, count: u64) -> Vec<Result<Bucket>> {
+        bucket_stream(Cursor::new(bytes))
+            .take(count)
+            .collect()
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn fragments_are_reassembled() {
+        let buckets = receive(fragmented_notification(), 1);
+        let bucket = buckets[0].as_ref().unwrap();
+
+        assert_eq!(bucket.head.command, 1001);
+        assert!(bucket.head.is_request());
+        assert_eq!(&bucket.body[..], &EMPTY_SECTION[..]);
+    }
+
+    #[test]
+    fn notification_is_fragmented_and_padded() {
+        let fragments = Bucket::notification(1001, Section::new()).fragment(32);
+        let bytes = fragments
+            .into_iter()
+            .flat_map(|fragment| fragment.to_bytes())
+            .collect::<Vec<u8>>();
+
+        assert_eq!(bytes, fragmented_notification());
+    }
+
+    #[test]
+    fn noise_is_discarded() {
+        let mut bytes = fragment_head(0x0c);
+        bytes.extend_from_slice(&[0u8; 32]);
+        assert_eq!(Bucket::noise(32).to_bytes(), bytes);
+
+        bytes.extend_from_slice(&NOTIFICATION_HEAD);
+        bytes.extend_from_slice(&EMPTY_SECTION);
+
+        let buckets = receive(bytes, 1);
+        assert_eq!(buckets[0].as_ref().unwrap().head.command, 1001);
+    }
+
+    #[test]
+    fn big_messages_wait_for_the_handshake() {
+        let mut bucket = Bucket::notification(1001, Section::new());
+        bucket.body = vec![0u8; LEVIN_INITIAL_MAX_PACKET_SIZE as usize].into();
+        bucket.head.cb = bucket.body.len() as u64;
+        let bytes = bucket
+            .fragment(64 * 1024)
+            .into_iter()
+            .flat_map(|fragment| fragment.to_bytes())
+            .collect::<Vec<u8>>();
+
+        match receive(bytes.clone(), 1).remove(0) {
+            Err(Error::Fragment(FragmentError::TooBig(_))) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let mut stream = bucket_stream(Cursor::new(bytes));
+        stream.set_max_packet_size(LEVIN_DEFAULT_MAX_PACKET_SIZE);
+        let (bucket, _) = stream.into_future().wait().map_err(|(e, _)| e).unwrap();
+        assert_eq!(bucket.unwrap().unwrap().head.cb, LEVIN_INITIAL_MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    fn invalid_fragments_are_rejected() {
+        let fragments = || Bucket::notification(1001, Section::new()).fragment(32);
+
+        let mut defragmenter = Defragmenter::new(40);
+        let mut iter = fragments().into_iter();
+        assert!(defragmenter.push(iter.next().unwrap()).unwrap().is_none());
+        match defragmenter.push(iter.next().unwrap()) {
+            Err(Error::Fragment(FragmentError::TooBig(64))) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let mut defragmenter = Defragmenter::new(1024);
+        let last = fragments().pop().unwrap();
+        match defragmenter.push(last) {
+            Err(Error::Fragment(FragmentError::Unexpected)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let first = fragments().remove(0);
+        defragmenter.push(first).unwrap();
+        match defragmenter.push(Bucket::notification(1001, Section::new())) {
+            Err(Error::Fragment(FragmentError::Interrupted)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
@@ -13,8 +13,12 @@
 
 mod bucket;
 mod bucket_head;
+mod fragment;
 
-pub use self::bucket::{Bucket, Receive};
+pub use self::bucket::{Bucket, Receive, SizeLimit, SizeLimitRef};
 pub use self::bucket_head::{BucketHead, LEVIN_SIGNATURE, LEVIN_PACKET_REQUEST,
-                            LEVIN_PACKET_RESPONSE, LEVIN_PROTOCOL_VER_1, BUCKET_HEAD_LENGTH,
-                            LEVIN_OK, LEVIN_DEFAULT_MAX_PACKET_SIZE};
+                            LEVIN_PACKET_RESPONSE, LEVIN_PACKET_BEGIN, LEVIN_PACKET_END,
+                            LEVIN_NOISE_COMMAND, LEVIN_PROTOCOL_VER_1, BUCKET_HEAD_LENGTH,
+                            LEVIN_OK, LEVIN_DEFAULT_MAX_PACKET_SIZE,
+                            LEVIN_INITIAL_MAX_PACKET_SIZE};
+pub use self::fragment::Defragmenter;
@@ -23,4 +23,7 @@ pub trait Command {
     ///
     /// [1]: const.COMMAND_BASE_ID.html
     const ID: Id;
+
+    /// The response of an invokation, notifications use `()`.
+    type Response;
 }
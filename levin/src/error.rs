@@ -22,6 +22,11 @@ pub enum Error {
     /// The command is invalid.
     #[fail(display = "the bucket command id ({}) is invalid", _0)]
     InvalidCommandId(u32),
+
+    /// A fragmented message couldn't be reassembled.
+    #[fail(display = "couldn't reassemble fragmented message: {}", _0)]
+    Fragment(#[cause]
+             FragmentError),
 }
 
 impl From<BucketHeadError> for Error {
@@ -30,6 +35,32 @@ impl From<BucketHeadError> for Error {
     }
 }
 
+impl From<FragmentError> for Error {
+    fn from(e: FragmentError) -> Error {
+        Error::Fragment(e)
+    }
+}
+
+/// An error returned when an invokation fails.
+#[derive(Debug, Fail)]
+pub enum InvokeError {
+    /// The peer didn't answer before the deadline.
+    #[fail(display = "the invokation timed out")]
+    Timeout,
+
+    /// The peer answered with an error code.
+    #[fail(display = "the response has an error number: {}", _0)]
+    ReturnCode(i32),
+
+    /// The response couldn't be decoded.
+    #[fail(display = "invalid response: {}", _0)]
+    InvalidResponse(String),
+
+    /// The connection was closed before the response arrived.
+    #[fail(display = "the connection was closed")]
+    Closed,
+}
+
 /// An error returned when the data of `BucketHead` is invalid.
 #[derive(Debug, Fail)]
 pub enum BucketHeadError {
@@ -41,13 +72,33 @@ pub enum BucketHeadError {
     #[fail(display = "invalid bucket signature (bad signature: {:08X})", _0)]
     InvalidSignature(u64),
 
-    /// An error code was returned.
-    #[fail(display = "the bucket has an error number: {}", _0)]
-    ReturnCode(i32),
-
     /// Packet too big. The maximum size of a levin bucket is [this][1]
     ///
     /// [1]: /bucket/constant.LEVIN_DEFAULT_MAX_PACKET_SIZE.html
     #[fail(display = "the bucket size is too big ({} bytes)", _0)]
     TooBig(u64),
 }
+
+/// An error returned when the fragments of a message are invalid.
+#[derive(Debug, Fail)]
+pub enum FragmentError {
+    /// A fragment was received without the start of a message.
+    #[fail(display = "received a fragment without the start of a message")]
+    Unexpected,
+
+    /// A new message was received before the last fragment.
+    #[fail(display = "the fragmented message was interrupted")]
+    Interrupted,
+
+    /// The reassembled message is bigger than the limit.
+    #[fail(display = "the fragmented message is too big ({} bytes)", _0)]
+    TooBig(u64),
+
+    /// The fragments end before the reassembled message does.
+    #[fail(display = "the fragmented message is truncated")]
+    Truncated,
+
+    /// The reassembled message is a fragment too.
+    #[fail(display = "the fragmented message contains another fragment")]
+    Nested,
+}
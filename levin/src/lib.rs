@@ -36,10 +36,23 @@ extern crate tokio_core;
 extern crate tokio_io;
 
 extern crate crossbeam;
+extern crate net2;
 extern crate parking_lot;
 
 extern crate bytes;
 
+#[cfg(feature = "tls")]
+extern crate rcgen;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate webpki;
+
+extern crate serde;
+#[cfg(test)]
+#[macro_use]
+extern crate serde_derive;
+
 #[macro_use]
 extern crate failure_derive;
 extern crate failure;
@@ -56,4 +69,4 @@ mod command;
 mod error;
 
 pub use command::{COMMAND_BASE_ID, Command, Id};
-pub use error::{BucketHeadError, Error, Result};
+pub use error::{BucketHeadError, Error, FragmentError, InvokeError, Result};
@@ -108,9 +108,14 @@ impl<A> Sink for BucketSink<A>
     }
 
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
-        if let Some(ref mut future) = self.current_write {
-            let (a, _) = try_ready!(future.poll());
-            self.a = Some(a);
+        if let Some(mut future) = self.current_write.take() {
+            match future.poll()? {
+                Async::Ready((a, _)) => self.a = Some(a),
+                Async::NotReady => {
+                    self.current_write = Some(future);
+                    return Ok(Async::NotReady);
+                }
+            }
         }
 
         // check if there are remaining items to write
@@ -133,7 +138,13 @@ impl<A> Sink for BucketSink<A>
                 }
             }
         } else {
-            return Ok(Async::Ready(()));
+            // everything was written, flush the writer.
+            let a = self.a.as_mut().expect("invalid state");
+            match a.flush() {
+                Ok(()) => Ok(Async::Ready(())),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+                Err(e) => Err(e),
+            }
         }
     }
 }
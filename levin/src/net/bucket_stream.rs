@@ -14,20 +14,69 @@ use futures::stream::Stream;
 
 use tokio_io::AsyncRead;
 
-use bucket::{Bucket, Receive};
-use error::Result;
+use bucket::{Bucket, Defragmenter, Receive, SizeLimitRef, LEVIN_INITIAL_MAX_PACKET_SIZE};
+use error::{BucketHeadError, Result};
 
-/// Creates the bucket stream.
+/// Creates the bucket stream, the reassembled messages are limited to
+/// [`LEVIN_INITIAL_MAX_PACKET_SIZE`][1] until
+/// [`set_max_packet_size`][2] raises it after the handshake.
+///
+/// [1]: ../bucket/constant.LEVIN_INITIAL_MAX_PACKET_SIZE.html
+/// [2]: struct.BucketStream.html#method.set_max_packet_size
 pub fn bucket_stream<A>(a: A) -> BucketStream<A>
     where A: AsyncRead
 {
-    BucketStream { future: Bucket::receive_future(a) }
+    BucketStream {
+        future: Bucket::receive_future(a),
+        defragmenter: Defragmenter::new(LEVIN_INITIAL_MAX_PACKET_SIZE),
+        limit: None,
+    }
 }
 
-/// A stream of buckets.
+/// Creates a bucket stream that fails on the buckets and reassembled
+/// messages bigger than `limit` allows.
+pub fn bucket_stream_with_limit<A>(a: A, limit: SizeLimitRef) -> BucketStream<A>
+    where A: AsyncRead
+{
+    BucketStream {
+        future: Bucket::receive_future_with_limit(a, limit.clone()),
+        defragmenter: Defragmenter::new(LEVIN_INITIAL_MAX_PACKET_SIZE),
+        limit: Some(limit),
+    }
+}
+
+/// A stream of buckets, fragmented messages are reassembled and noise is
+/// discarded.
 #[derive(Debug)]
 pub struct BucketStream<A: AsyncRead> {
     future: Receive<A>,
+    defragmenter: Defragmenter,
+    limit: Option<SizeLimitRef>,
+}
+
+impl<A> BucketStream<A>
+    where A: AsyncRead
+{
+    /// Changes the maximum size of the reassembled messages, the streams
+    /// with a limit use the one it allows instead.
+    pub fn set_max_packet_size(&mut self, size: u64) {
+        self.defragmenter.set_max_size(size);
+    }
+
+    fn defragment(&mut self, bucket: Bucket) -> Result<Option<Bucket>> {
+        let limit = match self.limit {
+            Some(ref limit) => limit.clone(),
+            None => return self.defragmenter.push(bucket),
+        };
+
+        self.defragmenter.set_max_size(limit.max_size(&bucket.head));
+        match self.defragmenter.push(bucket)? {
+            Some(ref bucket) if bucket.head.cb > limit.max_size(&bucket.head) => {
+                Err(BucketHeadError::TooBig(bucket.head.cb).into())
+            }
+            bucket => Ok(bucket),
+        }
+    }
 }
 
 impl<A> Stream for BucketStream<A>
@@ -37,10 +86,20 @@ impl<A> Stream for BucketStream<A>
     type Error = io::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
-        let (stream, result) = try_ready!(self.future.poll());
+        loop {
+            let (stream, result) = try_ready!(self.future.poll());
 
-        replace(&mut self.future, Bucket::receive_future(stream));
+            let future = match self.limit {
+                Some(ref limit) => Bucket::receive_future_with_limit(stream, limit.clone()),
+                None => Bucket::receive_future(stream),
+            };
+            replace(&mut self.future, future);
 
-        Ok(Async::Ready(Some(result)))
+            match result.and_then(|bucket| self.defragment(bucket)) {
+                Ok(Some(bucket)) => return Ok(Async::Ready(Some(Ok(bucket)))),
+                Ok(None) => continue,
+                Err(e) => return Ok(Async::Ready(Some(Err(e)))),
+            }
+        }
     }
 }
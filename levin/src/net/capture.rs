@@ -0,0 +1,335 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Capture of the levin traffic.
+//!
+//! A capture file starts with [`CAPTURE_MAGIC`][1] and the format version,
+//! each record is:
+//!
+//! - The timestamp, milliseconds since the unix epoch (`u64`).
+//! - The flow, `0` if received and `1` if sent (`u8`).
+//! - The length of the peer address (`u8`) and the address as text.
+//! - The bucket, head and body as sent over the wire.
+//!
+//! All integers are little endian.
+//!
+//! [1]: constant.CAPTURE_MAGIC.html
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut, BytesMut, IntoBuf, LittleEndian};
+use futures::{Future, Stream};
+use parking_lot::Mutex;
+use tokio_core::reactor::Remote;
+
+use bucket::{Bucket, BucketHead, BUCKET_HEAD_LENGTH};
+use net::io::IoHandlerRef;
+use net::tcp_client::{Commands, handle_bucket};
+
+/// The first bytes of a capture file.
+pub const CAPTURE_MAGIC: &[u8; 8] = b"LEVINCAP";
+
+/// The version of the capture format.
+pub const CAPTURE_VERSION: u32 = 1;
+
+/// Bytes of records kept in memory until they're written to the file.
+const CAPTURE_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Whether a bucket was received or sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// The bucket was received from the peer.
+    Received,
+    /// The bucket was sent to the peer.
+    Sent,
+}
+
+/// A captured bucket.
+#[derive(Debug)]
+pub struct Record {
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+    /// Whether the bucket was received or sent.
+    pub flow: Flow,
+    /// The peer address.
+    pub addr: SocketAddr,
+    /// The bucket.
+    pub bucket: Bucket,
+}
+
+/// Records the buckets of the connections to a file.
+///
+/// The records are buffered, [`flush`][1] writes them to the file and it's
+/// done when the capture is dropped.
+///
+/// [1]: #method.flush
+#[derive(Debug)]
+pub struct Capture {
+    writer: Mutex<BufWriter<File>>,
+}
+
+/// A reference to a `Capture`.
+pub type CaptureRef = Arc<Capture>;
+
+impl Capture {
+    /// Creates the capture file, it's truncated if it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Capture> {
+        let mut writer = BufWriter::with_capacity(CAPTURE_BUFFER_SIZE, File::create(path)?);
+
+        let mut header = BytesMut::with_capacity(CAPTURE_MAGIC.len() + 4);
+        header.put_slice(CAPTURE_MAGIC);
+        header.put_u32::<LittleEndian>(CAPTURE_VERSION);
+        writer.write_all(&header)?;
+        writer.flush()?;
+
+        Ok(Capture { writer: Mutex::new(writer) })
+    }
+
+    /// Records a bucket, failures are logged.
+    pub fn record(&self, flow: Flow, addr: &SocketAddr, bucket: &Bucket) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_millis()))
+            .unwrap_or(0);
+
+        let addr = addr.to_string();
+        let mut buf = BytesMut::with_capacity(10 + addr.len() + BUCKET_HEAD_LENGTH +
+                                              bucket.body.len());
+        buf.put_u64::<LittleEndian>(timestamp);
+        buf.put_u8(match flow {
+                       Flow::Received => 0,
+                       Flow::Sent => 1,
+                   });
+        buf.put_u8(addr.len() as u8);
+        buf.put_slice(addr.as_bytes());
+        BucketHead::write(&mut buf, &bucket.head);
+        buf.put_slice(&bucket.body);
+
+        if let Err(e) = self.writer.lock().write_all(&buf) {
+            warn!("couldn't write to the capture file: {}", e);
+        }
+    }
+
+    /// Writes the buffered records to the file, failures are logged.
+    pub fn flush(&self) {
+        if let Err(e) = self.writer.lock().flush() {
+            warn!("couldn't write to the capture file: {}", e);
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Reads the records of a capture.
+#[derive(Debug)]
+pub struct CaptureReader<R: Read> {
+    reader: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    /// Opens a capture file.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<CaptureReader<BufReader<File>>> {
+        CaptureReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads the capture header, it fails if it isn't a capture.
+    pub fn new(mut reader: R) -> io::Result<CaptureReader<R>> {
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+
+        if &header[..8] != CAPTURE_MAGIC {
+            return Err(invalid_data("not a levin capture"));
+        }
+
+        let version = (&header[8..]).into_buf().get_u32::<LittleEndian>();
+        if version != CAPTURE_VERSION {
+            return Err(invalid_data(format!("unsupported capture version {}", version)));
+        }
+
+        Ok(CaptureReader { reader })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let mut prefix = [0u8; 10];
+        match self.reader.read_exact(&mut prefix) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let mut prefix = (&prefix[..]).into_buf();
+        let timestamp = prefix.get_u64::<LittleEndian>();
+        let flow = match prefix.get_u8() {
+            0 => Flow::Received,
+            1 => Flow::Sent,
+            n => return Err(invalid_data(format!("invalid flow {}", n))),
+        };
+
+        let mut addr = vec![0u8; prefix.get_u8() as usize];
+        self.reader.read_exact(&mut addr)?;
+        let addr = String::from_utf8(addr)
+            .ok()
+            .and_then(|addr| addr.parse().ok())
+            .ok_or_else(|| invalid_data("invalid peer address"))?;
+
+        let mut head = [0u8; BUCKET_HEAD_LENGTH];
+        self.reader.read_exact(&mut head)?;
+        let head = BucketHead::read(&mut (&head[..]).into_buf())
+            .map_err(|e| invalid_data(e.to_string()))?;
+
+        // `BucketHead::read` rejects lengths over the
+        // `LEVIN_DEFAULT_MAX_PACKET_SIZE`, a corrupted one can't make us
+        // allocate anything.
+        let mut body = vec![0u8; head.cb as usize];
+        self.reader.read_exact(&mut body)?;
+
+        Ok(Some(Record {
+                    timestamp,
+                    flow,
+                    addr,
+                    bucket: Bucket {
+                        head,
+                        body: body.into(),
+                    },
+                }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        match self.read_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+    where E: Into<Box<::std::error::Error + Send + Sync>>
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Feeds the received requests of a capture to `io_handler` and returns
+/// the buckets it sent back, in order.
+pub fn replay<I>(records: I, io_handler: &IoHandlerRef, remote: Remote) -> Vec<Bucket>
+    where I: IntoIterator<Item = Record>
+{
+    let commands = Commands::new(remote);
+    for record in records {
+        if record.flow == Flow::Received && record.bucket.head.is_request() {
+            handle_bucket(&record.addr, io_handler, &commands, record.bucket);
+        }
+    }
+
+    commands.shutdown();
+    commands
+        .collect()
+        .wait()
+        .expect("the command queue doesn't fail")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use tokio_core::reactor::Core;
+
+    use portable_storage::{Section, to_section};
+
+    use bucket::LEVIN_DEFAULT_MAX_PACKET_SIZE;
+    use command::{COMMAND_BASE_ID, Command, Id};
+    use net::io::IoHandler;
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Pong {
+        value: u64,
+    }
+
+    struct Ping;
+
+    impl Command for Ping {
+        const ID: Id = COMMAND_BASE_ID + 1;
+
+        type Response = Pong;
+    }
+
+    #[test]
+    fn capture_is_read_and_replayed() {
+        let path = env::temp_dir().join(format!("xmr-levin-capture-{}", ::std::process::id()));
+        let addr = "[::1]:18080".parse().unwrap();
+        let pong = || to_section(&Pong { value: 7 }).unwrap();
+
+        {
+            let capture = Capture::create(&path).unwrap();
+            capture.record(Flow::Received, &addr, &Bucket::invokation(Ping::ID, Section::new()));
+            capture.record(Flow::Sent, &addr, &Bucket::invokation_response(Ping::ID, pong()));
+        }
+
+        let records = CaptureReader::open(&path)
+            .unwrap()
+            .collect::<io::Result<Vec<Record>>>()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].flow, Flow::Received);
+        assert_eq!(records[0].addr, addr);
+        assert!(records[0].bucket.head.is_request());
+        assert_eq!(records[1].flow, Flow::Sent);
+        assert_eq!(records[1].bucket.head.command, Ping::ID);
+        let expected = records[1].bucket.body.clone();
+
+        let mut io_handler = IoHandler::new();
+        io_handler.add_invokation::<Ping, _>(move |_, _| Ok(Some(pong())));
+
+        let core = Core::new().unwrap();
+        let responses = replay(records, &io_handler.to_ref(), core.remote());
+        assert_eq!(responses.len(), 1);
+        assert!(responses[0].head.is_response());
+        assert_eq!(responses[0].body, expected);
+    }
+
+    #[test]
+    fn oversized_bucket_isnt_read() {
+        let path = env::temp_dir().join(format!("xmr-levin-oversized-{}", ::std::process::id()));
+        let addr = "[::1]:18080".parse().unwrap();
+
+        {
+            let capture = Capture::create(&path).unwrap();
+            let mut bucket = Bucket::invokation(Ping::ID, Section::new());
+            bucket.head.cb = LEVIN_DEFAULT_MAX_PACKET_SIZE + 1;
+            capture.record(Flow::Received, &addr, &bucket);
+        }
+
+        let mut reader = CaptureReader::open(&path).unwrap();
+        let record = reader.next();
+        fs::remove_file(&path).unwrap();
+
+        match record {
+            Some(Err(ref e)) if e.kind() == io::ErrorKind::InvalidData => {}
+            record => panic!("unexpected record {:?}", record),
+        }
+    }
+}
@@ -0,0 +1,572 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp;
+use std::fmt::{self, Debug, Formatter};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::{Future, Stream, future};
+use futures::future::Either;
+
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::shutdown;
+
+use bucket::{BucketHead, SizeLimit};
+use error::Error;
+use net::capture::Flow;
+use net::io::IoHandlerRef;
+use net::bucket_stream::bucket_stream_with_limit;
+use net::bucket_sink::bucket_sink;
+use net::rate_limit::Throttled;
+use net::tcp_client::{Commands, handle_bucket};
+use net::transport::TransportFuture;
+
+/// Who opened a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The peer connected to us.
+    Inbound,
+    /// We connected to the peer.
+    Outbound,
+}
+
+/// Receives the events of the connections.
+pub trait ConnectionHandler: Send + Sync + 'static {
+    /// The connection was opened, it can be refused with
+    /// [`Connection::refuse`][1].
+    ///
+    /// [1]: struct.Connection.html#method.refuse
+    fn on_open(&self, connection: &Connection);
+
+    /// The connection was closed, by us or by the peer.
+    fn on_close(&self, connection: &Connection);
+
+    /// An IO error happened, the connection is closed after it.
+    fn on_error(&self, connection: &Connection, error: &io::Error);
+
+    /// The peer sent an invalid or too big bucket, the connection is closed
+    /// after it.
+    fn on_protocol_error(&self, connection: &Connection, error: &Error);
+}
+
+/// A reference to a `ConnectionHandler`.
+pub type ConnectionHandlerRef = Arc<ConnectionHandler>;
+
+/// A levin connection with a peer.
+#[allow(missing_debug_implementations)]
+#[derive(Clone)]
+pub struct Connection {
+    addr: SocketAddr,
+    direction: Direction,
+    commands: Commands,
+    refused: Arc<AtomicBool>,
+}
+
+impl Connection {
+    /// Creates a connection, the buckets queued in `commands` are sent
+    /// once it runs.
+    pub fn new(addr: SocketAddr, direction: Direction, commands: Commands) -> Connection {
+        Connection {
+            addr,
+            direction,
+            commands,
+            refused: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// The address of the peer.
+    pub fn addr(&self) -> &SocketAddr {
+        &self.addr
+    }
+
+    /// Who opened the connection.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// The command queue of the connection.
+    pub fn commands(&self) -> &Commands {
+        &self.commands
+    }
+
+    /// Closes the connection, the queued buckets are sent first.
+    pub fn shutdown(&self) {
+        self.commands.shutdown()
+    }
+
+    /// Refuses the connection from [`ConnectionHandler::on_open`][1], the
+    /// stream is closed without handling or sending any bucket.
+    ///
+    /// [1]: trait.ConnectionHandler.html#tymethod.on_open
+    pub fn refuse(&self) {
+        self.refused.store(true, Ordering::SeqCst);
+        self.commands.shutdown()
+    }
+
+    /// Creates a future that runs the connection over `stream` until it's
+    /// closed. The requests are given to `io_handler` and the events to
+    /// `connection_handler`.
+    pub fn run<S>(self,
+                  stream: S,
+                  io_handler: IoHandlerRef,
+                  connection_handler: ConnectionHandlerRef)
+                  -> Box<Future<Item = (), Error = ()> + Send + 'static>
+        where S: AsyncRead + AsyncWrite + Send + 'static
+    {
+        connection_handler.on_open(&self);
+
+        if self.refused.load(Ordering::SeqCst) {
+            return Box::new(shutdown(stream).then(move |_| {
+                                                      connection_handler.on_close(&self);
+                                                      Ok(())
+                                                  }));
+        }
+
+        self.serve(stream, io_handler, connection_handler)
+    }
+
+    /// Like `run`, but the connection is opened before `setup`, the
+    /// transport of its stream, completes so it counts while the transport
+    /// is set up. It's closed if the setup fails.
+    pub(crate) fn run_after(self,
+                            setup: TransportFuture,
+                            io_handler: IoHandlerRef,
+                            connection_handler: ConnectionHandlerRef)
+                            -> Box<Future<Item = (), Error = ()> + Send + 'static> {
+        connection_handler.on_open(&self);
+
+        if self.refused.load(Ordering::SeqCst) {
+            // dropping the setup closes the stream.
+            connection_handler.on_close(&self);
+            return Box::new(future::ok(()));
+        }
+
+        Box::new(setup.then(move |stream| match stream {
+                                Ok(stream) => {
+                                    Either::A(self.serve(stream, io_handler, connection_handler))
+                                }
+                                Err(e) => {
+                                    debug!("couldn't set up the transport with {}: {}",
+                                           self.addr,
+                                           e);
+                                    self.commands.shutdown();
+                                    connection_handler.on_close(&self);
+                                    Either::B(future::ok(()))
+                                }
+                            }))
+    }
+
+    /// Runs the opened connection over `stream`.
+    fn serve<S>(self,
+                stream: S,
+                io_handler: IoHandlerRef,
+                connection_handler: ConnectionHandlerRef)
+                -> Box<Future<Item = (), Error = ()> + Send + 'static>
+        where S: AsyncRead + AsyncWrite + Send + 'static
+    {
+        let (read_limiters, write_limiters) = io_handler.rate_limiters();
+        let stream = Throttled::new(stream,
+                                    self.commands.remote().clone(),
+                                    read_limiters,
+                                    write_limiters);
+        let (read_half, write_half) = stream.split();
+
+        let limit = Arc::new(PacketLimit {
+                                 commands: self.commands.clone(),
+                                 io_handler: io_handler.clone(),
+                             });
+
+        let capture = io_handler.capture().cloned();
+        let traffic_stats = io_handler.traffic_stats().cloned();
+
+        let receiver = {
+            let addr = self.addr;
+            let commands = self.commands.clone();
+            let capture = capture.clone();
+            let traffic_stats = traffic_stats.clone();
+            bucket_stream_with_limit(read_half, limit)
+                .map_err(Failure::Io)
+                .for_each(move |bucket| match bucket {
+                              Ok(bucket) => {
+                                  if let Some(ref capture) = capture {
+                                      capture.record(Flow::Received, &addr, &bucket);
+                                  }
+                                  if let Some(ref traffic_stats) = traffic_stats {
+                                      traffic_stats.record(Flow::Received, &addr, &bucket);
+                                  }
+
+                                  handle_bucket(&addr, &io_handler, &commands, bucket);
+                                  Ok(())
+                              }
+                              Err(e) => Err(Failure::Protocol(e)),
+                          })
+                .or_else(|failure| match failure {
+                             // The peer closed the connection.
+                             Failure::Io(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                                 Ok(())
+                             }
+                             failure => Err(failure),
+                         })
+        };
+
+        // Ends once the commands are shut down and the queued buckets are
+        // written.
+        let addr = self.addr;
+        let sent_stats = traffic_stats.clone();
+        let sender = self.commands
+            .clone()
+            .inspect(move |bucket| {
+                         if let Some(ref capture) = capture {
+                             capture.record(Flow::Sent, &addr, bucket);
+                         }
+                         if let Some(ref traffic_stats) = sent_stats {
+                             traffic_stats.record(Flow::Sent, &addr, bucket);
+                         }
+                     })
+            .forward(bucket_sink(write_half))
+            .and_then(|(_, sink)| {
+                          let write_half = sink.inner().expect("the sink is flushed");
+                          shutdown(write_half).map(|_| ())
+                      })
+            .map_err(Failure::Io);
+
+        let commands = self.commands.clone();
+        let io = receiver
+            .select2(sender)
+            .then(move |result| -> Box<Future<Item = (), Error = Failure> + Send> {
+                match result {
+                    // The peer stopped sending, the queued buckets are still
+                    // sent before closing.
+                    Ok(Either::A(((), sender))) => {
+                        commands.shutdown();
+                        Box::new(sender)
+                    }
+                    Ok(Either::B(((), _))) => Box::new(future::ok(())),
+                    Err(Either::A((e, _))) |
+                    Err(Either::B((e, _))) => Box::new(future::err(e)),
+                }
+            });
+
+        Box::new(io.then(move |result| {
+                             self.commands.shutdown();
+                             match result {
+                                 Ok(()) => {}
+                                 Err(Failure::Io(e)) => connection_handler.on_error(&self, &e),
+                                 Err(Failure::Protocol(e)) => {
+                                     connection_handler.on_protocol_error(&self, &e)
+                                 }
+                             }
+
+                             if let Some(ref traffic_stats) = traffic_stats {
+                                 traffic_stats.remove_peer(&self.addr);
+                             }
+
+                             connection_handler.on_close(&self);
+                             Ok(())
+                         }))
+    }
+}
+
+/// Why a connection was closed abruptly.
+enum Failure {
+    Io(io::Error),
+    Protocol(Error),
+}
+
+/// The size limits of the buckets received by a connection.
+struct PacketLimit {
+    commands: Commands,
+    io_handler: IoHandlerRef,
+}
+
+impl Debug for PacketLimit {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.debug_struct("PacketLimit")
+            .field("max_packet_size", &self.commands.max_packet_size())
+            .finish()
+    }
+}
+
+impl SizeLimit for PacketLimit {
+    fn max_size(&self, head: &BucketHead) -> u64 {
+        let max_size = self.commands.max_packet_size();
+        match self.io_handler.max_size(head.command) {
+            Some(command_max_size) if head.is_request() => cmp::min(max_size, command_max_size),
+            _ => max_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures::{Async, Poll};
+    use parking_lot::Mutex;
+
+    use tokio_core::reactor::Core;
+
+    use portable_storage::Section;
+
+    use bytes::BytesMut;
+
+    use bucket::{Bucket, LEVIN_INITIAL_MAX_PACKET_SIZE};
+    use command::{COMMAND_BASE_ID, Command, Id};
+    use net::io::IoHandler;
+    use net::stats::{Traffic, TrafficStats};
+    use net::transport::with_timeout;
+    use super::*;
+
+    struct Hello;
+
+    impl Command for Hello {
+        const ID: Id = COMMAND_BASE_ID + 1;
+
+        type Response = ();
+    }
+
+    /// A stream that reads the given bytes and keeps what's written.
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.lock().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for MockStream {}
+
+    impl AsyncWrite for MockStream {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Open(Direction),
+        Close(Direction),
+        ProtocolError,
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<Event>>,
+        refuse: bool,
+    }
+
+    impl ConnectionHandler for Recorder {
+        fn on_open(&self, connection: &Connection) {
+            self.events.lock().push(Event::Open(connection.direction()));
+            if self.refuse {
+                connection.refuse();
+            }
+        }
+
+        fn on_close(&self, connection: &Connection) {
+            self.events.lock().push(Event::Close(connection.direction()));
+        }
+
+        fn on_error(&self, _connection: &Connection, error: &io::Error) {
+            panic!("unexpected io error: {}", error);
+        }
+
+        fn on_protocol_error(&self, _connection: &Connection, _error: &Error) {
+            self.events.lock().push(Event::ProtocolError);
+        }
+    }
+
+    #[test]
+    fn queued_buckets_are_sent_before_closing() {
+        let mut core = Core::new().unwrap();
+
+        let received = Arc::new(AtomicUsize::new(0));
+        let mut io_handler = IoHandler::new();
+        io_handler.add_notification::<Hello, _>({
+            let received = received.clone();
+            move |_, _| {
+                received.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        let hello = Bucket::notification(Hello::ID, Section::new()).to_bytes();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let stream = MockStream {
+            input: Cursor::new(hello.to_vec()),
+            output: output.clone(),
+        };
+
+        let commands = Commands::new(core.remote());
+        commands.notify::<Hello>(Section::new());
+        commands.notify::<Hello>(Section::new());
+        let connection = Connection::new("127.0.0.1:18080".parse().unwrap(),
+                                         Direction::Inbound,
+                                         commands);
+        connection.shutdown();
+
+        let handler = Arc::new(Recorder::default());
+        core.run(connection.run(stream, io_handler.to_ref(), handler.clone()))
+            .unwrap();
+
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+        assert_eq!(*output.lock(), [&hello[..], &hello[..]].concat());
+        assert_eq!(*handler.events.lock(),
+                   vec![Event::Open(Direction::Inbound), Event::Close(Direction::Inbound)]);
+    }
+
+    #[test]
+    fn refused_connection_isnt_handled() {
+        let mut core = Core::new().unwrap();
+
+        let mut io_handler = IoHandler::new();
+        io_handler.add_notification::<Hello, _>(|_, _| panic!("refused connection handled"));
+
+        let hello = Bucket::notification(Hello::ID, Section::new()).to_bytes();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let stream = MockStream {
+            input: Cursor::new(hello.to_vec()),
+            output: output.clone(),
+        };
+
+        let commands = Commands::new(core.remote());
+        commands.notify::<Hello>(Section::new());
+        let connection = Connection::new("127.0.0.1:18080".parse().unwrap(),
+                                         Direction::Inbound,
+                                         commands);
+
+        let handler = Arc::new(Recorder {
+                                   refuse: true,
+                                   ..Recorder::default()
+                               });
+        core.run(connection.run(stream, io_handler.to_ref(), handler.clone()))
+            .unwrap();
+
+        assert!(output.lock().is_empty());
+        assert_eq!(*handler.events.lock(),
+                   vec![Event::Open(Direction::Inbound), Event::Close(Direction::Inbound)]);
+    }
+
+    #[test]
+    fn connection_is_opened_before_its_transport() {
+        let mut core = Core::new().unwrap();
+
+        let connection = Connection::new("127.0.0.1:18080".parse().unwrap(),
+                                         Direction::Inbound,
+                                         Commands::new(core.remote()));
+
+        // the peer never completes the setup.
+        let setup = with_timeout(Box::new(future::empty()),
+                                 Duration::from_millis(10),
+                                 &core.handle());
+        let handler = Arc::new(Recorder::default());
+        core.run(connection.run_after(setup, IoHandler::new().to_ref(), handler.clone()))
+            .unwrap();
+
+        assert_eq!(*handler.events.lock(),
+                   vec![Event::Open(Direction::Inbound), Event::Close(Direction::Inbound)]);
+    }
+
+    #[test]
+    fn traffic_is_counted() {
+        let mut core = Core::new().unwrap();
+
+        let traffic_stats = Arc::new(TrafficStats::new());
+        let mut io_handler = IoHandler::new();
+        io_handler.set_traffic_stats(traffic_stats.clone());
+        io_handler.add_notification::<Hello, _>(|_, _| {});
+
+        let hello = Bucket::notification(Hello::ID, Section::new()).to_bytes();
+        let stream = MockStream {
+            input: Cursor::new(hello.to_vec()),
+            output: Arc::new(Mutex::new(Vec::new())),
+        };
+
+        let commands = Commands::new(core.remote());
+        commands.notify::<Hello>(Section::new());
+        commands.notify::<Hello>(Section::new());
+        let connection = Connection::new("127.0.0.1:18080".parse().unwrap(),
+                                         Direction::Inbound,
+                                         commands);
+        connection.shutdown();
+
+        let handler = Arc::new(Recorder::default());
+        core.run(connection.run(stream, io_handler.to_ref(), handler))
+            .unwrap();
+
+        let traffic = Traffic {
+            bytes_received: hello.len() as u64,
+            bytes_sent: 2 * hello.len() as u64,
+            messages_received: 1,
+            messages_sent: 2,
+        };
+        assert_eq!(traffic_stats.command(Hello::ID), traffic);
+        assert_eq!(traffic_stats.total(), traffic);
+        assert!(traffic_stats.peers().is_empty());
+    }
+
+    fn assert_closed_on_protocol_error(input: Vec<u8>, io_handler: IoHandler) {
+        let mut core = Core::new().unwrap();
+        let stream = MockStream {
+            input: Cursor::new(input),
+            output: Arc::new(Mutex::new(Vec::new())),
+        };
+
+        let connection = Connection::new("127.0.0.1:18080".parse().unwrap(),
+                                         Direction::Inbound,
+                                         Commands::new(core.remote()));
+
+        let handler = Arc::new(Recorder::default());
+        core.run(connection.run(stream, io_handler.to_ref(), handler.clone()))
+            .unwrap();
+
+        assert_eq!(*handler.events.lock(),
+                   vec![Event::Open(Direction::Inbound),
+                        Event::ProtocolError,
+                        Event::Close(Direction::Inbound)]);
+    }
+
+    #[test]
+    fn big_bucket_before_handshake_is_rejected() {
+        let mut hello = Bucket::notification(Hello::ID, Section::new());
+        hello.head.cb = LEVIN_INITIAL_MAX_PACKET_SIZE + 1;
+
+        // Only the head is sent, the body must not be waited for.
+        let mut input = BytesMut::new();
+        BucketHead::write(&mut input, &hello.head);
+
+        assert_closed_on_protocol_error(input.to_vec(), IoHandler::new());
+    }
+
+    #[test]
+    fn request_bigger_than_command_limit_is_rejected() {
+        let mut io_handler = IoHandler::new();
+        io_handler.set_max_size::<Hello>(4);
+        io_handler.add_notification::<Hello, _>(|_, _| panic!("oversized request handled"));
+
+        let hello = Bucket::notification(Hello::ID, Section::new()).to_bytes();
+        assert_closed_on_protocol_error(hello.to_vec(), io_handler);
+    }
+}
@@ -10,7 +10,12 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use command::{Id, Command};
+use net::capture::CaptureRef;
 use net::handlers::{NotificationHandler, InvokationHandler, RemoteHandler};
+use net::rate_limit::{RateLimiter, RateLimiterRef, RateLimits};
+use net::socks::Socks5Proxy;
+use net::stats::TrafficStatsRef;
+use net::transport::{PlainTransport, TransportRef};
 
 /// A reference to an `IoHandler`.
 pub type IoHandlerRef = Arc<IoHandler>;
@@ -20,17 +25,45 @@ pub type IoHandlerRef = Arc<IoHandler>;
 #[derive(Clone)]
 pub struct IoHandler {
     handlers: HashMap<Id, RemoteHandler>,
+    max_sizes: HashMap<Id, u64>,
+    capture: Option<CaptureRef>,
+    transport: TransportRef,
+    proxy: Option<Socks5Proxy>,
+    up_limiter: Option<RateLimiterRef>,
+    down_limiter: Option<RateLimiterRef>,
+    connection_rate_limits: RateLimits,
+    traffic_stats: Option<TrafficStatsRef>,
 }
 
 impl IoHandler {
     /// Creates an empty `IoHandler`.
     pub fn new() -> IoHandler {
-        IoHandler { handlers: HashMap::new() }
+        IoHandler {
+            handlers: HashMap::new(),
+            max_sizes: HashMap::new(),
+            capture: None,
+            transport: Arc::new(PlainTransport),
+            proxy: None,
+            up_limiter: None,
+            down_limiter: None,
+            connection_rate_limits: RateLimits::default(),
+            traffic_stats: None,
+        }
     }
 
     /// Creates an `IoHandler` with the given capacity.
     pub fn with_capacity(cap: usize) -> IoHandler {
-        IoHandler { handlers: HashMap::with_capacity(cap) }
+        IoHandler {
+            handlers: HashMap::with_capacity(cap),
+            max_sizes: HashMap::new(),
+            capture: None,
+            transport: Arc::new(PlainTransport),
+            proxy: None,
+            up_limiter: None,
+            down_limiter: None,
+            connection_rate_limits: RateLimits::default(),
+            traffic_stats: None,
+        }
     }
 
     /// Add a notification to this handler.
@@ -59,6 +92,90 @@ impl IoHandler {
         trace!("Adding invokation #{}", C::ID);
     }
 
+    /// Sets the maximum body size of the requests of a command, bigger
+    /// requests close the connection.
+    pub fn set_max_size<C>(&mut self, max_size: u64)
+        where C: Command
+    {
+        self.max_sizes.insert(C::ID, max_size);
+    }
+
+    /// The maximum body size of the requests of a command, if any.
+    pub fn max_size(&self, id: Id) -> Option<u64> {
+        self.max_sizes.get(&id).cloned()
+    }
+
+    /// Records the buckets of the connections using this handler.
+    pub fn set_capture(&mut self, capture: CaptureRef) {
+        self.capture = Some(capture);
+    }
+
+    /// The capture of the connections using this handler, if any.
+    pub fn capture(&self) -> Option<&CaptureRef> {
+        self.capture.as_ref()
+    }
+
+    /// Sets the transport used to secure the connections of this handler.
+    pub fn set_transport(&mut self, transport: TransportRef) {
+        self.transport = transport;
+    }
+
+    /// The transport of the connections using this handler, plain TCP
+    /// by default.
+    pub fn transport(&self) -> &TransportRef {
+        &self.transport
+    }
+
+    /// Makes the outbound connections through a SOCKS5 proxy.
+    pub fn set_proxy(&mut self, proxy: Socks5Proxy) {
+        self.proxy = Some(proxy);
+    }
+
+    /// The proxy of the outbound connections, if any.
+    pub fn proxy(&self) -> Option<&Socks5Proxy> {
+        self.proxy.as_ref()
+    }
+
+    /// Limits the traffic of all the connections using this handler
+    /// together.
+    pub fn set_rate_limits(&mut self, limits: RateLimits) {
+        self.up_limiter = limits.up.map(|rate| Arc::new(RateLimiter::new(rate)));
+        self.down_limiter = limits.down.map(|rate| Arc::new(RateLimiter::new(rate)));
+    }
+
+    /// Limits the traffic of each of the connections using this handler.
+    pub fn set_connection_rate_limits(&mut self, limits: RateLimits) {
+        self.connection_rate_limits = limits;
+    }
+
+    /// The limiters of a new connection, the ones of the received and of
+    /// the sent bytes.
+    pub(crate) fn rate_limiters(&self) -> (Vec<RateLimiterRef>, Vec<RateLimiterRef>) {
+        let limits = self.connection_rate_limits;
+        let down = self.down_limiter
+            .iter()
+            .cloned()
+            .chain(limits.down.map(|rate| Arc::new(RateLimiter::new(rate))))
+            .collect();
+        let up = self.up_limiter
+            .iter()
+            .cloned()
+            .chain(limits.up.map(|rate| Arc::new(RateLimiter::new(rate))))
+            .collect();
+
+        (down, up)
+    }
+
+    /// Counts the traffic of the connections using this handler.
+    pub fn set_traffic_stats(&mut self, traffic_stats: TrafficStatsRef) {
+        self.traffic_stats = Some(traffic_stats);
+    }
+
+    /// The traffic counters of the connections using this handler, if any.
+    pub fn traffic_stats(&self) -> Option<&TrafficStatsRef> {
+        self.traffic_stats.as_ref()
+    }
+
     /// Get a handler.
     pub(crate) fn get(&self, id: Id) -> Option<RemoteHandler> {
         self.handlers.get(&id).cloned()
@@ -10,14 +10,32 @@
 
 mod bucket_sink;
 mod bucket_stream;
+mod capture;
+mod connection;
 mod handlers;
 mod io;
+mod rate_limit;
+mod socks;
+mod stats;
 mod tcp_server;
 mod tcp_client;
+#[cfg(feature = "tls")]
+mod tls;
+mod transport;
 
 pub use self::bucket_sink::{BucketSink, bucket_sink};
-pub use self::bucket_stream::{BucketStream, bucket_stream};
+pub use self::capture::{Capture, CaptureRef, CaptureReader, Flow, Record, replay,
+                        CAPTURE_MAGIC, CAPTURE_VERSION};
+pub use self::bucket_stream::{BucketStream, bucket_stream, bucket_stream_with_limit};
 pub use self::handlers::{NotificationHandler, InvokationHandler, RemoteHandler};
 pub use self::io::{IoHandler, IoHandlerRef};
-pub use self::tcp_server::{TcpServer, ConnectionHandler, ConnectionHandlerRef};
-pub use self::tcp_client::{connect, Commands};
+pub use self::connection::{Connection, ConnectionHandler, ConnectionHandlerRef, Direction};
+pub use self::rate_limit::{RateLimiter, RateLimiterRef, RateLimits};
+pub use self::socks::{Socks5Auth, Socks5Proxy, TargetAddr, socks5_connect};
+pub use self::stats::{Traffic, TrafficStats, TrafficStatsRef};
+pub use self::tcp_server::TcpServer;
+pub use self::tcp_client::{connect, connect_target, Commands, Invokation};
+#[cfg(feature = "tls")]
+pub use self::tls::TlsTransport;
+pub use self::transport::{BoxedStream, DuplexStream, NetStream, PlainTransport, Transport,
+                          TransportFuture, TransportRef, duplex};
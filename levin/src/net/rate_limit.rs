@@ -0,0 +1,275 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Poll, future};
+use futures::task;
+
+use tokio_core::reactor::{Remote, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use parking_lot::Mutex;
+
+/// The upload and download limits of the traffic, in bytes per second.
+/// `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    /// The limit of the sent bytes.
+    pub up: Option<u64>,
+    /// The limit of the received bytes.
+    pub down: Option<u64>,
+}
+
+/// A reference to a `RateLimiter`.
+pub type RateLimiterRef = Arc<RateLimiter>;
+
+/// A token bucket, it holds up to one second of traffic and is refilled
+/// at `rate` bytes per second.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: u64,
+    tokens: Mutex<Tokens>,
+}
+
+#[derive(Debug)]
+struct Tokens {
+    available: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a full token bucket, `rate` is in bytes per second.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` is zero.
+    pub fn new(rate: u64) -> RateLimiter {
+        assert!(rate > 0, "the rate limit can't be zero");
+
+        RateLimiter {
+            rate,
+            tokens: Mutex::new(Tokens {
+                                   available: rate as f64,
+                                   updated: Instant::now(),
+                               }),
+        }
+    }
+
+    /// The rate of the limiter in bytes per second.
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// The bytes that can be transferred right now.
+    pub fn available(&self) -> usize {
+        self.available_at(Instant::now())
+    }
+
+    /// Takes the tokens of transferred bytes.
+    pub fn consume(&self, bytes: usize) {
+        self.consume_at(bytes, Instant::now())
+    }
+
+    /// How long until a reasonable amount of bytes can be transferred, a
+    /// twentieth of the rate, so a slow limiter isn't polled for every byte.
+    pub fn delay(&self) -> Duration {
+        self.delay_at(Instant::now())
+    }
+
+    fn refill(&self, tokens: &mut Tokens, now: Instant) {
+        if now <= tokens.updated {
+            return;
+        }
+
+        let elapsed = now - tokens.updated;
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        tokens.available = (tokens.available + elapsed * self.rate as f64).min(self.rate as f64);
+        tokens.updated = now;
+    }
+
+    fn available_at(&self, now: Instant) -> usize {
+        let mut tokens = self.tokens.lock();
+        self.refill(&mut tokens, now);
+        cmp::max(tokens.available as i64, 0) as usize
+    }
+
+    fn consume_at(&self, bytes: usize, now: Instant) {
+        let mut tokens = self.tokens.lock();
+        self.refill(&mut tokens, now);
+        // other connections sharing the limiter may take the same tokens,
+        // the debt is paid before anything else is transferred.
+        tokens.available -= bytes as f64;
+    }
+
+    fn delay_at(&self, now: Instant) -> Duration {
+        let mut tokens = self.tokens.lock();
+        self.refill(&mut tokens, now);
+
+        let wanted = cmp::max(self.rate / 20, 1) as f64;
+        let missing = wanted - tokens.available;
+        if missing <= 0.0 {
+            return Duration::from_millis(0);
+        }
+
+        let nanos = (missing * 1e9 / self.rate as f64).ceil() as u64;
+        Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+    }
+}
+
+/// A stream whose reads and writes are limited by rate limiters, the task
+/// is woken up once the limiters are refilled.
+pub(crate) struct Throttled<S> {
+    inner: S,
+    remote: Remote,
+    read_limiters: Vec<RateLimiterRef>,
+    write_limiters: Vec<RateLimiterRef>,
+}
+
+impl<S> Throttled<S> {
+    pub(crate) fn new(inner: S,
+                      remote: Remote,
+                      read_limiters: Vec<RateLimiterRef>,
+                      write_limiters: Vec<RateLimiterRef>)
+                      -> Throttled<S> {
+        Throttled {
+            inner,
+            remote,
+            read_limiters,
+            write_limiters,
+        }
+    }
+
+    /// Wakes up the current task after `delay`.
+    fn wake_after(&self, delay: Duration) -> io::Error {
+        let task = task::current();
+        self.remote
+            .spawn(move |handle| {
+                       future::result(Timeout::new(delay, handle))
+                           .flatten()
+                           .then(move |_| {
+                                     task.notify();
+                                     Ok(())
+                                 })
+                   });
+
+        io::ErrorKind::WouldBlock.into()
+    }
+}
+
+/// The bytes of `len` all the limiters allow, or how long to wait until
+/// they do.
+fn allowance(limiters: &[RateLimiterRef], len: usize) -> Result<usize, Duration> {
+    let mut allowed = len;
+    let mut wait = None;
+    for limiter in limiters.iter() {
+        match limiter.available() {
+            0 => wait = cmp::max(wait, Some(limiter.delay())),
+            available => allowed = cmp::min(allowed, available),
+        }
+    }
+
+    match wait {
+        Some(wait) => Err(wait),
+        None => Ok(allowed),
+    }
+}
+
+impl<S: Read> Read for Throttled<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.read_limiters.is_empty() {
+            return self.inner.read(buf);
+        }
+
+        let allowed = allowance(&self.read_limiters, buf.len())
+            .map_err(|delay| self.wake_after(delay))?;
+        let read = self.inner.read(&mut buf[..allowed])?;
+        for limiter in self.read_limiters.iter() {
+            limiter.consume(read);
+        }
+
+        Ok(read)
+    }
+}
+
+impl<S: Write> Write for Throttled<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.write_limiters.is_empty() {
+            return self.inner.write(buf);
+        }
+
+        let allowed = allowance(&self.write_limiters, buf.len())
+            .map_err(|delay| self.wake_after(delay))?;
+        let written = self.inner.write(&buf[..allowed])?;
+        for limiter in self.write_limiters.iter() {
+            limiter.consume(written);
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Throttled<S> {}
+
+impl<S: AsyncWrite> AsyncWrite for Throttled<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_core::reactor::Core;
+    use tokio_io::io::{read_exact, write_all};
+
+    use net::transport::duplex;
+    use super::*;
+
+    #[test]
+    fn tokens_are_refilled_up_to_the_rate() {
+        let limiter = RateLimiter::new(1000);
+        let start = limiter.tokens.lock().updated;
+
+        assert_eq!(limiter.available_at(start), 1000);
+        limiter.consume_at(1500, start);
+        assert_eq!(limiter.available_at(start), 0);
+        assert_eq!(limiter.delay_at(start), Duration::from_millis(550));
+
+        let later = start + Duration::from_millis(750);
+        assert_eq!(limiter.available_at(later), 250);
+        assert_eq!(limiter.delay_at(later), Duration::from_millis(0));
+
+        let much_later = start + Duration::from_secs(10);
+        assert_eq!(limiter.available_at(much_later), 1000);
+    }
+
+    #[test]
+    fn writes_are_throttled() {
+        let mut core = Core::new().unwrap();
+        let (a, b) = duplex();
+
+        let limiter = Arc::new(RateLimiter::new(20_000));
+        let a = Throttled::new(a, core.remote(), Vec::new(), vec![limiter.clone()]);
+
+        let start = Instant::now();
+        let data = vec![0x42u8; 30_000];
+        let read = read_exact(b, vec![0u8; data.len()]);
+        let ((_, written), (_, read)) = core.run(write_all(a, data).join(read)).unwrap();
+
+        // the first 20000 bytes are the burst, the rest take half a second.
+        assert!(start.elapsed() >= Duration::from_millis(450));
+        assert_eq!(written, read);
+    }
+}
@@ -0,0 +1,429 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{self, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use futures::{Future, future};
+
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::{read_exact, write_all};
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_AUTH_VERSION: u8 = 1;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NOT_ACCEPTABLE: u8 = 0xff;
+
+const COMMAND_CONNECT: u8 = 0x01;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// A SOCKS5 proxy used for the outbound connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    /// The address of the proxy.
+    pub addr: SocketAddr,
+    /// The credentials, if the proxy needs them.
+    pub auth: Option<Socks5Auth>,
+}
+
+/// Username and password authentication.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Socks5Auth {
+    /// The username.
+    pub username: String,
+    /// The password.
+    pub password: String,
+}
+
+impl fmt::Debug for Socks5Auth {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.debug_struct("Socks5Auth")
+            .field("username", &self.username)
+            .field("password", &"...")
+            .finish()
+    }
+}
+
+impl FromStr for Socks5Auth {
+    type Err = io::Error;
+
+    /// Parses `username:password`.
+    fn from_str(s: &str) -> io::Result<Socks5Auth> {
+        let colon = s.find(':')
+            .ok_or_else(|| {
+                            io::Error::new(io::ErrorKind::InvalidInput,
+                                           "expected `username:password`")
+                        })?;
+
+        Ok(Socks5Auth {
+               username: s[..colon].to_string(),
+               password: s[colon + 1..].to_string(),
+           })
+    }
+}
+
+/// Where a connection goes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TargetAddr {
+    /// An IP address.
+    Ip(SocketAddr),
+    /// A hostname resolved by the proxy, e.g. an onion or I2P address.
+    Domain(String, u16),
+}
+
+impl TargetAddr {
+    /// The address the connections to the target are identified by. The
+    /// ones to a hostname get an address of the IPv6 discard prefix
+    /// `100::/64` made from a hash of the hostname, and its port.
+    pub fn key(&self) -> SocketAddr {
+        match *self {
+            TargetAddr::Ip(addr) => addr,
+            TargetAddr::Domain(ref host, port) => {
+                let mut hasher = DefaultHasher::new();
+                host.hash(&mut hasher);
+                let hash = hasher.finish();
+
+                let ip = Ipv6Addr::new(0x100,
+                                       0,
+                                       0,
+                                       0,
+                                       (hash >> 48) as u16,
+                                       (hash >> 32) as u16,
+                                       (hash >> 16) as u16,
+                                       hash as u16);
+                SocketAddr::new(ip.into(), port)
+            }
+        }
+    }
+}
+
+impl From<SocketAddr> for TargetAddr {
+    fn from(addr: SocketAddr) -> TargetAddr {
+        TargetAddr::Ip(addr)
+    }
+}
+
+impl Display for TargetAddr {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match *self {
+            TargetAddr::Ip(ref addr) => addr.fmt(fmt),
+            TargetAddr::Domain(ref host, port) => write!(fmt, "{}:{}", host, port),
+        }
+    }
+}
+
+impl FromStr for TargetAddr {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<TargetAddr> {
+        if let Ok(addr) = s.parse() {
+            return Ok(TargetAddr::Ip(addr));
+        }
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "expected `host:port`");
+        let colon = s.rfind(':').ok_or_else(&invalid)?;
+        let host = &s[..colon];
+        let port = s[colon + 1..].parse().map_err(|_| invalid())?;
+        if host.is_empty() || host.len() > 255 {
+            return Err(invalid());
+        }
+
+        Ok(TargetAddr::Domain(host.to_string(), port))
+    }
+}
+
+/// Asks the SOCKS5 proxy at the other end of `stream` to connect to
+/// `target`, the returned stream is connected to the target.
+pub fn socks5_connect<S>(stream: S,
+                         target: &TargetAddr,
+                         auth: Option<&Socks5Auth>)
+                         -> Box<Future<Item = S, Error = io::Error> + Send>
+    where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    let request = match connect_request(target) {
+        Ok(request) => request,
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    let auth = auth.cloned();
+    let greeting = match auth {
+        Some(_) => vec![SOCKS_VERSION, 2, METHOD_NO_AUTH, METHOD_USERNAME_PASSWORD],
+        None => vec![SOCKS_VERSION, 1, METHOD_NO_AUTH],
+    };
+
+    let negotiated = write_all(stream, greeting)
+        .and_then(|(stream, _)| read_exact(stream, [0u8; 2]))
+        .and_then(move |(stream, reply)| -> Box<Future<Item = S, Error = io::Error> + Send> {
+            if reply[0] != SOCKS_VERSION {
+                return Box::new(future::err(socks_error("invalid SOCKS version")));
+            }
+
+            match (reply[1], auth) {
+                (METHOD_NO_AUTH, _) => Box::new(future::ok(stream)),
+                (METHOD_USERNAME_PASSWORD, Some(auth)) => authenticate(stream, &auth),
+                (METHOD_NOT_ACCEPTABLE, _) => {
+                    Box::new(future::err(refused("the proxy refused the authentication methods")))
+                }
+                _ => Box::new(future::err(socks_error("invalid authentication method"))),
+            }
+        });
+
+    Box::new(negotiated
+                 .and_then(move |stream| write_all(stream, request))
+                 .and_then(|(stream, _)| read_exact(stream, [0u8; 4]))
+                 .and_then(|(stream, reply)| {
+                               if reply[0] != SOCKS_VERSION {
+                                   return Err(socks_error("invalid SOCKS version"));
+                               }
+                               if reply[1] != 0 {
+                                   return Err(refused(reply_message(reply[1])));
+                               }
+
+                               Ok((stream, reply[3]))
+                           })
+                 .and_then(skip_bound_addr))
+}
+
+fn authenticate<S>(stream: S, auth: &Socks5Auth) -> Box<Future<Item = S, Error = io::Error> + Send>
+    where S: AsyncRead + AsyncWrite + Send + 'static
+{
+    if auth.username.len() > 255 || auth.password.len() > 255 {
+        return Box::new(future::err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                   "SOCKS credentials are too long")));
+    }
+
+    let mut request = Vec::with_capacity(3 + auth.username.len() + auth.password.len());
+    request.push(SOCKS_AUTH_VERSION);
+    request.push(auth.username.len() as u8);
+    request.extend_from_slice(auth.username.as_bytes());
+    request.push(auth.password.len() as u8);
+    request.extend_from_slice(auth.password.as_bytes());
+
+    Box::new(write_all(stream, request)
+                 .and_then(|(stream, _)| read_exact(stream, [0u8; 2]))
+                 .and_then(|(stream, reply)| if reply[1] == 0 {
+                               Ok(stream)
+                           } else {
+                               Err(refused("the proxy rejected the credentials"))
+                           }))
+}
+
+fn connect_request(target: &TargetAddr) -> io::Result<Vec<u8>> {
+    let mut request = vec![SOCKS_VERSION, COMMAND_CONNECT, 0];
+    let port = match *target {
+        TargetAddr::Ip(addr) => {
+            match addr.ip() {
+                IpAddr::V4(ip) => {
+                    request.push(ATYP_IPV4);
+                    request.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    request.push(ATYP_IPV6);
+                    request.extend_from_slice(&ip.octets());
+                }
+            }
+            addr.port()
+        }
+        TargetAddr::Domain(ref host, port) => {
+            if host.is_empty() || host.len() > 255 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid hostname"));
+            }
+
+            request.push(ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+            port
+        }
+    };
+
+    request.push((port >> 8) as u8);
+    request.push(port as u8);
+    Ok(request)
+}
+
+/// Reads the address the proxy bound, it isn't used.
+fn skip_bound_addr<S>((stream, atyp): (S, u8)) -> Box<Future<Item = S, Error = io::Error> + Send>
+    where S: AsyncRead + Send + 'static
+{
+    Box::new(read_addr(stream, atyp).map(|(stream, _)| stream))
+}
+
+/// Reads an address of the given type followed by the port.
+fn read_addr<S>(stream: S, atyp: u8) -> Box<Future<Item = (S, Vec<u8>), Error = io::Error> + Send>
+    where S: AsyncRead + Send + 'static
+{
+    let len = match atyp {
+        ATYP_IPV4 => 4 + 2,
+        ATYP_IPV6 => 16 + 2,
+        ATYP_DOMAIN => {
+            return Box::new(read_exact(stream, [0u8; 1]).and_then(|(stream, len)| {
+                read_exact(stream, vec![0u8; len[0] as usize + 2]).map(move |(stream, addr)| {
+                    let mut domain = len.to_vec();
+                    domain.extend_from_slice(&addr);
+                    (stream, domain)
+                })
+            }))
+        }
+        _ => return Box::new(future::err(socks_error("invalid address type"))),
+    };
+
+    Box::new(read_exact(stream, vec![0u8; len]))
+}
+
+fn reply_message(reply: u8) -> &'static str {
+    match reply {
+        0x01 => "general SOCKS server failure",
+        0x02 => "connection not allowed by the proxy",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported by the proxy",
+        0x08 => "address type not supported by the proxy",
+        _ => "unknown SOCKS error",
+    }
+}
+
+fn socks_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("SOCKS5: {}", message))
+}
+
+fn refused(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionRefused, format!("SOCKS5: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::lazy;
+
+    use net::transport::{DuplexStream, duplex};
+    use super::*;
+
+    /// A SOCKS5 proxy that accepts one connection to `target` and answers
+    /// with `reply`.
+    fn proxy(stream: DuplexStream,
+             auth: Option<Socks5Auth>,
+             reply: u8)
+             -> Box<Future<Item = (DuplexStream, Vec<u8>), Error = io::Error> + Send> {
+        let method = match auth {
+            Some(_) => METHOD_USERNAME_PASSWORD,
+            None => METHOD_NO_AUTH,
+        };
+
+        let greeted = read_exact(stream, [0u8; 2])
+            .and_then(|(stream, head)| read_exact(stream, vec![0u8; head[1] as usize]))
+            .and_then(move |(stream, methods)| {
+                          assert!(methods.contains(&method));
+                          write_all(stream, [SOCKS_VERSION, method])
+                      });
+
+        let authenticated = greeted.and_then(move |(stream, _)| -> Box<Future<Item = _,
+                                                                               Error = _> + Send> {
+            let auth = match auth {
+                Some(auth) => auth,
+                None => return Box::new(future::ok(stream)),
+            };
+
+            Box::new(read_exact(stream, [0u8; 2])
+                         .and_then(|(stream, head)| {
+                                       read_exact(stream, vec![0u8; head[1] as usize + 1])
+                                   })
+                         .and_then(|(stream, username)| {
+                                       let len = username[username.len() - 1] as usize;
+                                       read_exact(stream, vec![0u8; len])
+                                           .map(move |(stream, password)| {
+                                                    (stream, username, password)
+                                                })
+                                   })
+                         .and_then(move |(stream, username, password)| {
+                                       assert_eq!(&username[..username.len() - 1],
+                                                  auth.username.as_bytes());
+                                       assert_eq!(password, auth.password.as_bytes());
+                                       write_all(stream, [SOCKS_AUTH_VERSION, 0])
+                                   })
+                         .map(|(stream, _)| stream))
+        });
+
+        Box::new(authenticated
+                     .and_then(|stream| read_exact(stream, [0u8; 4]))
+                     .and_then(|(stream, head)| {
+                                   read_addr(stream, head[3]).map(move |(stream, addr)| {
+                                       let mut request = head.to_vec();
+                                       request.extend_from_slice(&addr);
+                                       (stream, request)
+                                   })
+                               })
+                     .and_then(move |(stream, request)| {
+                                   let response = [SOCKS_VERSION, reply, 0, ATYP_IPV4,
+                                                   0, 0, 0, 0, 0, 0];
+                                   write_all(stream, response).map(|(stream, _)| (stream, request))
+                               }))
+    }
+
+    #[test]
+    fn hostnames_are_resolved_by_the_proxy() {
+        let (client, server) = duplex();
+        let auth = Socks5Auth {
+            username: "monero".to_string(),
+            password: "hunter2".to_string(),
+        };
+        let target = "xmrnode.onion:18083".parse::<TargetAddr>().unwrap();
+
+        let future = lazy(|| {
+            socks5_connect(client, &target, Some(&auth))
+                .and_then(|client| write_all(client, b"levin".to_vec()))
+                .join(proxy(server, Some(auth.clone()), 0)
+                          .and_then(|(server, request)| {
+                                        read_exact(server, [0u8; 5])
+                                            .map(move |(_, received)| (request, received))
+                                    }))
+        });
+
+        let (_, (request, received)) = future.wait().unwrap();
+        let mut expected = vec![SOCKS_VERSION, COMMAND_CONNECT, 0, ATYP_DOMAIN, 13];
+        expected.extend_from_slice(b"xmrnode.onion");
+        expected.extend_from_slice(&[0x46, 0xa3]);
+        assert_eq!(request, expected);
+        assert_eq!(&received, b"levin");
+    }
+
+    #[test]
+    fn refused_connection_fails() {
+        let (client, server) = duplex();
+        let target = TargetAddr::Ip("127.0.0.1:18080".parse().unwrap());
+
+        let future = lazy(|| {
+                              socks5_connect(client, &target, None).join(proxy(server, None, 0x05))
+                          });
+
+        let error = future.wait().err().unwrap();
+        assert_eq!(error.to_string(), "SOCKS5: connection refused");
+    }
+
+    #[test]
+    fn hostnames_have_their_own_keys() {
+        let first = TargetAddr::Domain("first.onion".to_string(), 18080);
+        let second = TargetAddr::Domain("second.onion".to_string(), 18080);
+
+        assert_eq!(first.key(), first.clone().key());
+        assert_ne!(first.key(), second.key());
+        assert_eq!(first.key().port(), 18080);
+
+        let ip: SocketAddr = "127.0.0.1:18080".parse().unwrap();
+        assert_eq!(TargetAddr::Ip(ip).key(), ip);
+    }
+}
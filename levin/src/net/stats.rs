@@ -0,0 +1,123 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use bucket::{Bucket, BUCKET_HEAD_LENGTH};
+use command::Id;
+use net::capture::Flow;
+
+/// The traffic of a peer, a command or of all the connections. The bytes
+/// are the ones of the buckets, head and body.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    /// Received bytes.
+    pub bytes_received: u64,
+    /// Sent bytes.
+    pub bytes_sent: u64,
+    /// Received buckets.
+    pub messages_received: u64,
+    /// Sent buckets.
+    pub messages_sent: u64,
+}
+
+impl Traffic {
+    fn add(&mut self, flow: Flow, bytes: u64) {
+        match flow {
+            Flow::Received => {
+                self.bytes_received += bytes;
+                self.messages_received += 1;
+            }
+            Flow::Sent => {
+                self.bytes_sent += bytes;
+                self.messages_sent += 1;
+            }
+        }
+    }
+}
+
+/// A reference to `TrafficStats`.
+pub type TrafficStatsRef = Arc<TrafficStats>;
+
+/// Counts the traffic of the connections per command and per peer, the
+/// counters of a peer are dropped once its connection is closed.
+#[derive(Debug, Default)]
+pub struct TrafficStats {
+    counters: Mutex<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    total: Traffic,
+    commands: HashMap<Id, Traffic>,
+    peers: HashMap<SocketAddr, Traffic>,
+}
+
+impl TrafficStats {
+    /// Creates empty counters.
+    pub fn new() -> TrafficStats {
+        TrafficStats::default()
+    }
+
+    /// Counts a bucket received from or sent to a peer.
+    pub fn record(&self, flow: Flow, addr: &SocketAddr, bucket: &Bucket) {
+        let bytes = (BUCKET_HEAD_LENGTH + bucket.body.len()) as u64;
+
+        let mut counters = self.counters.lock();
+        counters.total.add(flow, bytes);
+        counters
+            .commands
+            .entry(bucket.head.command)
+            .or_default()
+            .add(flow, bytes);
+        counters
+            .peers
+            .entry(*addr)
+            .or_default()
+            .add(flow, bytes);
+    }
+
+    /// The traffic of all the connections.
+    pub fn total(&self) -> Traffic {
+        self.counters.lock().total
+    }
+
+    /// The traffic of a command.
+    pub fn command(&self, id: Id) -> Traffic {
+        self.counters
+            .lock()
+            .commands
+            .get(&id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The traffic of every command seen.
+    pub fn commands(&self) -> HashMap<Id, Traffic> {
+        self.counters.lock().commands.clone()
+    }
+
+    /// The traffic of a connected peer.
+    pub fn peer(&self, addr: &SocketAddr) -> Option<Traffic> {
+        self.counters.lock().peers.get(addr).cloned()
+    }
+
+    /// The traffic of every connected peer.
+    pub fn peers(&self) -> HashMap<SocketAddr, Traffic> {
+        self.counters.lock().peers.clone()
+    }
+
+    /// Drops the counters of a peer, returns them if there were any.
+    pub fn remove_peer(&self, addr: &SocketAddr) -> Option<Traffic> {
+        self.counters.lock().peers.remove(addr)
+    }
+}
@@ -6,204 +6,391 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug, Formatter};
 use std::io;
+use std::marker::PhantomData;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 use futures::{Async, Future, Poll, future};
 use futures::stream::Stream;
+use futures::sync::oneshot;
 use futures::task::{self, Task};
 
 use tokio_core::net::TcpStream;
-use tokio_core::reactor::Handle;
-use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_core::reactor::{Handle, Interval, Remote, Timeout};
 
 use crossbeam::sync::MsQueue;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
-use portable_storage::Section;
+use serde::de::DeserializeOwned;
 
-use bucket::Bucket;
+use portable_storage::{Section, from_section};
+
+use bucket::{Bucket, LEVIN_INITIAL_MAX_PACKET_SIZE};
 use command::{Command, Id};
+use error::InvokeError;
 use net::io::IoHandlerRef;
 use net::handlers::RemoteHandler;
-use net::bucket_stream::bucket_stream;
-use net::bucket_sink::bucket_sink;
+use net::connection::{Connection, ConnectionHandlerRef, Direction};
+use net::socks::{Socks5Proxy, TargetAddr, socks5_connect};
+use net::transport::{BoxedStream, TRANSPORT_TIMEOUT, with_timeout};
 
-/// Connects to a levin server.
+/// Connects to a levin server, the returned future fails only if the
+/// connection can't be made and resolves once it's closed.
 pub fn connect(addr: &SocketAddr,
                handle: &Handle,
                io_handler: IoHandlerRef,
+               connection_handler: ConnectionHandlerRef,
                commands: Commands)
-               -> Box<Future<Item = (), Error = io::Error> + Send + Sync + 'static> {
-    let addr = addr.clone();
-    Box::new(TcpStream::connect(&addr, handle).and_then(move |stream| {
-        let io_handler = io_handler.clone();
-        let commands = commands.clone();
-
-        let (read_half, write_half) = stream.split();
-
-        let buckets = bucket_stream(read_half);
-
-        let commands_ = commands.clone();
-        let receiver = buckets.for_each(move |bucket| {
-            let io_handler = io_handler.clone();
-            let commands = commands_.clone();
-
-            let bucket = match bucket {
-                Ok(b) => b,
-                Err(e) => {
-                    warn!("received invalid bucket: {}", e);
-                    return future::ok::<(), io::Error>(());
-                }
-            };
-
-            let id = bucket.head.command;
-            let section = match bucket.into_section() {
-                Ok(s) => s,
-                Err(e) => {
-                    warn!("received bucket with invalid portable-storage section: {}",
-                          e);
-                    commands.error_response(id, -1);
-                    return future::ok::<(), io::Error>(());
-                }
-            };
-
-            if bucket.head.is_request() {
-                match io_handler.get(id) {
-                    Some(RemoteHandler::Invokation(handler)) => {
-                        let response = handler.call(addr.clone(), section);
-                        match response {
-                            Ok(Some(r)) => commands.invokation_response(id, r),
-                            Ok(None) => { /* do nothing, the command stream is closed */},
-                            Err(e) => commands.error_response(id, e),
-                        }
-                    }
-                    Some(RemoteHandler::Notification(handler)) => handler.call(addr.clone(), section),
-                    None => {
-                        warn!("received bucket with ID #{} but a handler isn't defined.",
-                              id);
-                        commands.error_response(id, -1);
-                        return future::ok::<(), io::Error>(());
-                    }
-                }
-            } else {
-                if let Some((handler_id, handler)) = commands.current_handler() {
-                    if id != handler_id {
-                        warn!("response id #{} doesn't match handler id #{}",
-                              id,
-                              handler_id);
-                        commands.error_response(id, -1);
-                        return future::ok::<(), io::Error>(());
-                    }
-
-                    handler.call(section);
-                } else {
-                    warn!("received response with ID #{}, but no handler is defined.",
-                          id);
-                    commands.error_response(id, -1);
-                    return future::ok::<(), io::Error>(());
-                }
-            }
-
-            future::ok::<(), io::Error>(())
-        });
-
-        let bucket_sender = bucket_sink(write_half);
-
-        let sender = commands.forward(bucket_sender)
-            .map(|(_, sender)| sender.inner().unwrap().shutdown());
+               -> Box<Future<Item = (), Error = io::Error> + Send + 'static> {
+    connect_target(&TargetAddr::Ip(*addr),
+                   handle,
+                   io_handler,
+                   connection_handler,
+                   commands)
+}
 
-        receiver.join(sender).map(|_| ())
+/// Connects to a levin server by its IP address or hostname, like
+/// `connect`. Hostnames are resolved by the proxy so they need one, the
+/// connection is identified by [`TargetAddr::key`][1].
+///
+/// [1]: enum.TargetAddr.html#method.key
+pub fn connect_target(target: &TargetAddr,
+                      handle: &Handle,
+                      io_handler: IoHandlerRef,
+                      connection_handler: ConnectionHandlerRef,
+                      commands: Commands)
+                      -> Box<Future<Item = (), Error = io::Error> + Send + 'static> {
+    let proxy = io_handler.proxy().cloned();
+    if let (&TargetAddr::Domain(..), None) = (target, proxy.as_ref()) {
+        return Box::new(future::err(no_proxy()));
+    }
+    let addr = target.key();
+
+    let target = target.clone();
+    let remote = handle.remote().clone();
+    let transport = io_handler.transport().clone();
+    let stream = dial(target.clone(), proxy.clone(), handle).and_then(move |stream| {
+        let handle = remote.handle().expect("connecting from the reactor thread");
+        let setup = with_timeout(transport.connect(stream),
+                                 Duration::from_secs(TRANSPORT_TIMEOUT),
+                                 &handle);
+        // peers without support for the transport are connected again in plain levin.
+        setup.or_else(move |e| {
+            debug!("couldn't set up the transport with {}, using plain levin: {}", target, e);
+            let handle = remote.handle().expect("connecting from the reactor thread");
+            dial(target, proxy, &handle)
+        })
+    });
+
+    Box::new(stream.and_then(move |stream| {
+        Connection::new(addr, Direction::Outbound, commands)
+            .run(stream, io_handler, connection_handler)
+            .then(|_| Ok(()))
     }))
 }
 
-pub trait InvokationResponseHandler: Send + Sync + 'static {
-    fn call(&self, response: Section);
+/// Opens a TCP connection to `target`, through the proxy if there's one.
+fn dial(target: TargetAddr,
+        proxy: Option<Socks5Proxy>,
+        handle: &Handle)
+        -> Box<Future<Item = BoxedStream, Error = io::Error> + Send> {
+    match (target, proxy) {
+        (target, Some(proxy)) => {
+            Box::new(TcpStream::connect(&proxy.addr, handle)
+                         .and_then(move |stream| {
+                                       socks5_connect(stream, &target, proxy.auth.as_ref())
+                                   })
+                         .map(|stream| -> BoxedStream { Box::new(stream) }))
+        }
+        (TargetAddr::Ip(addr), None) => {
+            Box::new(TcpStream::connect(&addr, handle)
+                         .map(|stream| -> BoxedStream { Box::new(stream) }))
+        }
+        (TargetAddr::Domain(..), None) => Box::new(future::err(no_proxy())),
+    }
+}
+
+fn no_proxy() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput,
+                   "connecting to a hostname needs a proxy")
 }
 
-impl<F> InvokationResponseHandler for F
-    where F: Send + Sync + 'static + Fn(Section)
-{
-    fn call(&self, response: Section) {
-        self(response)
+/// Handles a received bucket, requests are given to the `IoHandler` and
+/// responses complete the invokation waiting for them.
+pub(crate) fn handle_bucket(addr: &SocketAddr,
+                            io_handler: &IoHandlerRef,
+                            commands: &Commands,
+                            bucket: Bucket) {
+    let id = bucket.head.command;
+    if !bucket.head.is_request() {
+        commands.on_response(bucket);
+        return;
     }
+
+    let section = match bucket.into_section() {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("received bucket with invalid portable-storage section: {}",
+                  e);
+            commands.error_response(id, -1);
+            return;
+        }
+    };
+
+    match io_handler.get(id) {
+        Some(RemoteHandler::Invokation(handler)) => {
+            let response = handler.call(addr.clone(), section);
+            match response {
+                Ok(Some(r)) => commands.invokation_response(id, r),
+                Ok(None) => { /* do nothing, the command stream is closed */ }
+                Err(e) => commands.error_response(id, e),
+            }
+        }
+        Some(RemoteHandler::Notification(handler)) => handler.call(addr.clone(), section),
+        None => {
+            warn!("received bucket with ID #{} but a handler isn't defined.",
+                  id);
+            commands.error_response(id, -1);
+        }
+    }
+}
+
+/// An invokation waiting for its response.
+struct Pending {
+    sequence: usize,
+    /// `None` once the invokation timed out, the response is discarded.
+    sender: Option<oneshot::Sender<Result<Section, InvokeError>>>,
 }
 
+/// The invokations waiting for a response, levin responses don't have an
+/// identifier besides the command id so they are answered in order.
+type PendingInvokations = Arc<Mutex<HashMap<Id, VecDeque<Pending>>>>;
+
 /// A command queue stream.
 #[allow(missing_debug_implementations)]
 #[derive(Clone)]
 pub struct Commands {
     queue: Arc<MsQueue<Bucket>>,
-    handler_queue: Arc<MsQueue<(Id, Arc<InvokationResponseHandler>)>>,
+    /// Keeps the fragments of a message together in the queue.
+    push_lock: Arc<Mutex<()>>,
+    pending: PendingInvokations,
+    sequence: Arc<AtomicUsize>,
+    remote: Remote,
     task: Arc<RwLock<Option<Task>>>,
     shutdown: Arc<RwLock<bool>>,
+    max_packet_size: Arc<RwLock<u64>>,
 }
 
 impl Commands {
-    /// Creates an empty `Commands` strucutre.
-    pub fn new() -> Commands {
+    /// Creates an empty `Commands` strucutre, the invokation deadlines run
+    /// on the given event loop.
+    pub fn new(remote: Remote) -> Commands {
         Commands {
             queue: Arc::new(MsQueue::new()),
-            handler_queue: Arc::new(MsQueue::new()),
+            push_lock: Arc::new(Mutex::new(())),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            sequence: Arc::new(AtomicUsize::new(0)),
+            remote,
             task: Arc::new(RwLock::new(None)),
             shutdown: Arc::new(RwLock::new(false)),
+            max_packet_size: Arc::new(RwLock::new(LEVIN_INITIAL_MAX_PACKET_SIZE)),
         }
     }
 
-    /// Adds an invokation to the queue.
-    pub fn invoke<C, F>(&self, request: Section, command: F)
+    /// Adds an invokation to the queue, the returned future resolves to the
+    /// response or fails if it isn't received before `timeout`.
+    pub fn invoke<C>(&self, request: Section, timeout: Duration) -> Invokation<C::Response>
         where C: Command,
-              F: InvokationResponseHandler + 'static
+              C::Response: DeserializeOwned
     {
-        let bucket = Bucket::invokation(C::ID, request);
+        let (sender, receiver) = oneshot::channel();
+        let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
 
-        self.queue.push(bucket);
-        self.handler_queue.push((C::ID, Arc::new(command)));
-        if let Some(ref task) = *self.task.read() {
-            task.notify()
+        if *self.shutdown.read() {
+            return Invokation::new(receiver);
         }
+
+        self.pending
+            .lock()
+            .entry(C::ID)
+            .or_default()
+            .push_back(Pending {
+                           sequence,
+                           sender: Some(sender),
+                       });
+
+        let pending = self.pending.clone();
+        self.remote
+            .spawn(move |handle| {
+                future::result(Timeout::new(timeout, handle))
+                    .flatten()
+                    .then(move |_| {
+                              expire(&pending, C::ID, sequence);
+                              Ok(())
+                          })
+            });
+
+        self.push(Bucket::invokation(C::ID, request));
+        Invokation::new(receiver)
     }
 
     /// Adds a notification to the queue.
     pub fn notify<C>(&self, request: Section)
         where C: Command
     {
-        let bucket = Bucket::notification(C::ID, request);
+        self.push(Bucket::notification(C::ID, request));
+    }
 
-        self.queue.push(bucket);
-        if let Some(ref task) = *self.task.read() {
-            task.notify()
-        }
+    /// Adds a notification to the queue, split in fragments with a body of
+    /// `fragment_size` bytes.
+    pub fn notify_fragmented<C>(&self, request: Section, fragment_size: usize)
+        where C: Command
+    {
+        self.push_all(Bucket::notification(C::ID, request).fragment(fragment_size));
+    }
+
+    /// Adds a noise bucket with `size` bytes of padding to the queue.
+    pub fn noise(&self, size: usize) {
+        self.push(Bucket::noise(size));
+    }
+
+    /// Adds a noise bucket with `size` bytes of padding to the queue every
+    /// `period` until the connection is closed.
+    pub fn send_noise(&self, size: usize, period: Duration) {
+        let commands = self.clone();
+        self.remote
+            .spawn(move |handle| {
+                future::result(Interval::new(period, handle))
+                    .and_then(move |interval| {
+                        let running = commands.clone();
+                        interval
+                            .take_while(move |_| Ok(!*running.shutdown.read()))
+                            .for_each(move |_| {
+                                          commands.noise(size);
+                                          Ok(())
+                                      })
+                    })
+                    .map_err(|e| warn!("noise timer error: {}", e))
+            });
     }
 
     /// Adds an invokation response to the queue.
     pub fn invokation_response(&self, id: Id, response: Section) {
-        let bucket = Bucket::invokation_response(id, response);
-
-        self.queue.push(bucket);
-        if let Some(ref task) = *self.task.read() {
-            task.notify()
-        }
+        self.push(Bucket::invokation_response(id, response));
     }
 
     /// Adds an error response bucket to the queue.
     pub fn error_response(&self, id: Id, return_code: i32) {
-        let bucket = Bucket::error_response(id, return_code);
+        self.push(Bucket::error_response(id, return_code));
+    }
+
+    fn push(&self, bucket: Bucket) {
+        self.push_all(Some(bucket));
+    }
+
+    /// Adds the buckets to the queue one after the other.
+    fn push_all<I>(&self, buckets: I)
+        where I: IntoIterator<Item = Bucket>
+    {
+        if *self.shutdown.read() {
+            debug!("discarding buckets, the connection is closed");
+            return;
+        }
+
+        {
+            let _lock = self.push_lock.lock();
+            for bucket in buckets {
+                self.queue.push(bucket);
+            }
+        }
 
-        self.queue.push(bucket);
         if let Some(ref task) = *self.task.read() {
             task.notify()
         }
     }
 
-    pub(crate) fn current_handler(&self) -> Option<(Id, Arc<InvokationResponseHandler>)> {
-        self.handler_queue.try_pop()
+    /// Completes the oldest invokation of the response command.
+    fn on_response(&self, bucket: Bucket) {
+        let id = bucket.head.command;
+        let pending = self.pending
+            .lock()
+            .get_mut(&id)
+            .and_then(|queue| queue.pop_front());
+
+        let sender = match pending {
+            Some(Pending { sender: Some(sender), .. }) => sender,
+            Some(Pending { sender: None, .. }) => {
+                debug!("discarding late response with ID #{}", id);
+                return;
+            }
+            None => {
+                warn!("received response with ID #{}, but no invokation is waiting for it.",
+                      id);
+                return;
+            }
+        };
+
+        let result = if bucket.head.return_code < 0 {
+            Err(InvokeError::ReturnCode(bucket.head.return_code))
+        } else {
+            bucket
+                .into_section()
+                .map_err(|e| InvokeError::InvalidResponse(e.to_string()))
+        };
+
+        // The invokation future may have been dropped.
+        let _ = sender.send(result);
+    }
+
+    /// The maximum body size of the received buckets, it starts at
+    /// [`LEVIN_INITIAL_MAX_PACKET_SIZE`][1] until the handshake is done.
+    ///
+    /// [1]: ../bucket/constant.LEVIN_INITIAL_MAX_PACKET_SIZE.html
+    pub fn max_packet_size(&self) -> u64 {
+        *self.max_packet_size.read()
+    }
+
+    /// Changes the maximum body size of the received buckets.
+    pub fn set_max_packet_size(&self, size: u64) {
+        *self.max_packet_size.write() = size;
+    }
+
+    /// Returns `true` if both are the command queue of the same connection.
+    pub fn ptr_eq(&self, other: &Commands) -> bool {
+        Arc::ptr_eq(&self.queue, &other.queue)
+    }
+
+    /// The event loop of the invokation deadlines.
+    pub(crate) fn remote(&self) -> &Remote {
+        &self.remote
     }
 
-    /// Stop streaming commands.
+    /// Stop streaming commands once the queued ones are sent, the pending
+    /// invokations fail.
     pub fn shutdown(&self) {
         *self.shutdown.write() = true;
+        self.pending.lock().clear();
+        if let Some(ref task) = *self.task.read() {
+            task.notify()
+        }
+    }
+}
+
+/// Fails an invokation that didn't get a response in time.
+fn expire(pending: &PendingInvokations, id: Id, sequence: usize) {
+    let sender = pending
+        .lock()
+        .get_mut(&id)
+        .and_then(|queue| queue.iter_mut().find(|pending| pending.sequence == sequence))
+        .and_then(|pending| pending.sender.take());
+
+    if let Some(sender) = sender {
+        let _ = sender.send(Err(InvokeError::Timeout));
     }
 }
 
@@ -212,21 +399,166 @@ impl Stream for Commands {
     type Error = io::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        let shutdown = *self.shutdown.read();
+        // The buckets queued before the shutdown are still sent.
         if let Some(bucket) = self.queue.try_pop() {
-            if shutdown {
-                Ok(Async::Ready(None))
-            } else {
-                *self.task.write() = None;
-                Ok(Async::Ready(Some(bucket)))
-            }
+            *self.task.write() = None;
+            Ok(Async::Ready(Some(bucket)))
+        } else if *self.shutdown.read() {
+            Ok(Async::Ready(None))
         } else {
-            if shutdown {
-                Ok(Async::Ready(None))
-            } else {
-                *self.task.write() = Some(task::current());
-                Ok(Async::NotReady)
+            *self.task.write() = Some(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// A future resolving to the response of an invokation.
+pub struct Invokation<T> {
+    receiver: oneshot::Receiver<Result<Section, InvokeError>>,
+    response: PhantomData<fn() -> T>,
+}
+
+impl<T> Invokation<T> {
+    fn new(receiver: oneshot::Receiver<Result<Section, InvokeError>>)
+           -> Invokation<T> {
+        Invokation {
+            receiver,
+            response: PhantomData,
+        }
+    }
+}
+
+impl<T> Debug for Invokation<T> {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.debug_struct("Invokation").finish()
+    }
+}
+
+impl<T> Future for Invokation<T>
+    where T: DeserializeOwned
+{
+    type Item = T;
+    type Error = InvokeError;
+
+    fn poll(&mut self) -> Poll<T, InvokeError> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(Ok(section))) => {
+                from_section(section)
+                    .map(Async::Ready)
+                    .map_err(|e| InvokeError::InvalidResponse(e.to_string()))
             }
+            Ok(Async::Ready(Err(e))) => Err(e),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(oneshot::Canceled) => Err(InvokeError::Closed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_core::reactor::Core;
+
+    use portable_storage::to_section;
+
+    use command::COMMAND_BASE_ID;
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Pong {
+        value: u64,
+    }
+
+    struct First;
+
+    impl Command for First {
+        const ID: Id = COMMAND_BASE_ID + 1;
+
+        type Response = Pong;
+    }
+
+    struct Second;
+
+    impl Command for Second {
+        const ID: Id = COMMAND_BASE_ID + 2;
+
+        type Response = Pong;
+    }
+
+    fn response(id: Id, value: u64) -> Bucket {
+        Bucket::invokation_response(id, to_section(&Pong { value }).unwrap())
+    }
+
+    #[test]
+    fn responses_are_matched_by_command() {
+        let mut core = Core::new().unwrap();
+        let commands = Commands::new(core.remote());
+        let timeout = Duration::from_secs(10);
+
+        let first = commands.invoke::<First>(Section::new(), timeout);
+        let second = commands.invoke::<Second>(Section::new(), timeout);
+        commands.on_response(response(Second::ID, 2));
+        commands.on_response(response(First::ID, 1));
+
+        assert_eq!(core.run(first).unwrap(), Pong { value: 1 });
+        assert_eq!(core.run(second).unwrap(), Pong { value: 2 });
+    }
+
+    #[test]
+    fn error_response_fails_the_invokation() {
+        let mut core = Core::new().unwrap();
+        let commands = Commands::new(core.remote());
+
+        let first = commands.invoke::<First>(Section::new(), Duration::from_secs(10));
+        commands.on_response(Bucket::error_response(First::ID, -3));
+
+        match core.run(first) {
+            Err(InvokeError::ReturnCode(-3)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn late_response_is_discarded() {
+        let mut core = Core::new().unwrap();
+        let commands = Commands::new(core.remote());
+
+        let expired = commands.invoke::<First>(Section::new(), Duration::from_millis(10));
+        match core.run(expired) {
+            Err(InvokeError::Timeout) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let next = commands.invoke::<First>(Section::new(), Duration::from_secs(10));
+        commands.on_response(response(First::ID, 1));
+        commands.on_response(response(First::ID, 2));
+        assert_eq!(core.run(next).unwrap(), Pong { value: 2 });
+    }
+
+    #[test]
+    fn fragments_are_queued_together() {
+        let core = Core::new().unwrap();
+        let commands = Commands::new(core.remote());
+
+        commands.notify_fragmented::<First>(Section::new(), 32);
+        commands.shutdown();
+
+        let buckets = commands.collect().wait().unwrap();
+        assert_eq!(buckets.len(), 2);
+        assert!(buckets[0].head.is_fragment_start());
+        assert!(buckets[1].head.is_fragment_end());
+    }
+
+    #[test]
+    fn shutdown_closes_the_invokations() {
+        let mut core = Core::new().unwrap();
+        let commands = Commands::new(core.remote());
+
+        let first = commands.invoke::<First>(Section::new(), Duration::from_secs(10));
+        commands.shutdown();
+
+        match core.run(first) {
+            Err(InvokeError::Closed) => {}
+            result => panic!("unexpected result {:?}", result),
         }
     }
 }
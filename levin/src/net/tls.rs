@@ -0,0 +1,378 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use futures::{Async, Future, Poll, future};
+use futures::future::Either;
+
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::read_exact;
+
+use rcgen::generate_simple_self_signed;
+use rustls::{Certificate, ClientConfig, ClientSession, NoClientAuth, PrivateKey,
+             RootCertStore, ServerCertVerified, ServerCertVerifier, ServerConfig,
+             ServerSession, Session, TLSError};
+use webpki::DNSNameRef;
+
+use net::transport::{BoxedStream, Transport, TransportFuture};
+
+/// The name of the ephemeral certificates.
+const TLS_SERVER_NAME: &str = "xmr-levin";
+
+/// The first byte of a TLS handshake record, levin buckets start with
+/// `0x01`.
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+/// Encrypts the connections with TLS.
+///
+/// The certificates are self-signed and generated when the transport is
+/// created, they aren't verified so this only protects the traffic from
+/// passive observers. Peers that don't start a TLS handshake are served
+/// in plain levin.
+pub struct TlsTransport {
+    client: Arc<ClientConfig>,
+    server: Arc<ServerConfig>,
+}
+
+impl TlsTransport {
+    /// Creates a transport with a new ephemeral certificate.
+    pub fn new() -> io::Result<TlsTransport> {
+        let cert = generate_simple_self_signed(vec![TLS_SERVER_NAME.to_string()])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let der = cert.serialize_der()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let key = PrivateKey(cert.serialize_private_key_der());
+
+        let mut server = ServerConfig::new(NoClientAuth::new());
+        server
+            .set_single_cert(vec![Certificate(der)], key)
+            .map_err(tls_error)?;
+
+        let mut client = ClientConfig::new();
+        client
+            .dangerous()
+            .set_certificate_verifier(Arc::new(AnyCertificate));
+
+        Ok(TlsTransport {
+               client: Arc::new(client),
+               server: Arc::new(server),
+           })
+    }
+}
+
+impl Debug for TlsTransport {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.debug_struct("TlsTransport").finish()
+    }
+}
+
+impl Transport for TlsTransport {
+    fn connect(&self, stream: BoxedStream) -> TransportFuture {
+        let name = DNSNameRef::try_from_ascii_str(TLS_SERVER_NAME).expect("valid name");
+        let session = ClientSession::new(&self.client, name);
+        Box::new(Handshake { stream: Some(TlsStream::new(stream, session)) })
+    }
+
+    fn accept(&self, stream: BoxedStream) -> TransportFuture {
+        let config = self.server.clone();
+        Box::new(read_exact(stream, [0u8; 1]).and_then(move |(stream, first)| {
+            let stream = Prefixed {
+                prefix: Some(first[0]),
+                io: stream,
+            };
+
+            if first[0] == TLS_HANDSHAKE_RECORD {
+                let session = ServerSession::new(&config);
+                let stream = TlsStream::new(Box::new(stream), session);
+                Either::A(Handshake { stream: Some(stream) })
+            } else {
+                Either::B(future::ok::<BoxedStream, _>(Box::new(stream)))
+            }
+        }))
+    }
+}
+
+/// Accepts any server certificate, they're ephemeral.
+struct AnyCertificate;
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(&self,
+                          _roots: &RootCertStore,
+                          _presented_certs: &[Certificate],
+                          _dns_name: DNSNameRef,
+                          _ocsp_response: &[u8])
+                          -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn tls_error(e: TLSError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Turns a `WouldBlock` error into `Async::NotReady`.
+fn nonblocking<T>(result: io::Result<T>) -> Poll<T, io::Error> {
+    match result {
+        Ok(t) => Ok(Async::Ready(t)),
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+        Err(e) => Err(e),
+    }
+}
+
+/// A stream that gives back a byte that was already read from it.
+struct Prefixed {
+    prefix: Option<u8>,
+    io: BoxedStream,
+}
+
+impl Read for Prefixed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.prefix.take() {
+            Some(byte) if !buf.is_empty() => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            prefix => {
+                self.prefix = prefix;
+                self.io.read(buf)
+            }
+        }
+    }
+}
+
+impl Write for Prefixed {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl AsyncRead for Prefixed {}
+
+impl AsyncWrite for Prefixed {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+/// A stream encrypted by a TLS session.
+struct TlsStream<S> {
+    io: BoxedStream,
+    session: S,
+    eof: bool,
+    closing: bool,
+}
+
+impl<S> TlsStream<S>
+    where S: Session
+{
+    fn new(io: BoxedStream, session: S) -> TlsStream<S> {
+        TlsStream {
+            io,
+            session,
+            eof: false,
+            closing: false,
+        }
+    }
+
+    /// Writes the pending TLS records.
+    fn write_tls(&mut self) -> io::Result<()> {
+        while self.session.wants_write() {
+            self.session.write_tls(&mut self.io)?;
+        }
+        Ok(())
+    }
+
+    /// Reads TLS records, returns `false` if the stream ended.
+    fn read_tls(&mut self) -> io::Result<bool> {
+        if self.session.read_tls(&mut self.io)? == 0 {
+            return Ok(false);
+        }
+
+        if let Err(e) = self.session.process_new_packets() {
+            // tell the peer what went wrong.
+            let _ = self.write_tls();
+            return Err(tls_error(e));
+        }
+
+        Ok(true)
+    }
+}
+
+impl<S> Read for TlsStream<S>
+    where S: Session
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.session.read(buf)?;
+            if read != 0 || self.eof || buf.is_empty() {
+                return Ok(read);
+            }
+
+            // the records are also written by the writes, so blocking here
+            // isn't an error.
+            nonblocking(self.write_tls())?;
+
+            self.eof = !self.read_tls()?;
+        }
+    }
+}
+
+impl<S> Write for TlsStream<S>
+    where S: Session
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_tls()?;
+
+        let written = self.session.write(buf)?;
+        // the rest is written when flushing.
+        nonblocking(self.write_tls())?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session.flush()?;
+        self.write_tls()?;
+        self.io.flush()
+    }
+}
+
+impl<S> AsyncRead for TlsStream<S> where S: Session {}
+
+impl<S> AsyncWrite for TlsStream<S>
+    where S: Session
+{
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        if !self.closing {
+            self.session.send_close_notify();
+            self.closing = true;
+        }
+
+        try_ready!(nonblocking(self.write_tls()));
+        self.io.shutdown()
+    }
+}
+
+/// A future that completes the TLS handshake.
+struct Handshake<S> {
+    stream: Option<TlsStream<S>>,
+}
+
+impl<S> Future for Handshake<S>
+    where S: Session + Send + 'static
+{
+    type Item = BoxedStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let stream = self.stream.as_mut().expect("polled after completion");
+            loop {
+                try_ready!(nonblocking(stream.write_tls()));
+                if !stream.session.is_handshaking() {
+                    break;
+                }
+
+                if !try_ready!(nonblocking(stream.read_tls())) {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                              "the peer closed the TLS handshake"));
+                }
+            }
+        }
+
+        Ok(Async::Ready(Box::new(self.stream.take().expect("polled after completion"))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::future::lazy;
+
+    use tokio_core::reactor::Core;
+    use tokio_io::io::{read_exact, read_to_end, write_all};
+
+    use net::transport::{duplex, with_timeout};
+    use super::*;
+
+    #[test]
+    fn tls_connections_are_encrypted() {
+        let transport = TlsTransport::new().unwrap();
+        let (a, b) = duplex();
+
+        let future = lazy(|| {
+            transport
+                .connect(Box::new(a))
+                .join(transport.accept(Box::new(b)))
+                .and_then(|(client, server)| {
+                              write_all(client, b"levin".to_vec())
+                                  .join(read_exact(server, [0u8; 5]))
+                          })
+        });
+
+        let (_, (_, received)) = future.wait().unwrap();
+        assert_eq!(&received, b"levin");
+    }
+
+    #[test]
+    fn plain_peers_are_accepted() {
+        let transport = TlsTransport::new().unwrap();
+        let (a, b) = duplex();
+
+        let future = lazy(|| {
+            write_all(a, vec![0x01, 0x21, 0x01, 0x01])
+                .and_then(|(a, _)| {
+                              drop(a);
+                              transport.accept(Box::new(b))
+                          })
+                .and_then(|server| read_to_end(server, Vec::new()))
+        });
+
+        let (_, received) = future.wait().unwrap();
+        assert_eq!(received, vec![0x01, 0x21, 0x01, 0x01]);
+    }
+
+    #[test]
+    fn handshake_with_plain_peer_fails() {
+        let transport = TlsTransport::new().unwrap();
+        let (a, b) = duplex();
+
+        // the plain peer drops the connection after reading the client hello.
+        let future = lazy(|| {
+            transport
+                .connect(Box::new(a))
+                .join(read_exact(b, [0u8; 1]).map(drop))
+        });
+
+        assert!(future.wait().is_err());
+    }
+
+    #[test]
+    fn idle_peer_times_out() {
+        let mut core = Core::new().unwrap();
+        let transport = TlsTransport::new().unwrap();
+        let (_a, b) = duplex();
+
+        // the peer never sends its first byte.
+        let setup = with_timeout(transport.accept(Box::new(b)),
+                                 Duration::from_millis(10),
+                                 &core.handle());
+        match core.run(setup) {
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("idle peer accepted"),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{Async, Future, Poll, future};
+use futures::future::Either;
+use futures::task::{self, Task};
+
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use parking_lot::Mutex;
+
+/// A byte stream a connection can run over.
+pub trait NetStream: AsyncRead + AsyncWrite + Send {}
+
+impl<S> NetStream for S where S: AsyncRead + AsyncWrite + Send {}
+
+/// A boxed `NetStream`.
+pub type BoxedStream = Box<NetStream>;
+
+/// A future that sets up a transport over a stream.
+pub type TransportFuture = Box<Future<Item = BoxedStream, Error = io::Error> + Send>;
+
+/// A reference to a `Transport`.
+pub type TransportRef = Arc<Transport>;
+
+/// Wraps the streams of the connections before levin runs over them,
+/// e.g. to encrypt them.
+pub trait Transport: Send + Sync + 'static {
+    /// Sets up the transport on a connection we opened. If it fails the
+    /// connection is opened again in plain levin.
+    fn connect(&self, stream: BoxedStream) -> TransportFuture;
+
+    /// Sets up the transport on a connection a peer opened.
+    fn accept(&self, stream: BoxedStream) -> TransportFuture;
+}
+
+/// Seconds a transport has to be set up in, the same as the levin
+/// handshake.
+pub(crate) const TRANSPORT_TIMEOUT: u64 = 5;
+
+/// Fails `setup` with `TimedOut` if it doesn't complete before `timeout`.
+pub(crate) fn with_timeout(setup: TransportFuture,
+                           timeout: Duration,
+                           handle: &Handle)
+                           -> TransportFuture {
+    let timeout = match Timeout::new(timeout, handle) {
+        Ok(timeout) => timeout,
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    Box::new(setup
+                 .select2(timeout)
+                 .then(|result| match result {
+                           Ok(Either::A((stream, _))) => Ok(stream),
+                           Ok(Either::B(((), _))) => {
+                               Err(io::Error::new(io::ErrorKind::TimedOut,
+                                                  "the transport wasn't set up in time"))
+                           }
+                           Err(Either::A((e, _))) |
+                           Err(Either::B((e, _))) => Err(e),
+                       }))
+}
+
+/// The plain levin transport, the streams are used as they are.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlainTransport;
+
+impl Transport for PlainTransport {
+    fn connect(&self, stream: BoxedStream) -> TransportFuture {
+        Box::new(future::ok(stream))
+    }
+
+    fn accept(&self, stream: BoxedStream) -> TransportFuture {
+        Box::new(future::ok(stream))
+    }
+}
+
+/// Creates a pair of connected in-memory streams, what's written to one
+/// can be read from the other.
+pub fn duplex() -> (DuplexStream, DuplexStream) {
+    let a = Arc::new(Mutex::new(Pipe::default()));
+    let b = Arc::new(Mutex::new(Pipe::default()));
+
+    (DuplexStream {
+         read: a.clone(),
+         write: b.clone(),
+     },
+     DuplexStream { read: b, write: a })
+}
+
+#[derive(Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    closed: bool,
+    reader: Option<Task>,
+}
+
+impl Pipe {
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(task) = self.reader.take() {
+            task.notify();
+        }
+    }
+}
+
+/// One end of an in-memory stream created with `duplex`.
+pub struct DuplexStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+impl Debug for DuplexStream {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.debug_struct("DuplexStream")
+            .field("available", &self.read.lock().buf.len())
+            .finish()
+    }
+}
+
+impl Read for DuplexStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.read.lock();
+        if pipe.buf.is_empty() {
+            if pipe.closed {
+                return Ok(0);
+            }
+
+            pipe.reader = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let len = buf.len().min(pipe.buf.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for DuplexStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.write.lock();
+        if pipe.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        pipe.buf.extend(buf);
+        if let Some(task) = pipe.reader.take() {
+            task.notify();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for DuplexStream {}
+
+impl AsyncWrite for DuplexStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.write.lock().close();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        self.write.lock().close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::lazy;
+
+    use tokio_io::io::{read_exact, read_to_end, shutdown, write_all};
+
+    use super::*;
+
+    #[test]
+    fn duplex_streams_are_connected() {
+        let (a, b) = duplex();
+
+        let future = write_all(a, b"levin".to_vec())
+            .and_then(|(a, _)| shutdown(a))
+            .and_then(move |_| read_exact(b, [0u8; 2]))
+            .and_then(|(b, head)| {
+                          assert_eq!(&head, b"le");
+                          read_to_end(b, Vec::new())
+                      });
+
+        let (_, rest) = lazy(|| future).wait().unwrap();
+        assert_eq!(rest, b"vin".to_vec());
+    }
+}
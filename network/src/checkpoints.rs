@@ -0,0 +1,124 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use primitives::H256;
+
+/// Known block ids at given heights, a chain that doesn't have these blocks
+/// at these heights is rejected.
+#[derive(Debug, Default, Clone)]
+pub struct Checkpoints {
+    points: BTreeMap<u64, H256>,
+}
+
+impl Checkpoints {
+    pub fn new() -> Checkpoints {
+        Checkpoints::default()
+    }
+
+    /// Adds a checkpoint, it fails if there's a different one at the same
+    /// height.
+    pub fn insert(&mut self, height: u64, id: H256) -> Result<(), String> {
+        if let Some(existing) = self.points.get(&height) {
+            if *existing != id {
+                return Err(format!("conflicting checkpoints at height {}", height));
+            }
+        }
+
+        self.points.insert(height, id);
+        Ok(())
+    }
+
+    pub fn get(&self, height: u64) -> Option<&H256> {
+        self.points.get(&height)
+    }
+
+    /// Returns `false` if there's a checkpoint at this height with a
+    /// different block id.
+    pub fn check(&self, height: u64, id: &H256) -> bool {
+        self.points.get(&height).map(|point| point == id).unwrap_or(true)
+    }
+
+    pub fn last_height(&self) -> Option<u64> {
+        self.points.keys().next_back().cloned()
+    }
+
+    /// Whether the height is at or below the last checkpoint, the blocks
+    /// there are already known to be valid.
+    pub fn is_in_checkpoint_zone(&self, height: u64) -> bool {
+        self.last_height().map(|last| height <= last).unwrap_or(false)
+    }
+
+    /// Adds the checkpoints of a file, one `height:id` per line. Empty
+    /// lines and lines starting with `#` are ignored.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+
+        self.parse(&contents)
+    }
+
+    pub fn parse(&mut self, s: &str) -> Result<(), String> {
+        for line in s.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, ':');
+            let (height, id) = match (parts.next(), parts.next()) {
+                (Some(height), Some(id)) => (height.trim(), id.trim()),
+                _ => return Err(format!("invalid checkpoint {}", line)),
+            };
+
+            let height = height
+                .parse()
+                .map_err(|_| format!("invalid checkpoint height {}", line))?;
+            self.insert(height, id.parse()?)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    const ID: &str = "418015bb9ae982a1975da7d79277c2705727a56894ba0fb246adaabb1f4632e3";
+
+    #[test]
+    fn parse() {
+        let mut checkpoints = Checkpoints::new();
+        checkpoints
+            .parse(&format!("# comment\n\n10:{}\n 20 : {} \n", ID, ID))
+            .unwrap();
+
+        let id: H256 = ID.parse().unwrap();
+        assert_eq!(checkpoints.get(10), Some(&id));
+        assert_eq!(checkpoints.last_height(), Some(20));
+        assert!(checkpoints.check(10, &id));
+        assert!(!checkpoints.check(10, &H256::new()));
+        assert!(checkpoints.check(15, &H256::new()));
+        assert!(checkpoints.is_in_checkpoint_zone(20));
+        assert!(!checkpoints.is_in_checkpoint_zone(21));
+
+        assert!(checkpoints.parse("10").is_err());
+        assert!(checkpoints.parse("10:1234").is_err());
+        assert!(checkpoints
+                    .parse("10:0000000000000000000000000000000000000000000000000000000000000000")
+                    .is_err());
+    }
+}
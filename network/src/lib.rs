@@ -22,6 +22,10 @@ extern crate xmr_chain as chain;
 extern crate xmr_primitives as primitives;
 extern crate xmr_verification as verification;
 
+mod checkpoints;
+
+pub use checkpoints::Checkpoints;
+
 use chain::transaction::Transaction;
 use chain::{Block, BlockHeader};
 use uuid::Uuid;
@@ -43,6 +47,19 @@ pub const MAINNET_GENESIS_TX: &'static [u8] =
 
 pub const MAINNET_GENESIS_NONCE: u32 = 10000;
 
+pub const MAINNET_CHECKPOINTS: &[(u64, &str)] =
+    &[(0, "418015bb9ae982a1975da7d79277c2705727a56894ba0fb246adaabb1f4632e3"),
+      (1, "771fbcd656ec1464d3a02ead5e18644030007a0fc664c0a964d30922821a8148"),
+      (10, "c0e3b387e47042f72d8ccdca88071ff96bff1ac7cde09ae113dbb7ad3fe92381"),
+      (100, "ac3e11ca545e57c49fca2b4e8c48c03c23be047c43e471e1394528b1f9f80b2d"),
+      (1000, "5acfc45acffd2b2e7345caf42fa02308c5793f15ec33946e969e829f40b03876"),
+      (10000, "c758b7c81f928be3295d45e230646de8b852ec96a821eac3fea4daf3fcac0ca2"),
+      (22231, "7cb10e29d67e1c069e6e11b17d30b809724255fee2f6868dc14cfc6ed44dfb25"),
+      (29556, "53c484a8ed91e4da621bb2fa88106dbde426fe90d7ef07b9c1e5127fb6f3a7f6"),
+      (50000, "0fe8758ab06a8b9cb35b7328fd4f757af530a5d37759f9d3e421023231f7b31c"),
+      (80000, "a62dcd7b536f22e003ebae8726e9e7276f63d594e264b6f0cd7aab27b66e75e3"),
+      (202612, "bbd604d2ba11ba27935e006ed39c9bfdd99b76bf4a50654bc1e1e61217962698")];
+
 pub const TESTNET_GENESIS_TX: &'static [u8] =
     &[0x01, 0x3c, 0x01, 0xff, 0x00, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x03, 0x02, 0x9b,
       0x2e, 0x4c, 0x02, 0x81, 0xc0, 0xb0, 0x2e, 0x7c, 0x53, 0x29, 0x1a, 0x94, 0xd1, 0xd0, 0xcb,
@@ -52,6 +69,10 @@ pub const TESTNET_GENESIS_TX: &'static [u8] =
       0x9d, 0x0b, 0xc8, 0x90, 0xd1];
 pub const TESTNET_GENESIS_NONCE: u32 = 10001;
 
+pub const TESTNET_CHECKPOINTS: &[(u64, &str)] =
+    &[(0, "48ca7cd3c8de5b6a4d53d2861fbdaedca141553559f9be9520068053cda8430b"),
+      (1000000, "46b690b710a07ea051bc4a6b6842ac37be691089c0f7758cfeec4d5fc0b4a258")];
+
 /// The Monero network we are in
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Network {
@@ -170,6 +191,23 @@ impl Network {
         HardForks::from(parameters)
     }
 
+    /// Returns the hard coded checkpoints.
+    pub fn checkpoints(&self) -> Checkpoints {
+        let points = match *self {
+            Network::Mainnet => MAINNET_CHECKPOINTS,
+            Network::Testnet => TESTNET_CHECKPOINTS,
+        };
+
+        let mut checkpoints = Checkpoints::new();
+        for &(height, id) in points {
+            checkpoints
+                .insert(height, id.parse().expect("invalid hard coded checkpoint"))
+                .expect("conflicting hard coded checkpoints");
+        }
+
+        checkpoints
+    }
+
     pub fn genesis_transaction(&self) -> Transaction {
         let tx = match *self {
             Network::Mainnet => Transaction::from_bytes(MAINNET_GENESIS_TX),
@@ -222,13 +260,14 @@ impl HardForks {
     }
 
     pub fn ideal_version_for_height(&self, height: u64) -> u8 {
-        for hard_fork in self.parameters {
+        for hard_fork in self.parameters.iter().rev() {
             if height >= hard_fork.height {
                 return hard_fork.version;
             }
         }
 
-        unreachable!();
+        // the genesis block is before the first fork.
+        self.parameters[0].version
     }
 }
 
@@ -299,4 +338,12 @@ pub mod tests {
         let _tesnet_block = Network::Testnet.genesis_block();
         // TODO: check for testnet genesis block id validity.
     }
+
+    #[test]
+    fn checkpoints_start_at_genesis() {
+        for network in [Network::Mainnet, Network::Testnet].iter() {
+            let checkpoints = network.checkpoints();
+            assert_eq!(checkpoints.get(0), Some(&network.genesis_block().id()));
+        }
+    }
 }
@@ -14,10 +14,14 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::net::SocketAddr;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use levin::net::{RateLimits, Socks5Proxy};
 use network::Network;
 
-use types::PeerId;
+use types::{NetworkAddress, PeerId};
 
 /// P2P configuration.
 #[derive(Debug, Clone)]
@@ -26,8 +30,10 @@ pub struct Config {
     pub threads: usize,
     /// The network id.
     pub network: Network,
-    /// Peers to connect.
-    pub peers: Vec<SocketAddr>,
+    /// Peers to connect, the anonymity network ones need a proxy.
+    pub peers: Vec<NetworkAddress>,
+    /// Addresses the server listens on.
+    pub bind_addrs: Vec<IpAddr>,
     /// Listening port.
     pub listen_port: Option<u16>,
     /// Hide my port.
@@ -38,4 +44,22 @@ pub struct Config {
     pub in_peers: u32,
     /// The peer ID.
     pub peer_id: PeerId,
+    /// How long a misbehaving peer stays banned.
+    pub ban_time: Duration,
+    /// Where the ban list is persisted, if anywhere.
+    pub ban_list: Option<PathBuf>,
+    /// Where the white, gray and anchor peer lists are persisted, if
+    /// anywhere.
+    pub peer_store: Option<PathBuf>,
+    /// Where the levin traffic is recorded, if anywhere.
+    pub capture: Option<PathBuf>,
+    /// Encrypt the connections with TLS when the peers support it, it
+    /// needs the `tls` feature.
+    pub tls: bool,
+    /// The SOCKS5 proxy of the outbound connections, if any.
+    pub proxy: Option<Socks5Proxy>,
+    /// The limits of the traffic of all the connections together.
+    pub rate_limits: RateLimits,
+    /// The limits of the traffic of each connection.
+    pub peer_rate_limits: RateLimits,
 }
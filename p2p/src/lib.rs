@@ -39,14 +39,24 @@ extern crate xmr_portable_storage_utils as portable_storage_utils;
 extern crate xmr_primitives as primitives;
 extern crate xmr_storage as storage;
 
+#[cfg(test)]
+extern crate xmr_chain as chain;
+#[cfg(test)]
+extern crate xmr_keys as keys;
+
 pub mod event_loop;
 pub mod types;
 pub mod p2p;
 pub mod net;
+pub mod probe;
 pub mod protocol;
 pub mod config;
 pub mod utils;
 
+#[cfg(test)]
+mod test_harness;
+
 pub use p2p::P2P;
+pub use probe::Probe;
 pub use event_loop::{event_loop, forever};
 pub use config::Config;
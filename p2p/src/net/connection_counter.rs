@@ -16,16 +16,40 @@
 
 use std::cmp;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 use parking_lot::RwLock;
 
+use net::EvictionCandidate;
+use utils::Subnet;
+
 /// Connection type.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ConnectionType {
     Inbound,
     Outbound,
+    /// An outbound connection that only pings the peer, it doesn't take an
+    /// outbound slot.
+    Ping,
+}
+
+/// An open connection.
+struct Connection {
+    connection_type: ConnectionType,
+    connected_at: Instant,
+    latency: Option<Duration>,
+}
+
+impl Connection {
+    fn new(connection_type: ConnectionType) -> Connection {
+        Connection {
+            connection_type,
+            connected_at: Instant::now(),
+            latency: None,
+        }
+    }
 }
 
 /// Counts number of open inbound and outbound connections.
@@ -38,8 +62,8 @@ pub struct ConnectionCounter {
     max_inbound_connections: u32,
     /// Maximum number of outbound connections.
     max_outbound_connections: u32,
-    /// Connection type.
-    connection_type: RwLock<HashMap<SocketAddr, ConnectionType>>,
+    /// Open connections.
+    connections: RwLock<HashMap<SocketAddr, Connection>>,
 }
 
 impl ConnectionCounter {
@@ -50,7 +74,7 @@ impl ConnectionCounter {
             current_outbound_connections: AtomicUsize::new(0),
             max_inbound_connections: max_inbound_connections,
             max_outbound_connections: max_outbound_connections,
-            connection_type: RwLock::new(HashMap::with_capacity(total_max_connections as _)),
+            connections: RwLock::new(HashMap::with_capacity(total_max_connections as _)),
         }
     }
 
@@ -58,25 +82,32 @@ impl ConnectionCounter {
     pub fn note_new_inbound_connection(&self, addr: SocketAddr) {
         self.current_inbound_connections
             .fetch_add(1, Ordering::AcqRel);
-        self.connection_type
+        self.connections
             .write()
-            .insert(addr, ConnectionType::Inbound);
+            .insert(addr, Connection::new(ConnectionType::Inbound));
     }
 
     /// Increases outbound connections counter by 1.
     pub fn note_new_outbound_connection(&self, addr: SocketAddr) {
         self.current_outbound_connections
             .fetch_add(1, Ordering::AcqRel);
-        self.connection_type
+        self.connections
             .write()
-            .insert(addr, ConnectionType::Outbound);
+            .insert(addr, Connection::new(ConnectionType::Outbound));
+    }
+
+    /// Notes a ping connection, it isn't counted.
+    pub fn note_new_ping_connection(&self, addr: SocketAddr) {
+        self.connections
+            .write()
+            .insert(addr, Connection::new(ConnectionType::Ping));
     }
 
     /// Closes an inbound or outbound connection depending on the
     /// direction of `addr` and decreases their counter by 1 respectively.
     pub fn note_close_connection(&self, addr: &SocketAddr) {
-        if let Some(connection) = self.connection_type.write().remove(addr) {
-            match connection {
+        if let Some(connection) = self.connections.write().remove(addr) {
+            match connection.connection_type {
                 ConnectionType::Outbound => {
                     self.current_outbound_connections
                         .fetch_sub(1, Ordering::AcqRel);
@@ -85,6 +116,7 @@ impl ConnectionCounter {
                     self.current_inbound_connections
                         .fetch_sub(1, Ordering::AcqRel);
                 }
+                ConnectionType::Ping => {}
             }
         }
     }
@@ -117,6 +149,77 @@ impl ConnectionCounter {
 
     /// The type of connection
     pub fn connection_type(&self, addr: &SocketAddr) -> Option<ConnectionType> {
-        self.connection_type.read().get(addr).cloned()
+        self.connections
+            .read()
+            .get(addr)
+            .map(|connection| connection.connection_type.clone())
+    }
+
+    /// Records the time the peer took to answer a request.
+    pub fn note_latency(&self, addr: &SocketAddr, latency: Duration) {
+        if let Some(connection) = self.connections.write().get_mut(addr) {
+            connection.latency = Some(latency);
+        }
+    }
+
+    /// Number of inbound connections from the given address.
+    pub fn inbound_from_ip(&self, ip: &IpAddr) -> usize {
+        self.connections
+            .read()
+            .iter()
+            .filter(|&(addr, connection)| {
+                        connection.connection_type == ConnectionType::Inbound &&
+                        addr.ip() == *ip
+                    })
+            .count()
+    }
+
+    /// Number of inbound connections from the given subnet.
+    pub fn inbound_from_subnet(&self, subnet: &Subnet) -> usize {
+        self.connections
+            .read()
+            .iter()
+            .filter(|&(addr, connection)| {
+                        connection.connection_type == ConnectionType::Inbound &&
+                        subnet.contains(&addr.ip())
+                    })
+            .count()
+    }
+
+    /// The inbound connections, to choose one to evict.
+    pub fn eviction_candidates(&self) -> Vec<EvictionCandidate> {
+        self.connections
+            .read()
+            .iter()
+            .filter(|&(_, connection)| connection.connection_type == ConnectionType::Inbound)
+            .map(|(addr, connection)| {
+                     EvictionCandidate {
+                         addr: *addr,
+                         connected_at: connection.connected_at,
+                         latency: connection.latency,
+                     }
+                 })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn pings_arent_counted() {
+        let counter = ConnectionCounter::new(1, 1);
+        let (ping, outbound) = ("127.0.0.1:18080".parse().unwrap(),
+                                "127.0.0.2:18080".parse().unwrap());
+
+        counter.note_new_ping_connection(ping);
+        assert_eq!(counter.outbound_connections_needed(), 1);
+        assert_eq!(counter.connection_type(&ping), Some(ConnectionType::Ping));
+
+        counter.note_new_outbound_connection(outbound);
+        counter.note_close_connection(&ping);
+        assert_eq!(counter.outbound_connections(), (1, 1));
+        assert_eq!(counter.connection_type(&ping), None);
     }
 }
@@ -0,0 +1,149 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use utils::Subnet;
+
+/// Number of peers with the lowest latency protected from eviction.
+pub const PROTECTED_BY_LATENCY: usize = 4;
+/// Number of peers of distinct subnets protected from eviction.
+pub const PROTECTED_BY_SUBNET: usize = 4;
+
+/// An inbound peer that could be evicted to make room for a new one.
+#[derive(Debug, Clone)]
+pub struct EvictionCandidate {
+    pub addr: SocketAddr,
+    pub connected_at: Instant,
+    pub latency: Option<Duration>,
+}
+
+/// Chooses the inbound peer to disconnect when all the inbound slots are
+/// taken.
+///
+/// The peers with the lowest latency, a few peers of distinct subnets and
+/// the older half of the rest are protected. The newest peer of the subnet
+/// with most of the remaining peers is chosen, so a single network can't
+/// push out everyone else.
+pub fn select_peer_to_evict(mut candidates: Vec<EvictionCandidate>) -> Option<SocketAddr> {
+    // Lowest latency, peers we didn't measure go last.
+    candidates.sort_by_key(|candidate| {
+                               (candidate.latency.is_none(), candidate.latency, candidate.addr)
+                           });
+    let measured = candidates
+        .iter()
+        .take(PROTECTED_BY_LATENCY)
+        .filter(|candidate| candidate.latency.is_some())
+        .count();
+    candidates.drain(..measured);
+
+    // One peer of each of the least represented subnets, the oldest one.
+    let mut subnet_sizes = HashMap::new();
+    for candidate in candidates.iter() {
+        *subnet_sizes.entry(Subnet::of(candidate.addr.ip())).or_insert(0usize) += 1;
+    }
+    let mut subnets: Vec<(Subnet, usize)> = subnet_sizes.into_iter().collect();
+    subnets.sort_by_key(|&(subnet, size)| (size, subnet.to_string()));
+    for &(subnet, _) in subnets.iter().take(PROTECTED_BY_SUBNET) {
+        let oldest = candidates
+            .iter()
+            .enumerate()
+            .filter(|&(_, candidate)| subnet.contains(&candidate.addr.ip()))
+            .min_by_key(|&(_, candidate)| candidate.connected_at)
+            .map(|(i, _)| i);
+        if let Some(i) = oldest {
+            candidates.remove(i);
+        }
+    }
+
+    // The longest connected half.
+    candidates.sort_by_key(|candidate| candidate.connected_at);
+    let protected = candidates.len() / 2;
+    candidates.drain(..protected);
+
+    let mut subnet_peers: HashMap<Subnet, Vec<&EvictionCandidate>> = HashMap::new();
+    for candidate in candidates.iter() {
+        subnet_peers
+            .entry(Subnet::of(candidate.addr.ip()))
+            .or_default()
+            .push(candidate);
+    }
+
+    subnet_peers
+        .into_iter()
+        .max_by_key(|&(subnet, ref peers)| (peers.len(), subnet.to_string()))
+        .and_then(|(_, peers)| {
+                      peers
+                          .into_iter()
+                          .max_by_key(|candidate| candidate.connected_at)
+                          .map(|candidate| candidate.addr)
+                  })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn candidate(addr: &str, age: u64, latency: Option<u64>, now: Instant) -> EvictionCandidate {
+        EvictionCandidate {
+            addr: addr.parse().unwrap(),
+            connected_at: now - Duration::from_secs(age),
+            latency: latency.map(Duration::from_millis),
+        }
+    }
+
+    #[test]
+    fn evicts_newest_peer_of_crowded_subnet() {
+        let now = Instant::now() + Duration::from_secs(1000);
+        let mut candidates = Vec::new();
+        for i in 0..8 {
+            let addr = format!("10.0.0.{}:18080", i);
+            candidates.push(candidate(&addr, 100 + i, None, now));
+        }
+        candidates.push(candidate("10.0.0.100:18080", 10, None, now));
+        candidates.push(candidate("192.168.0.1:18080", 5, None, now));
+
+        assert_eq!(select_peer_to_evict(candidates),
+                   Some("10.0.0.100:18080".parse().unwrap()));
+    }
+
+    #[test]
+    fn low_latency_peers_are_protected() {
+        let now = Instant::now() + Duration::from_secs(1000);
+        let mut candidates = Vec::new();
+        for i in 0..8 {
+            let addr = format!("10.0.0.{}:18080", i);
+            candidates.push(candidate(&addr, 100 + i, None, now));
+        }
+        candidates.push(candidate("10.0.0.100:18080", 10, Some(5), now));
+
+        let evicted = select_peer_to_evict(candidates.clone());
+        assert!(evicted.is_some());
+        assert_ne!(evicted, Some("10.0.0.100:18080".parse().unwrap()));
+    }
+
+    #[test]
+    fn few_peers_arent_evicted() {
+        let now = Instant::now() + Duration::from_secs(1000);
+        let candidates = vec![candidate("10.0.0.1:18080", 10, Some(5), now),
+                              candidate("10.0.1.1:18080", 10, None, now)];
+
+        assert_eq!(select_peer_to_evict(candidates), None);
+        assert_eq!(select_peer_to_evict(Vec::new()), None);
+    }
+}
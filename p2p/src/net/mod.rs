@@ -15,7 +15,10 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 mod connection_counter;
+mod eviction;
 mod peer_context;
 
 pub use self::connection_counter::{ConnectionType, ConnectionCounter};
+pub use self::eviction::{PROTECTED_BY_LATENCY, PROTECTED_BY_SUBNET, EvictionCandidate,
+                         select_peer_to_evict};
 pub use self::peer_context::PeerContext;
@@ -20,6 +20,8 @@ use std::net::SocketAddr;
 use levin::Command;
 use portable_storage::Section;
 
+use net::ConnectionType;
+use protocol::Misbehaviour;
 use p2p::Context;
 
 pub struct PeerContext {
@@ -51,6 +53,17 @@ impl PeerContext {
         };
     }
 
+    pub fn connection_type(&self) -> Option<ConnectionType> {
+        self.context
+            .connection_counter
+            .connection_type(&self.addr)
+    }
+
+    /// Penalises the peer, returns `true` if it got banned.
+    pub fn misbehaving(&self, misbehaviour: Misbehaviour) -> bool {
+        Context::misbehaving(self.context.clone(), &self.addr, misbehaviour)
+    }
+
     pub fn close(&self) {
         Context::close(self.context.clone(), &self.addr);
     }
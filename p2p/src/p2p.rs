@@ -14,33 +14,40 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::net::SocketAddr;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
 
 use failure::Error;
 
-use futures::Future;
+use futures::{Future, Stream, future};
 use futures_cpupool::CpuPool;
-use tokio_core::reactor::{Handle, Remote};
+use tokio_core::reactor::{Handle, Interval, Remote};
 
 use parking_lot::RwLock;
 
 use storage::SharedStore;
 
-use levin::net::{IoHandler, IoHandlerRef, TcpServer, Commands,
+use levin::Error as LevinError;
+use levin::bucket::LEVIN_DEFAULT_MAX_PACKET_SIZE;
+#[cfg(feature = "tls")]
+use levin::net::TlsTransport;
+use levin::net::{IoHandler, IoHandlerRef, TcpServer, Capture, Commands, Connection, Direction,
                  ConnectionHandler as ConnectionHandlerTrait, ConnectionHandlerRef,
-                 connect as levin_connect};
+                 TrafficStats, TrafficStatsRef, connect as levin_connect,
+                 connect_target};
 
 use portable_storage::{Section, from_section, to_section};
 
 use config::Config;
 
-use net::{ConnectionCounter, ConnectionType, PeerContext};
-use protocol::{LocalSyncNodeRef, OutboundSync, InboundSyncConnectionRef};
+use net::{ConnectionCounter, ConnectionType, PeerContext, select_peer_to_evict};
+use protocol::{LocalSyncNodeRef, OutboundSync, InboundSyncConnectionRef, Misbehaviour,
+               BAN_SCORE, SCORE_DECAY, SUBNET_BAN_SCORE};
 
-use types::{BasicNodeData, PeerlistEntry};
+use types::{BasicNodeData, NetworkAddress, PeerId, PeerlistEntry};
 use types::cn::CoreSyncData;
 use types::cmd::{Handshake, HandshakeRequest, HandshakeResponse, Ping, PingResponse,
                  RequestSupportFlags, SupportFlagsResponse, TimedSync, TimedSyncRequest,
@@ -49,18 +56,54 @@ use types::cn::cmd::{NewBlock, NewFluffyBlock, NewTransactions, RequestChain,
                      RequestFluffyMissingTx, RequestGetObjects, ResponseChainEntry,
                      ResponseGetObjects};
 
-use utils::Peerlist;
+
+use utils::{BanList, BanTarget, MAX_PEERS_IN_PEERLIST, PeerInfo, Peerlist, PeerStore, Subnet};
+
+/// Seconds between the checks of the outbound connections.
+pub const CONNECTIONS_MAINTENANCE_INTERVAL: u64 = 5;
+/// Seconds between the saves of the peer store.
+pub const PEER_STORE_SAVE_INTERVAL: u64 = 60;
+/// Seconds between the decays of the misbehaviour scores.
+pub const SCORE_DECAY_INTERVAL: u64 = 60;
+/// Seconds between the writes of the buffered traffic capture.
+pub const CAPTURE_FLUSH_INTERVAL: u64 = 1;
+/// Seconds between the `TimedSync` requests, a peer that doesn't answer
+/// before the next one is sent gets disconnected.
+pub const TIMED_SYNC_INTERVAL: u64 = 60;
+/// Seconds a peer has to answer our `Handshake`.
+pub const HANDSHAKE_TIMEOUT: u64 = 5;
+/// Seconds a peer has to answer a `Ping`.
+pub const PING_TIMEOUT: u64 = 2;
+/// Seconds a peer has to answer a `RequestSupportFlags`.
+pub const SUPPORT_FLAGS_TIMEOUT: u64 = 5;
+/// Maximum number of inbound connections from a single address.
+pub const MAX_INBOUND_PER_IP: usize = 2;
+/// Maximum number of inbound connections from a single subnet.
+pub const MAX_INBOUND_PER_SUBNET: usize = 4;
 
 pub struct Context {
     remote: Remote,
     pool: CpuPool,
     config: Config,
-    connection_counter: ConnectionCounter,
+    pub(crate) connection_counter: ConnectionCounter,
     store: SharedStore,
     pub(crate) command_streams: RwLock<HashMap<SocketAddr, Commands>>,
-    peerlist: RwLock<Peerlist>,
+    /// The anonymity network peers we connected to, by the key of their
+    /// connection.
+    hosts: RwLock<HashMap<SocketAddr, NetworkAddress>>,
+    /// The keys of the outbound connections that aren't registered yet.
+    dials: RwLock<HashSet<SocketAddr>>,
+    /// Peers we connected to.
+    white_list: RwLock<Peerlist>,
+    /// Peers other peers told us about.
+    gray_list: RwLock<Peerlist>,
+    /// Outbound peers of the previous session not tried yet.
+    anchor_list: RwLock<Peerlist>,
     local_sync_node: LocalSyncNodeRef,
     inbound_sync_connections: RwLock<HashMap<SocketAddr, InboundSyncConnectionRef>>,
+    ban_list: RwLock<BanList>,
+    scores: RwLock<HashMap<IpAddr, u32>>,
+    traffic_stats: TrafficStatsRef,
 }
 
 impl Context {
@@ -76,6 +119,28 @@ impl Context {
         let command_streams = RwLock::new(HashMap::with_capacity(max_peers as _));
         let inbound_sync_connections = RwLock::new(HashMap::with_capacity(max_peers as _));
 
+        let mut ban_list = match config.ban_list {
+            Some(ref path) => {
+                BanList::load(path).unwrap_or_else(|e| {
+                    warn!("couldn't load ban list {}: {}", path.display(), e);
+                    BanList::new()
+                })
+            }
+            None => BanList::new(),
+        };
+        ban_list.remove_expired(Context::local_time());
+
+        let mut peer_store = match config.peer_store {
+            Some(ref path) => {
+                PeerStore::load(path).unwrap_or_else(|e| {
+                    warn!("couldn't load peer store {}: {}", path.display(), e);
+                    PeerStore::new()
+                })
+            }
+            None => PeerStore::new(),
+        };
+        peer_store.prune(Context::local_time() as i64);
+
         Context {
             remote,
             pool,
@@ -83,56 +148,393 @@ impl Context {
             connection_counter,
             store,
             command_streams,
-            peerlist: RwLock::new(Peerlist::new()),
+            hosts: RwLock::new(HashMap::new()),
+            dials: RwLock::new(HashSet::new()),
+            white_list: RwLock::new(peer_store.white),
+            gray_list: RwLock::new(peer_store.gray),
+            anchor_list: RwLock::new(peer_store.anchors),
             local_sync_node,
             inbound_sync_connections,
+            ban_list: RwLock::new(ban_list),
+            scores: RwLock::new(HashMap::new()),
+            traffic_stats: Arc::new(TrafficStats::new()),
+        }
+    }
+
+    pub fn is_banned(context: Arc<Context>, addr: &NetworkAddress) -> bool {
+        context
+            .ban_list
+            .read()
+            .is_banned(addr, Context::local_time())
+    }
+
+    /// Adds the misbehaviour penalty to the peer score, the peer gets banned
+    /// when its score (or the score of its subnet) crosses the threshold.
+    ///
+    /// Returns `true` if the peer was banned.
+    pub fn misbehaving(context: Arc<Context>,
+                       addr: &SocketAddr,
+                       misbehaviour: Misbehaviour)
+                       -> bool {
+        let ip = addr.ip();
+        let subnet = Subnet::of(ip);
+
+        let (score, subnet_score) = {
+            let mut scores = context.scores.write();
+            let score = {
+                let score = scores.entry(ip).or_insert(0);
+                *score = score.saturating_add(misbehaviour.penalty());
+                *score
+            };
+            let subnet_score = scores
+                .iter()
+                .filter(|&(ip, _)| subnet.contains(ip))
+                .fold(0u32, |acc, (_, score)| acc.saturating_add(*score));
+
+            (score, subnet_score)
+        };
+
+        info!("peer {} misbehaving ({:?}), score {}", addr, misbehaviour, score);
+
+        // the addresses of the anonymity network peers are made up, they
+        // don't share subnets and their hosts are banned.
+        let host = context.hosts.read().get(addr).cloned();
+        if let Some(host) = host {
+            if score < BAN_SCORE {
+                return false;
+            }
+
+            Context::ban(context, BanTarget::of(&host));
+            return true;
+        }
+
+        if subnet_score >= SUBNET_BAN_SCORE {
+            Context::ban(context, subnet.into());
+            true
+        } else if score >= BAN_SCORE {
+            Context::ban(context, Subnet::host(ip).into());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Lowers the misbehaviour scores so the occasional faults of a peer
+    /// don't add up to a ban, the scores that reach zero are forgotten.
+    pub fn decay_scores(context: Arc<Context>) {
+        context
+            .scores
+            .write()
+            .retain(|_, score| {
+                        *score = score.saturating_sub(SCORE_DECAY);
+                        *score != 0
+                    });
+    }
+
+    /// Bans a subnet or host and closes all the connections from it.
+    pub fn ban(context: Arc<Context>, target: BanTarget) {
+        info!("banning {} for {} seconds", target, context.config.ban_time.as_secs());
+
+        let until = Context::local_time() + context.config.ban_time.as_secs();
+        {
+            let mut ban_list = context.ban_list.write();
+            ban_list.ban(target.clone(), until);
+
+            if let Some(ref path) = context.config.ban_list {
+                if let Err(e) = ban_list.save(path) {
+                    warn!("couldn't save ban list {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        context
+            .scores
+            .write()
+            .retain(|ip, _| !target.contains_ip(ip));
+
+        let addrs: Vec<SocketAddr> = context
+            .command_streams
+            .read()
+            .keys()
+            .filter(|addr| target.contains(&Context::network_address(context.clone(), addr)))
+            .cloned()
+            .collect();
+        for addr in addrs.iter() {
+            Context::close(context.clone(), addr);
         }
     }
 
     pub fn close(context: Arc<Context>, addr: &SocketAddr) {
         if let Some(command_stream) = context.command_streams.write().remove(addr) {
             context.inbound_sync_connections.write().remove(addr);
+            context.hosts.write().remove(addr);
             command_stream.shutdown();
             context.connection_counter.note_close_connection(addr);
         }
     }
 
+    /// Checks if an inbound connection can be accepted, a peer is evicted
+    /// if all the inbound slots are taken.
+    pub fn accept_inbound(context: Arc<Context>, addr: &SocketAddr) -> bool {
+        let ip = addr.ip();
+        if context.connection_counter.inbound_from_ip(&ip) >= MAX_INBOUND_PER_IP {
+            info!("refusing inbound connection from {}, too many from the same address",
+                  addr);
+            return false;
+        }
+
+        let subnet = Subnet::of(ip);
+        if context.connection_counter.inbound_from_subnet(&subnet) >= MAX_INBOUND_PER_SUBNET {
+            info!("refusing inbound connection from {}, too many from {}", addr, subnet);
+            return false;
+        }
+
+        if context.connection_counter.inbound_connections_needed() > 0 {
+            return true;
+        }
+
+        let candidates = context.connection_counter.eviction_candidates();
+        match select_peer_to_evict(candidates) {
+            Some(evicted) => {
+                info!("evicting inbound peer {} to make room for {}", evicted, addr);
+                Context::close(context.clone(), &evicted);
+                true
+            }
+            None => {
+                info!("refusing inbound connection from {}, no inbound slots left", addr);
+                false
+            }
+        }
+    }
+
+    /// Adds the peers of a received peerlist to the gray list.
+    pub fn on_peerlist(context: Arc<Context>,
+                       addr: &SocketAddr,
+                       peerlist: &[PeerlistEntry]) {
+        if peerlist.len() > MAX_PEERS_IN_PEERLIST {
+            info!("peer {} sent a peerlist with {} peers", addr, peerlist.len());
+            Context::misbehaving(context.clone(), addr, Misbehaviour::ProtocolError);
+            return;
+        }
+
+        let now = Context::local_time();
+        let ban_list = context.ban_list.read();
+        let white_list = context.white_list.read();
+        let mut gray_list = context.gray_list.write();
+        for entry in peerlist.iter() {
+            if entry.id == context.config.peer_id || entry.adr.port() == 0 ||
+               white_list.contains(&entry.adr) || ban_list.is_banned(&entry.adr, now) {
+                continue;
+            }
+
+            let peer = PeerInfo {
+                id: entry.id,
+                last_seen: entry.last_seen,
+            };
+            gray_list.insert(entry.adr.clone(), peer);
+        }
+    }
+
+    /// Moves a peer we connected to to the white list.
+    pub fn promote(context: Arc<Context>, addr: &SocketAddr, peer_id: PeerId) {
+        let entry = PeerInfo {
+            id: peer_id,
+            last_seen: Context::local_time() as i64,
+        };
+
+        let addr = Context::network_address(context.clone(), addr);
+        context.gray_list.write().remove(&addr);
+        context.white_list.write().insert(addr, entry);
+    }
+
+    /// Saves the white and gray lists, the outbound peers we are connected
+    /// to are saved as the anchors. The previous anchors are kept if there
+    /// are none.
+    pub fn save_peer_store(context: Arc<Context>) {
+        let path = match context.config.peer_store {
+            Some(ref path) => path,
+            None => return,
+        };
+
+        let mut peer_store = PeerStore::new();
+        peer_store.white.list = context.white_list.read().list.clone();
+        peer_store.gray.list = context.gray_list.read().list.clone();
+        peer_store.prune(Context::local_time() as i64);
+
+        for (addr, entry) in peer_store.white.list.iter() {
+            let connection_type = context
+                .connection_counter
+                .connection_type(&addr.target().key());
+            if connection_type == Some(ConnectionType::Outbound) {
+                peer_store.anchors.insert(addr.clone(), entry.clone());
+            }
+        }
+        if peer_store.anchors.is_empty() {
+            peer_store.anchors.list = context.anchor_list.read().list.clone();
+        }
+
+        if let Err(e) = peer_store.save(path) {
+            warn!("couldn't save peer store {}: {}", path.display(), e);
+        }
+    }
+
+    /// Connects to the anchor peers of the previous session, they are
+    /// only tried once.
+    pub fn connect_anchors(context: Arc<Context>, io_handler: IoHandlerRef) {
+        let anchors: Vec<NetworkAddress> = context
+            .anchor_list
+            .write()
+            .list
+            .drain()
+            .map(|(addr, _)| addr)
+            .filter(|addr| Context::can_connect(context.clone(), addr))
+            .collect();
+        for addr in anchors.iter() {
+            info!("connecting to anchor {}", addr);
+            Context::connect(context.clone(), addr, io_handler.clone());
+        }
+    }
+
+    fn is_connected(context: Arc<Context>, addr: &NetworkAddress) -> bool {
+        let key = addr.target().key();
+        context.command_streams.read().contains_key(&key) || context.dials.read().contains(&key)
+    }
+
+    /// The anonymity network peers can only be connected to through the
+    /// proxy.
+    fn can_connect(context: Arc<Context>, addr: &NetworkAddress) -> bool {
+        addr.socket_addr().is_some() || context.config.proxy.is_some()
+    }
+
+    /// The address of the peer of a connection.
+    pub fn network_address(context: Arc<Context>, addr: &SocketAddr) -> NetworkAddress {
+        context
+            .hosts
+            .read()
+            .get(addr)
+            .cloned()
+            .unwrap_or_else(|| (*addr).into())
+    }
+
+    /// Opens outbound connections until there are `out_peers` of them,
+    /// peers of the white list are preferred over the ones of the gray
+    /// list. A gray peer is pinged too, so it can be promoted.
+    pub fn maintain_connections(context: Arc<Context>, io_handler: IoHandlerRef) {
+        let now = Context::local_time();
+        let mut chosen = HashSet::new();
+        // The dials of the previous runs that are still being set up count
+        // as connections.
+        let needed = context
+            .connection_counter
+            .outbound_connections_needed()
+            .saturating_sub(context.dials.read().len() as u32);
+        for _ in 0..needed {
+            let addr = {
+                let ban_list = context.ban_list.read();
+                let usable = |addr: &NetworkAddress| {
+                    Context::can_connect(context.clone(), addr) && !chosen.contains(addr) &&
+                    !Context::is_connected(context.clone(), addr) &&
+                    !ban_list.is_banned(addr, now)
+                };
+
+                context
+                    .white_list
+                    .read()
+                    .random(&usable)
+                    .or_else(|| context.gray_list.read().random(&usable))
+                    .or_else(|| context.config.peers.iter().find(|addr| usable(addr)).cloned())
+            };
+
+            match addr {
+                Some(addr) => {
+                    info!("connecting to {}", addr);
+                    chosen.insert(addr.clone());
+                    Context::connect(context.clone(), &addr, io_handler.clone());
+                }
+                None => break,
+            }
+        }
+
+        // the anonymity network peers aren't pinged.
+        let gray_peer = context
+            .gray_list
+            .read()
+            .random(|addr| {
+                        addr.socket_addr().is_some() && !chosen.contains(addr) &&
+                        !Context::is_connected(context.clone(), addr)
+                    })
+            .and_then(|addr| addr.socket_addr());
+        if let Some(addr) = gray_peer {
+            Context::try_ping(context.clone(), &addr);
+        }
+    }
+
+    /// Spawns a server for each of the bind addresses.
     pub fn spawn_server(context: Arc<Context>, io_handler: IoHandlerRef) {
-        let addr = context
+        let port = context
             .config
             .listen_port
-            .map(|port| format!("127.0.0.1:{}", port))
-            .unwrap_or(format!("127.0.0.1:{}", context.config.network.listen_port()))
-            .parse()
-            .unwrap();
+            .unwrap_or(context.config.network.listen_port() as u16);
 
-        context
-            .remote
-            .clone()
-            .spawn(move |handle| {
-                // TODO: spawn this future on the threadpool.
-                let connection_handler = ConnectionHandler::new(context.clone());
-                let future = TcpServer::bind(&addr, handle, io_handler, connection_handler)
-                    .unwrap()
-                    .run()
-                    .map_err(|e| {
-                                 warn!("server io error: {}", e);
-                                 ()
-                             });
+        for ip in context.config.bind_addrs.iter() {
+            let addr = SocketAddr::new(*ip, port);
+            let context = context.clone();
+            let io_handler = io_handler.clone();
 
-                context.pool.clone().spawn(future)
-            })
+            context
+                .remote
+                .clone()
+                .spawn(move |handle| {
+                    // TODO: spawn this future on the threadpool.
+                    let connection_handler = ConnectionHandler::new(context.clone());
+                    let server = match TcpServer::bind(&addr,
+                                                       handle,
+                                                       io_handler,
+                                                       connection_handler) {
+                        Ok(server) => server,
+                        Err(e) => {
+                            warn!("couldn't listen on {}: {}", addr, e);
+                            return future::Either::A(future::ok(()));
+                        }
+                    };
+
+                    info!("listening on {}", addr);
+                    let future = server
+                        .run()
+                        .map_err(|e| {
+                                     warn!("server io error: {}", e);
+                                     ()
+                                 });
+
+                    future::Either::B(context.pool.clone().spawn(future))
+                })
+        }
     }
 
-    pub fn connect(context: Arc<Context>, addr: &SocketAddr, io_handler: IoHandlerRef) {
-        let addr = addr.clone();
+    /// Connects to a peer, the anonymity network peers are connected to
+    /// through the proxy.
+    pub fn connect(context: Arc<Context>, peer: &NetworkAddress, io_handler: IoHandlerRef) {
+        if Context::is_banned(context.clone(), peer) {
+            info!("not connecting to banned peer {}", peer);
+            return;
+        }
+
+        if !Context::can_connect(context.clone(), peer) {
+            info!("not connecting to {}, it needs a proxy", peer);
+            return;
+        }
+
+        let target = peer.target();
+        let addr = target.key();
+        let peer = peer.clone();
+        context.dials.write().insert(addr.clone());
         context
             .remote
             .clone()
             .spawn(move |handle| {
                 // TODO: on threadpool
 
-                let commands = Commands::new();
+                let commands = Commands::new(handle.remote().clone());
 
                 let request = to_section(&HandshakeRequest {
                                               node_data: Context::basic_node_data(context.clone()),
@@ -141,34 +543,26 @@ impl Context {
                                           })
                         .unwrap();
 
-                commands.invoke::<Handshake, _>(request, {
-                    let context = context.clone();
-                    let addr = addr.clone();
-                    move |response: Section| {
-                        // TODO: handle errors
-                        let response: HandshakeResponse = from_section(response).unwrap();
-
-                        if response.node_data.peer_id == context.config.peer_id {
-                            warn!("same peer id from address {}, disconnecting", addr);
-                            Context::close(context.clone(), &addr);
-                        }
-
-                        let peer_context = PeerContext::new(context.clone(), addr.clone());
-                        let outbound_sync = Arc::new(OutboundSync::new(peer_context));
-
-                        let sync_data = response.payload_data;
-
-                        let inbound_sync_connection =
-                            context
-                                .local_sync_node
-                                .new_sync_connection(&sync_data, outbound_sync);
+                let handshake = commands
+                    .invoke::<Handshake>(request, Duration::from_secs(HANDSHAKE_TIMEOUT))
+                    .then({
+                        let context = context.clone();
+                        let addr = addr.clone();
+                        move |response| {
+                            match response {
+                                Ok(response) => {
+                                    Context::on_handshake_response(context, &addr, response)
+                                }
+                                Err(e) => {
+                                    warn!("handshake with {} failed: {}", addr, e);
+                                    Context::close(context, &addr);
+                                }
+                            }
 
-                        context
-                            .inbound_sync_connections
-                            .write()
-                            .insert(addr.clone(), inbound_sync_connection);
-                    }
-                });
+                            Ok::<(), ()>(())
+                        }
+                    });
+                context.pool.spawn(handshake).forget();
 
                 context
                     .command_streams
@@ -177,19 +571,74 @@ impl Context {
                 context
                     .connection_counter
                     .note_new_outbound_connection(addr.clone());
+                context.dials.write().remove(&addr);
+                if peer.socket_addr().is_none() {
+                    context.hosts.write().insert(addr, peer.clone());
+                }
                 // XXX: peerlist?
 
-                let future = levin_connect(&addr, handle, io_handler, commands)
-                    .map_err(|e| {
-                        warn!("connect io error: {}", e);
-                        ()
+                let connection_handler = ConnectionHandler::new(context.clone());
+                let future = connect_target(&target,
+                                            handle,
+                                            io_handler,
+                                            connection_handler,
+                                            commands)
+                    .then({
+                        let context = context.clone();
+                        move |result| {
+                            if let Err(e) = result {
+                                warn!("couldn't connect to {}: {}", peer, e);
+                                Context::close(context, &addr);
+                            }
+
+                            Ok(())
+                        }
                     });
 
                 context.pool.clone().spawn(future)
             })
     }
 
+    /// Starts synchronizing with a peer that accepted our handshake.
+    fn on_handshake_response(context: Arc<Context>,
+                             addr: &SocketAddr,
+                             response: HandshakeResponse) {
+        if response.node_data.peer_id == context.config.peer_id {
+            warn!("same peer id from address {}, disconnecting", addr);
+            Context::close(context.clone(), addr);
+            return;
+        }
+
+        Context::on_peerlist(context.clone(), addr, &response.local_peerlist_new);
+        Context::promote(context.clone(), addr, response.node_data.peer_id);
+
+        if let Some(commands) = context.command_streams.read().get(addr) {
+            commands.set_max_packet_size(LEVIN_DEFAULT_MAX_PACKET_SIZE);
+        }
+
+        let peer_context = PeerContext::new(context.clone(), addr.clone());
+        let outbound_sync = Arc::new(OutboundSync::new(peer_context));
+
+        let sync_data = response.payload_data;
+
+        let inbound_sync_connection =
+            context
+                .local_sync_node
+                .new_sync_connection(&sync_data, outbound_sync);
+
+        context
+            .inbound_sync_connections
+            .write()
+            .insert(addr.clone(), inbound_sync_connection);
+    }
+
+    /// Pings the peer listening at `addr`, it's moved to the white list if
+    /// it answers and it's forgotten if it doesn't.
     pub fn try_ping(context: Arc<Context>, addr: &SocketAddr) {
+        if Context::is_connected(context.clone(), &(*addr).into()) {
+            return;
+        }
+
         let addr = addr.clone();
         context
             .remote
@@ -197,46 +646,38 @@ impl Context {
             .spawn(move |handle| {
                 // TODO: on threadpool
 
-                let commands = Commands::new();
-                let io_handler = IoHandler::new().to_ref();
-
-                commands.invoke::<Ping, _>(Section::new(), {
-                    let context = context.clone();
-                    let addr = addr.clone();
-                    move |response: Section| {
-                        let response: Result<PingResponse, _> = from_section(response);
-                        if let Ok(response) = response {
-                            if response.is_ok() {
-                                let adr = match addr {
-                                    SocketAddr::V4(ref adr) => adr.clone(),
-                                    SocketAddr::V6(_) => {
-                                        warn!("IPv6 adresses aren't supported (yet),
-                                              disconnecting from {}",
-                                              addr);
-                                        Context::close(context.clone(), &addr);
-                                        return;
-                                    }
-                                };
-
-                                let entry = PeerlistEntry {
-                                    adr: adr.into(),
-                                    id: response.peer_id,
-                                    last_seen: Context::local_time() as i64,
-                                };
-
-                                context.peerlist.write().insert(addr, entry);
-                            } else {
-                                warn!("Peer {} returned invalid ping status ({:?})",
-                                      addr,
-                                      response.status);
+                let commands = Commands::new(handle.remote().clone());
+                let mut io_handler = IoHandler::new();
+                io_handler.set_traffic_stats(context.traffic_stats.clone());
+                let io_handler = io_handler.to_ref();
+
+                let ping = commands
+                    .invoke::<Ping>(Section::new(), Duration::from_secs(PING_TIMEOUT))
+                    .then({
+                        let context = context.clone();
+                        let addr = addr.clone();
+                        move |response| {
+                            match response {
+                                Ok(ref response) if response.is_ok() => {
+                                    Context::promote(context.clone(), &addr, response.peer_id);
+                                }
+                                Ok(response) => {
+                                    warn!("Peer {} returned invalid ping status ({:?})",
+                                          addr,
+                                          response.status);
+                                    context.gray_list.write().remove(&addr.into());
+                                }
+                                Err(e) => {
+                                    warn!("Peer {} didn't answer the ping: {}", addr, e);
+                                    context.gray_list.write().remove(&addr.into());
+                                }
                             }
-                        } else {
-                            warn!("Peer {} returned invalid ping status data.", addr);
-                        }
 
-                        Context::close(context.clone(), &addr);
-                    }
-                });
+                            Context::close(context, &addr);
+                            Ok::<(), ()>(())
+                        }
+                    });
+                context.pool.spawn(ping).forget();
 
                 context
                     .command_streams
@@ -244,13 +685,21 @@ impl Context {
                     .insert(addr.clone(), commands.clone());
                 context
                     .connection_counter
-                    .note_new_outbound_connection(addr.clone());
-                // XXX: peerlist?
+                    .note_new_ping_connection(addr.clone());
+
+                let connection_handler = ConnectionHandler::new(context.clone());
+                let future = levin_connect(&addr, handle, io_handler, connection_handler, commands)
+                    .then({
+                        let context = context.clone();
+                        move |result| {
+                            if let Err(e) = result {
+                                warn!("couldn't connect to {} to ping it: {}", addr, e);
+                                context.gray_list.write().remove(&addr.into());
+                                Context::close(context, &addr);
+                            }
 
-                let future = levin_connect(&addr, handle, io_handler, commands)
-                    .map_err(|e| {
-                        warn!("connect io error: {}", e);
-                        ()
+                            Ok(())
+                        }
                     });
 
                 context.pool.clone().spawn(future)
@@ -264,23 +713,42 @@ impl Context {
         let network_id = request.node_data.network_id.0;
         if network_id != context.config.network.id() {
             info!("wrong network agent connected! id {}", network_id);
-            Context::close(context.clone(), &addr);
+            if !Context::misbehaving(context.clone(), &addr, Misbehaviour::ProtocolError) {
+                Context::close(context.clone(), &addr);
+            }
 
             return None;
         }
 
         match context.connection_counter.connection_type(&addr) {
-            Some(ConnectionType::Outbound) => {
+            Some(ConnectionType::Outbound) |
+            Some(ConnectionType::Ping) => {
                 info!("handshake didn't came from inbound connection! address {}",
                       addr);
                 Context::close(context.clone(), &addr);
 
                 return None;
             }
-            None => unreachable!(),
+            None => {
+                info!("handshake from unregistered connection {}", addr);
+                Context::close(context.clone(), &addr);
+
+                return None;
+            }
             _ => { /* it's fine */ }
         }
 
+        let command_stream = match context.command_streams.read().get(&addr).cloned() {
+            Some(command_stream) => command_stream,
+            None => {
+                info!("handshake from closed connection {}", addr);
+                Context::close(context.clone(), &addr);
+
+                return None;
+            }
+        };
+        command_stream.set_max_packet_size(LEVIN_DEFAULT_MAX_PACKET_SIZE);
+
         let peer_context = PeerContext::new(context.clone(), addr.clone());
         let out_sync = Arc::new(OutboundSync::new(peer_context));
 
@@ -294,42 +762,46 @@ impl Context {
             .write()
             .insert(addr.clone(), in_sync.clone());
 
-        let command_stream = context
-            .command_streams
-            .read()
-            .get(&addr)
-            .cloned()
-            .unwrap();
-
         if context.config.peer_id != request.node_data.peer_id && request.node_data.my_port != 0 {
-            Context::try_ping(context.clone(), &addr);
+            // The peer is pinged where it listens, not where it connected from.
+            let listen_addr = SocketAddr::new(addr.ip(), request.node_data.my_port as u16);
+            Context::try_ping(context.clone(), &listen_addr);
         }
 
-        command_stream.invoke::<RequestSupportFlags, _>(Section::new(), {
-            let context = context.clone();
-            let in_sync = in_sync.clone();
-            let addr = addr.clone();
-
-            move |response: Section| {
-                let response: Result<SupportFlagsResponse, _> = from_section(response);
-                match response {
-                    Ok(response) => {
-                        in_sync.on_support_flags(response.support_flags);
-                    }
-                    Err(e) => {
-                        warn!("Disconnecting from peer {} due to bad `SupportFlagsResponse`: {}.",
-                              addr,
-                              e);
-                        Context::close(context.clone(), &addr);
+        let sent_at = Instant::now();
+        let timeout = Duration::from_secs(SUPPORT_FLAGS_TIMEOUT);
+        let support_flags = command_stream
+            .invoke::<RequestSupportFlags>(Section::new(), timeout)
+            .then({
+                let context = context.clone();
+                let in_sync = in_sync.clone();
+                let addr = addr.clone();
+
+                move |response| {
+                    match response {
+                        Ok(response) => {
+                            context
+                                .connection_counter
+                                .note_latency(&addr, sent_at.elapsed());
+                            in_sync.on_support_flags(response.support_flags);
+                        }
+                        Err(e) => {
+                            warn!("Disconnecting from peer {}, `RequestSupportFlags` failed: {}.",
+                                  addr,
+                                  e);
+                            Context::close(context, &addr);
+                        }
                     }
+
+                    Ok::<(), ()>(())
                 }
-            }
-        });
+            });
+        context.pool.spawn(support_flags).forget();
 
         Some(HandshakeResponse {
                  node_data: Context::basic_node_data(context.clone()),
                  payload_data: Context::core_sync_data(context.clone()),
-                 local_peerlist: context.peerlist.read().stl_peerlist(),
+                 local_peerlist_new: context.white_list.read().peerlist(),
              })
     }
 
@@ -342,21 +814,143 @@ impl Context {
     }
 
     pub fn on_timed_sync(context: Arc<Context>,
-                         _addr: SocketAddr,
-                         _request: TimedSyncRequest)
+                         addr: SocketAddr,
+                         request: TimedSyncRequest)
                          -> TimedSyncResponse {
-        // TODO: handle request.payload_data
+        Context::on_sync_data(context.clone(), &addr, &request.payload_data);
 
         TimedSyncResponse {
             local_time: Context::local_time(),
             payload_data: Context::core_sync_data(context.clone()),
-            local_peerlist: context.peerlist.read().stl_peerlist(),
+            local_peerlist_new: context.white_list.read().peerlist(),
+        }
+    }
+
+    /// Handles the response to a `TimedSync` we sent.
+    pub fn on_timed_sync_response(context: Arc<Context>,
+                                  addr: &SocketAddr,
+                                  response: &TimedSyncResponse) {
+        Context::on_peerlist(context.clone(), addr, &response.local_peerlist_new);
+        Context::on_sync_data(context, addr, &response.payload_data);
+    }
+
+    /// Gives the synchronization data of a `TimedSync` to the sync node.
+    fn on_sync_data(context: Arc<Context>, addr: &SocketAddr, sync_data: &CoreSyncData) {
+        let in_sync = context
+            .inbound_sync_connections
+            .read()
+            .get(addr)
+            .cloned();
+        if let Some(in_sync) = in_sync {
+            in_sync.on_sync_data(sync_data);
+        }
+    }
+
+    /// The sync connection of a peer we did the handshake with, the peers
+    /// that send sync commands before it are disconnected.
+    fn inbound_sync(context: Arc<Context>, addr: &SocketAddr) -> Option<InboundSyncConnectionRef> {
+        let in_sync = context
+            .inbound_sync_connections
+            .read()
+            .get(addr)
+            .cloned();
+        if in_sync.is_none() {
+            info!("peer {} sent a sync command before the handshake", addr);
+            Context::close(context, addr);
+        }
+
+        in_sync
+    }
+
+    /// Sends a `TimedSync` to the peers we did the handshake with, the ones
+    /// that don't answer before the next one are disconnected.
+    pub fn timed_sync(context: Arc<Context>) {
+        let request = to_section(&TimedSyncRequest {
+                                     payload_data: Context::core_sync_data(context.clone()),
+                                 })
+                .unwrap();
+
+        let addrs: Vec<SocketAddr> = context
+            .inbound_sync_connections
+            .read()
+            .keys()
+            .cloned()
+            .collect();
+        for addr in addrs {
+            let command_stream = match context.command_streams.read().get(&addr).cloned() {
+                Some(command_stream) => command_stream,
+                None => continue,
+            };
+
+            let sent_at = Instant::now();
+            let timed_sync = command_stream
+                .invoke::<TimedSync>(request.clone(), Duration::from_secs(TIMED_SYNC_INTERVAL))
+                .then({
+                    let context = context.clone();
+                    move |response| {
+                        match response {
+                            Ok(response) => {
+                                context
+                                    .connection_counter
+                                    .note_latency(&addr, sent_at.elapsed());
+                                Context::on_timed_sync_response(context, &addr, &response);
+                            }
+                            Err(e) => {
+                                info!("peer {} didn't answer the timed sync ({}), disconnecting",
+                                      addr,
+                                      e);
+                                Context::close(context, &addr);
+                            }
+                        }
+
+                        Ok::<(), ()>(())
+                    }
+                });
+            context.pool.spawn(timed_sync).forget();
         }
     }
 
     fn io_handler(context: Arc<Context>) -> IoHandlerRef {
         let mut io_handler = IoHandler::with_capacity(12);
 
+        // The same limits as monerod, requests bigger than these close the
+        // connection.
+        io_handler.set_max_size::<Handshake>(64 * 1024);
+        io_handler.set_max_size::<TimedSync>(64 * 1024);
+        io_handler.set_max_size::<Ping>(4 * 1024);
+        io_handler.set_max_size::<RequestSupportFlags>(4 * 1024);
+        io_handler.set_max_size::<NewBlock>(128 * 1024 * 1024);
+        io_handler.set_max_size::<NewTransactions>(128 * 1024 * 1024);
+        io_handler.set_max_size::<RequestGetObjects>(2 * 1024 * 1024);
+        io_handler.set_max_size::<ResponseGetObjects>(128 * 1024 * 1024);
+        io_handler.set_max_size::<RequestChain>(512 * 1024);
+        io_handler.set_max_size::<ResponseChainEntry>(4 * 1024 * 1024);
+        io_handler.set_max_size::<NewFluffyBlock>(4 * 1024 * 1024);
+        io_handler.set_max_size::<RequestFluffyMissingTx>(4 * 1024 * 1024);
+
+        if let Some(ref path) = context.config.capture {
+            match Capture::create(path) {
+                Ok(capture) => {
+                    info!("recording the p2p traffic to {}", path.display());
+                    io_handler.set_capture(Arc::new(capture));
+                }
+                Err(e) => warn!("couldn't create capture {}: {}", path.display(), e),
+            }
+        }
+
+        io_handler.set_traffic_stats(context.traffic_stats.clone());
+        io_handler.set_rate_limits(context.config.rate_limits);
+        io_handler.set_connection_rate_limits(context.config.peer_rate_limits);
+
+        if context.config.tls {
+            set_tls_transport(&mut io_handler);
+        }
+
+        if let Some(ref proxy) = context.config.proxy {
+            info!("connecting to the peers through the SOCKS5 proxy {}", proxy.addr);
+            io_handler.set_proxy(proxy.clone());
+        }
+
         io_handler.add_invokation::<Handshake, _>({
           let context = context.clone();
           move |addr: SocketAddr,
@@ -403,13 +997,9 @@ impl Context {
             let context = context.clone();
             move |addr: SocketAddr, request: Section| {
                 if let Ok(req) = from_section(request) {
-                    context
-                        .inbound_sync_connections
-                        .read()
-                        .get(&addr)
-                        .cloned()
-                        .unwrap()
-                        .on_new_block(&req);
+                    if let Some(in_sync) = Context::inbound_sync(context.clone(), &addr) {
+                        in_sync.on_new_block(&req);
+                    }
                 }
             }
         });
@@ -418,13 +1008,9 @@ impl Context {
             let context = context.clone();
             move |addr: SocketAddr, request: Section| {
                 if let Ok(req) = from_section(request) {
-                    context
-                        .inbound_sync_connections
-                        .read()
-                        .get(&addr)
-                        .cloned()
-                        .unwrap()
-                        .on_new_fluffy_block(&req);
+                    if let Some(in_sync) = Context::inbound_sync(context.clone(), &addr) {
+                        in_sync.on_new_fluffy_block(&req);
+                    }
                 }
             }
         });
@@ -433,12 +1019,9 @@ impl Context {
             let context = context.clone();
             move |addr: SocketAddr, request: Section| {
                 if let Ok(req) = from_section(request) {
-                    context
-                        .inbound_sync_connections
-                        .read()
-                        .get(&addr)
-                        .unwrap()
-                        .on_new_transactions(&req);
+                    if let Some(in_sync) = Context::inbound_sync(context.clone(), &addr) {
+                        in_sync.on_new_transactions(&req);
+                    }
                 }
             }
         });
@@ -447,13 +1030,9 @@ impl Context {
             let context = context.clone();
             move |addr: SocketAddr, request: Section| {
                 if let Ok(req) = from_section(request) {
-                    context
-                        .inbound_sync_connections
-                        .read()
-                        .get(&addr)
-                        .cloned()
-                        .unwrap()
-                        .on_request_chain(&req);
+                    if let Some(in_sync) = Context::inbound_sync(context.clone(), &addr) {
+                        in_sync.on_request_chain(&req);
+                    }
                 }
             }
         });
@@ -462,13 +1041,9 @@ impl Context {
             let context = context.clone();
             move |addr: SocketAddr, request: Section| {
                 if let Ok(req) = from_section(request) {
-                    context
-                        .inbound_sync_connections
-                        .read()
-                        .get(&addr)
-                        .cloned()
-                        .unwrap()
-                        .on_request_fluffy_missing_tx(&req);
+                    if let Some(in_sync) = Context::inbound_sync(context.clone(), &addr) {
+                        in_sync.on_request_fluffy_missing_tx(&req);
+                    }
                 }
             }
         });
@@ -477,13 +1052,9 @@ impl Context {
             let context = context.clone();
             move |addr: SocketAddr, request: Section| {
                 if let Ok(req) = from_section(request) {
-                    context
-                        .inbound_sync_connections
-                        .read()
-                        .get(&addr)
-                        .cloned()
-                        .unwrap()
-                        .on_request_get_objects(&req);
+                    if let Some(in_sync) = Context::inbound_sync(context.clone(), &addr) {
+                        in_sync.on_request_get_objects(&req);
+                    }
                 }
             }
         });
@@ -492,13 +1063,9 @@ impl Context {
             let context = context.clone();
             move |addr: SocketAddr, request: Section| {
                 if let Ok(req) = from_section(request) {
-                    context
-                        .inbound_sync_connections
-                        .read()
-                        .get(&addr)
-                        .cloned()
-                        .unwrap()
-                        .on_response_chain_entry(&req);
+                    if let Some(in_sync) = Context::inbound_sync(context.clone(), &addr) {
+                        in_sync.on_response_chain_entry(&req);
+                    }
                 }
             }
         });
@@ -507,13 +1074,9 @@ impl Context {
             let context = context.clone();
             move |addr: SocketAddr, request: Section| {
                 if let Ok(req) = from_section(request) {
-                    context
-                        .inbound_sync_connections
-                        .read()
-                        .get(&addr)
-                        .cloned()
-                        .unwrap()
-                        .on_response_get_objects(&req);
+                    if let Some(in_sync) = Context::inbound_sync(context.clone(), &addr) {
+                        in_sync.on_response_get_objects(&req);
+                    }
                 }
             }
         });
@@ -543,8 +1106,10 @@ impl Context {
     pub fn core_sync_data(context: Arc<Context>) -> CoreSyncData {
         let best_block = context.store.best_block();
         CoreSyncData {
-            // TODO: cumulative difficulty?,
-            cumulative_difficulty: 0,
+            cumulative_difficulty: context
+                .store
+                .cumulative_difficulty(&best_block.id)
+                .unwrap_or(0),
             current_height: best_block.height,
             top_id: best_block.id,
             top_version: context.config.network.hard_forks().ideal_version(),
@@ -559,6 +1124,22 @@ impl Context {
     }
 }
 
+#[cfg(feature = "tls")]
+fn set_tls_transport(io_handler: &mut IoHandler) {
+    match TlsTransport::new() {
+        Ok(transport) => {
+            info!("encrypting the p2p connections with TLS");
+            io_handler.set_transport(Arc::new(transport));
+        }
+        Err(e) => warn!("couldn't create the TLS transport: {}", e),
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+fn set_tls_transport(_io_handler: &mut IoHandler) {
+    warn!("TLS was requested but xmr-p2p was built without the `tls` feature");
+}
+
 pub struct P2P {
     config: Config,
     context: Arc<Context>,
@@ -588,13 +1169,91 @@ impl P2P {
             Context::spawn_server(self.context.clone(), io_handler.clone())
         }
 
+        Context::connect_anchors(self.context.clone(), io_handler.clone());
+
         for addr in self.config.peers.iter() {
             info!("connecting to {}", addr);
             Context::connect(self.context.clone(), addr, io_handler.clone())
         }
 
+        if let Some(capture) = io_handler.capture().cloned() {
+            self.context
+                .remote
+                .spawn(move |handle| {
+                    let interval = Duration::from_secs(CAPTURE_FLUSH_INTERVAL);
+                    Interval::new(interval, handle)
+                        .expect("couldn't create the capture timer")
+                        .for_each(move |_| {
+                            capture.flush();
+                            Ok(())
+                        })
+                        .map_err(|e| warn!("capture timer error: {}", e))
+                });
+        }
+
+        let context = self.context.clone();
+        self.context
+            .remote
+            .spawn(move |handle| {
+                let interval = Duration::from_secs(CONNECTIONS_MAINTENANCE_INTERVAL);
+                Interval::new(interval, handle)
+                    .expect("couldn't create the connections maintenance timer")
+                    .for_each(move |_| {
+                        Context::maintain_connections(context.clone(), io_handler.clone());
+                        Ok(())
+                    })
+                    .map_err(|e| warn!("connections maintenance timer error: {}", e))
+            });
+
+        let context = self.context.clone();
+        self.context
+            .remote
+            .spawn(move |handle| {
+                let interval = Duration::from_secs(PEER_STORE_SAVE_INTERVAL);
+                Interval::new(interval, handle)
+                    .expect("couldn't create the peer store timer")
+                    .for_each(move |_| {
+                        Context::save_peer_store(context.clone());
+                        Ok(())
+                    })
+                    .map_err(|e| warn!("peer store timer error: {}", e))
+            });
+
+        let context = self.context.clone();
+        self.context
+            .remote
+            .spawn(move |handle| {
+                let interval = Duration::from_secs(SCORE_DECAY_INTERVAL);
+                Interval::new(interval, handle)
+                    .expect("couldn't create the score decay timer")
+                    .for_each(move |_| {
+                        Context::decay_scores(context.clone());
+                        Ok(())
+                    })
+                    .map_err(|e| warn!("score decay timer error: {}", e))
+            });
+
+        let context = self.context.clone();
+        self.context
+            .remote
+            .spawn(move |handle| {
+                let interval = Duration::from_secs(TIMED_SYNC_INTERVAL);
+                Interval::new(interval, handle)
+                    .expect("couldn't create the timed sync timer")
+                    .for_each(move |_| {
+                        Context::timed_sync(context.clone());
+                        Ok(())
+                    })
+                    .map_err(|e| warn!("timed sync timer error: {}", e))
+            });
+
         Ok(())
     }
+
+    /// The traffic counters of the p2p connections.
+    pub fn traffic_stats(&self) -> TrafficStatsRef {
+        self.context.traffic_stats.clone()
+    }
 }
 
 pub struct ConnectionHandler {
@@ -608,14 +1267,216 @@ impl ConnectionHandler {
 }
 
 impl ConnectionHandlerTrait for ConnectionHandler {
-    fn on_connect(&self, addr: SocketAddr, commands: Commands) {
+    fn on_open(&self, connection: &Connection) {
+        // Outbound connections are registered before connecting.
+        if connection.direction() == Direction::Outbound {
+            return;
+        }
+
+        let addr = connection.addr();
+        if Context::is_banned(self.context.clone(), &(*addr).into()) {
+            info!("refusing inbound connection from banned peer {}", addr);
+            connection.refuse();
+            return;
+        }
+
+        if !Context::accept_inbound(self.context.clone(), addr) {
+            connection.refuse();
+            return;
+        }
+
         info!("new inbound connection from {}", addr);
         self.context
             .command_streams
             .write()
-            .insert(addr.clone(), commands);
+            .insert(addr.clone(), connection.commands().clone());
         self.context
             .connection_counter
             .note_new_inbound_connection(addr.clone());
     }
+
+    fn on_close(&self, connection: &Connection) {
+        // A newer connection with the same address may have replaced this
+        // one.
+        let registered = self.context
+            .command_streams
+            .read()
+            .get(connection.addr())
+            .map(|commands| commands.ptr_eq(connection.commands()))
+            .unwrap_or(false);
+
+        if registered {
+            debug!("connection with {} closed", connection.addr());
+            Context::close(self.context.clone(), connection.addr());
+        }
+    }
+
+    fn on_error(&self, connection: &Connection, error: &io::Error) {
+        warn!("io error on connection with {}: {}", connection.addr(), error);
+    }
+
+    fn on_protocol_error(&self, connection: &Connection, error: &LevinError) {
+        info!("peer {} broke the levin protocol: {}", connection.addr(), error);
+        Context::misbehaving(self.context.clone(),
+                             connection.addr(),
+                             Misbehaviour::ProtocolError);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::mpsc;
+
+    use tokio_core::reactor::Core;
+    use tokio_io::io::{read_to_end, write_all};
+
+    use levin::Command;
+    use levin::bucket::Bucket;
+    use levin::net::{Socks5Proxy, duplex};
+
+    use test_harness::{config, context, peer_addr};
+    use super::*;
+
+    fn gray_entry() -> PeerInfo {
+        PeerInfo {
+            id: PeerId::from(2),
+            last_seen: 0,
+        }
+    }
+
+    /// Runs an inbound connection whose peer sends a `Handshake` right
+    /// away, returns what the node answered.
+    fn handshake_from(core: &mut Core, context: Arc<Context>, addr: SocketAddr) -> Vec<u8> {
+        let request = to_section(&HandshakeRequest {
+                                      node_data: Context::basic_node_data(context.clone()),
+                                      payload_data: Context::core_sync_data(context.clone()),
+                                  })
+                .unwrap();
+        let handshake = Bucket::invokation(Handshake::ID, request).to_bytes();
+
+        let (peer, node) = duplex();
+        let (peer, _) = core.run(write_all(peer, handshake)).unwrap();
+
+        let connection = Connection::new(addr, Direction::Inbound, Commands::new(core.remote()));
+        let io_handler = Context::io_handler(context.clone());
+        core.run(connection.run(node, io_handler, ConnectionHandler::new(context)))
+            .unwrap();
+
+        let (_, response) = core.run(read_to_end(peer, Vec::new())).unwrap();
+        response
+    }
+
+    #[test]
+    fn banned_peer_is_refused_before_its_handshake() {
+        let mut core = Core::new().unwrap();
+        let context = context(core.remote(), config());
+        let addr = peer_addr(18080);
+        Context::ban(context.clone(), Subnet::host(addr.ip()).into());
+
+        assert!(handshake_from(&mut core, context.clone(), addr).is_empty());
+        assert!(context.command_streams.read().is_empty());
+    }
+
+    #[test]
+    fn peer_over_the_inbound_cap_is_refused_before_its_handshake() {
+        let mut core = Core::new().unwrap();
+        let context = context(core.remote(),
+                              Config {
+                                  in_peers: 0,
+                                  ..config()
+                              });
+
+        assert!(handshake_from(&mut core, context.clone(), peer_addr(18080)).is_empty());
+        assert!(context.command_streams.read().is_empty());
+    }
+
+    #[test]
+    fn onion_peer_is_dialed_through_the_proxy() {
+        let mut core = Core::new().unwrap();
+        let proxy = Socks5Proxy {
+            addr: peer_addr(9050),
+            auth: None,
+        };
+        let context = context(core.remote(),
+                              Config {
+                                  proxy: Some(proxy),
+                                  ..config()
+                              });
+
+        let onion = NetworkAddress::tor(&format!("{}.onion", "a".repeat(56)), 18080).unwrap();
+        context.gray_list.write().insert(onion.clone(), gray_entry());
+
+        // the dial runs on the pool, it's held until the connection is checked.
+        let (release, held) = mpsc::channel::<()>();
+        context
+            .pool
+            .spawn_fn(move || held.recv().map_err(drop))
+            .forget();
+
+        Context::maintain_connections(context.clone(), Context::io_handler(context.clone()));
+        let key = onion.target().key();
+        for _ in 0..100 {
+            if context.hosts.read().contains_key(&key) {
+                break;
+            }
+            core.turn(Some(Duration::from_millis(10)));
+        }
+
+        assert_eq!(context.hosts.read().get(&key), Some(&onion));
+        assert_eq!(context.connection_counter.connection_type(&key),
+                   Some(ConnectionType::Outbound));
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn pending_dials_count_as_outbound_connections() {
+        let core = Core::new().unwrap();
+        let context = context(core.remote(),
+                              Config {
+                                  out_peers: 2,
+                                  ..config()
+                              });
+        for port in 18080..18084 {
+            context.gray_list.write().insert(peer_addr(port).into(), gray_entry());
+        }
+
+        // the reactor isn't turned, the dials stay pending.
+        let io_handler = Context::io_handler(context.clone());
+        Context::maintain_connections(context.clone(), io_handler.clone());
+        Context::maintain_connections(context.clone(), io_handler);
+
+        assert_eq!(context.dials.read().len(), 2);
+    }
+
+    #[test]
+    fn scores_decay() {
+        let core = Core::new().unwrap();
+        let context = context(core.remote(), config());
+        let addr = peer_addr(18080);
+
+        for _ in 0..4 {
+            assert!(!Context::misbehaving(context.clone(), &addr, Misbehaviour::ProtocolError));
+        }
+        Context::decay_scores(context.clone());
+        Context::decay_scores(context.clone());
+        assert!(!Context::misbehaving(context.clone(), &addr, Misbehaviour::ProtocolError));
+        assert_eq!(context.scores.read().get(&addr.ip()), Some(&80));
+
+        for _ in 0..8 {
+            Context::decay_scores(context.clone());
+        }
+        assert!(context.scores.read().is_empty());
+    }
+
+    #[test]
+    fn onion_peer_isnt_dialed_without_a_proxy() {
+        let core = Core::new().unwrap();
+        let context = context(core.remote(), config());
+
+        let onion = NetworkAddress::tor(&format!("{}.onion", "a".repeat(56)), 18080).unwrap();
+        context.gray_list.write().insert(onion, gray_entry());
+
+        Context::maintain_connections(context.clone(), Context::io_handler(context.clone()));
+        assert!(context.command_streams.read().is_empty());
+    }
 }
@@ -0,0 +1,197 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! A client that asks a node about itself without running a full node.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::{Future, future};
+use futures::sync::oneshot;
+use parking_lot::Mutex;
+use rand;
+use tokio_core::reactor::{Handle, Timeout};
+
+use levin::Error as LevinError;
+use levin::bucket::LEVIN_DEFAULT_MAX_PACKET_SIZE;
+use levin::net::{Commands, Connection, ConnectionHandler, IoHandler, Invokation, Socks5Proxy,
+                 TargetAddr, connect_target};
+use network::Network;
+use portable_storage::{Section, to_section};
+
+use types::{BasicNodeData, PeerId};
+use types::cmd::{Handshake, HandshakeRequest, HandshakeResponse, Ping, PingResponse,
+                 RequestSupportFlags, SupportFlagsResponse, TimedSync, TimedSyncRequest,
+                 TimedSyncResponse};
+use types::cn::CoreSyncData;
+
+/// A connection used to query a node.
+pub struct Probe {
+    addr: TargetAddr,
+    network: Network,
+    peer_id: PeerId,
+    timeout: Duration,
+    commands: Commands,
+}
+
+impl Probe {
+    /// Connects to the node at `addr`, through `proxy` if given, the
+    /// requests fail if the node doesn't answer before `timeout`.
+    pub fn connect(addr: &TargetAddr,
+                   network: Network,
+                   timeout: Duration,
+                   proxy: Option<Socks5Proxy>,
+                   handle: &Handle)
+                   -> Box<Future<Item = Probe, Error = io::Error>> {
+        let commands = Commands::new(handle.remote().clone());
+
+        // The probe only talks to the node it asked for.
+        commands.set_max_packet_size(LEVIN_DEFAULT_MAX_PACKET_SIZE);
+
+        let (sender, receiver) = oneshot::channel();
+        let opened = Arc::new(Mutex::new(Some(sender)));
+
+        let mut io_handler = IoHandler::new();
+        io_handler.add_invokation::<RequestSupportFlags, _>(|_: SocketAddr, _: Section| {
+            Ok(Some(to_section(&SupportFlagsResponse::supported()).unwrap()))
+        });
+
+        if let Some(proxy) = proxy {
+            io_handler.set_proxy(proxy);
+        }
+
+        let connection = connect_target(addr,
+                                        handle,
+                                        io_handler.to_ref(),
+                                        Arc::new(ProbeHandler { opened: opened.clone() }),
+                                        commands.clone())
+                .map_err(move |e| if let Some(sender) = opened.lock().take() {
+                             let _ = sender.send(Err(e));
+                         });
+        handle.spawn(connection);
+
+        let opened = receiver.then(|result| match result {
+                                       Ok(result) => result,
+                                       Err(oneshot::Canceled) => {
+                                           Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                                              "the connection was closed"))
+                                       }
+                                   });
+
+        let deadline = future::result(Timeout::new(timeout, handle))
+            .flatten()
+            .and_then(|_| {
+                          Err::<(), _>(io::Error::new(io::ErrorKind::TimedOut,
+                                                      "couldn't connect in time"))
+                      });
+
+        let probe = Probe {
+            addr: addr.clone(),
+            network,
+            peer_id: PeerId::random(&mut rand::thread_rng()),
+            timeout,
+            commands: commands.clone(),
+        };
+
+        Box::new(opened
+                     .select(deadline)
+                     .map(move |_| probe)
+                     .map_err(move |(e, _)| {
+                                  commands.shutdown();
+                                  e
+                              }))
+    }
+
+    /// The address of the node.
+    pub fn addr(&self) -> &TargetAddr {
+        &self.addr
+    }
+
+    /// Sends a `Handshake`, it has to be done before `TimedSync`.
+    pub fn handshake(&self, payload_data: CoreSyncData) -> Invokation<HandshakeResponse> {
+        let request = HandshakeRequest {
+            node_data: self.node_data(),
+            payload_data,
+        };
+
+        self.commands
+            .invoke::<Handshake>(to_section(&request).unwrap(), self.timeout)
+    }
+
+    /// Sends a `Ping`.
+    pub fn ping(&self) -> Invokation<PingResponse> {
+        self.commands.invoke::<Ping>(Section::new(), self.timeout)
+    }
+
+    /// Asks for the support flags of the node.
+    pub fn support_flags(&self) -> Invokation<SupportFlagsResponse> {
+        self.commands
+            .invoke::<RequestSupportFlags>(Section::new(), self.timeout)
+    }
+
+    /// Sends a `TimedSync`.
+    pub fn timed_sync(&self, payload_data: CoreSyncData) -> Invokation<TimedSyncResponse> {
+        let request = TimedSyncRequest { payload_data };
+        self.commands
+            .invoke::<TimedSync>(to_section(&request).unwrap(), self.timeout)
+    }
+
+    /// Closes the connection.
+    pub fn close(&self) {
+        self.commands.shutdown()
+    }
+
+    fn node_data(&self) -> BasicNodeData {
+        BasicNodeData {
+            network_id: self.network.id().into(),
+            local_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            // We don't listen, so the node doesn't try to ping us.
+            my_port: 0,
+            peer_id: self.peer_id,
+        }
+    }
+}
+
+/// Tells the probe when the connection is open.
+struct ProbeHandler {
+    opened: Arc<Mutex<Option<oneshot::Sender<io::Result<()>>>>>,
+}
+
+impl ConnectionHandler for ProbeHandler {
+    fn on_open(&self, _connection: &Connection) {
+        if let Some(sender) = self.opened.lock().take() {
+            let _ = sender.send(Ok(()));
+        }
+    }
+
+    fn on_close(&self, connection: &Connection) {
+        debug!("connection with {} closed", connection.addr());
+    }
+
+    fn on_error(&self, connection: &Connection, error: &io::Error) {
+        warn!("io error on connection with {}: {}", connection.addr(), error);
+    }
+
+    fn on_protocol_error(&self, connection: &Connection, error: &LevinError) {
+        warn!("peer {} broke the levin protocol: {}", connection.addr(), error);
+    }
+}
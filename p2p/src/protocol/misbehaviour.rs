@@ -0,0 +1,53 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+/// Score at which a peer gets banned.
+pub const BAN_SCORE: u32 = 100;
+/// Score of all the peers in a subnet at which the subnet gets banned.
+pub const SUBNET_BAN_SCORE: u32 = 3 * BAN_SCORE;
+/// Points taken from the peer scores at every decay.
+pub const SCORE_DECAY: u32 = 10;
+
+/// Kinds of peer misbehaviour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehaviour {
+    /// The peer sent an invalid block.
+    InvalidBlock,
+    /// The peer sent an invalid transaction.
+    InvalidTransaction,
+    /// The peer sent an invalid chain entry.
+    BadChainEntry,
+    /// The peer claimed a higher cumulative difficulty than it's chain has.
+    OverclaimedDifficulty,
+    /// The peer didn't follow the protocol.
+    ProtocolError,
+    /// The peer didn't answer in time.
+    Timeout,
+}
+
+impl Misbehaviour {
+    /// The penalty added to the peer score.
+    pub fn penalty(&self) -> u32 {
+        match *self {
+            Misbehaviour::InvalidBlock => 100,
+            Misbehaviour::InvalidTransaction => 50,
+            Misbehaviour::BadChainEntry => 50,
+            Misbehaviour::OverclaimedDifficulty => 50,
+            Misbehaviour::ProtocolError => 20,
+            Misbehaviour::Timeout => 10,
+        }
+    }
+}
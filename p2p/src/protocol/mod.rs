@@ -14,8 +14,11 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+mod misbehaviour;
 mod sync;
 
+pub use self::misbehaviour::{Misbehaviour, BAN_SCORE, SCORE_DECAY, SUBNET_BAN_SCORE};
+
 pub use self::sync::{OutboundSync, OutboundSyncConnection, OutboundSyncConnectionRef,
                      InboundSyncConnection, InboundSyncConnectionRef, LocalSyncNode,
                      LocalSyncNodeRef};
@@ -18,7 +18,8 @@ use std::sync::Arc;
 
 use portable_storage::to_section;
 
-use net::PeerContext;
+use net::{ConnectionType, PeerContext};
+use protocol::Misbehaviour;
 
 use types::cn::CoreSyncData;
 use types::cn::cmd::{NewBlock, NewFluffyBlock, NewTransactions, RequestChain,
@@ -43,6 +44,9 @@ pub trait OutboundSyncConnection: Send + Sync {
     fn notify_request_get_objects(&self, arg: &RequestGetObjects);
     fn notify_response_chain_entry(&self, arg: &ResponseChainEntry);
     fn notify_response_get_objects(&self, arg: &ResponseGetObjects);
+    fn is_outbound(&self) -> bool;
+    /// Penalises the peer, returns `true` if it got banned (and disconnected).
+    fn misbehaving(&self, misbehaviour: Misbehaviour) -> bool;
     fn close(&self);
 }
 
@@ -99,6 +103,14 @@ impl OutboundSyncConnection for OutboundSync {
             .notify::<ResponseGetObjects>(to_section(arg).unwrap())
     }
 
+    fn is_outbound(&self) -> bool {
+        self.context.connection_type() == Some(ConnectionType::Outbound)
+    }
+
+    fn misbehaving(&self, misbehaviour: Misbehaviour) -> bool {
+        self.context.misbehaving(misbehaviour)
+    }
+
     fn close(&self) {
         self.context.close();
     }
@@ -119,6 +131,10 @@ pub trait InboundSyncConnection: Send + Sync + 'static {
     /// This isn't a notification, it is called when a `RequestSupportFlags`
     /// response is received.
     fn on_support_flags(&self, arg: u32);
+
+    /// Called with the synchronization data the peer sends periodically in
+    /// `TimedSync`.
+    fn on_sync_data(&self, arg: &CoreSyncData);
 }
 
 pub type InboundSyncConnectionRef = Arc<InboundSyncConnection>;
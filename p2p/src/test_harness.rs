@@ -0,0 +1,160 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! An empty store and the config of a node to test the p2p context
+//! without a database.
+
+use std::collections::LinkedList;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_cpupool::CpuPool;
+use tokio_core::reactor::Remote;
+
+use chain::{IndexedBlock, IndexedTransaction};
+use keys::KeyImage;
+use network::Network;
+use primitives::H256;
+use storage::{BestBlock, BlockChain, BlockProvider, BlockRef, CanonStore, IndexedBlockProvider,
+              KeyImageProvider, Store, TransactionProvider};
+
+use config::Config;
+use p2p::Context;
+use protocol::{InboundSyncConnectionRef, LocalSyncNode, OutboundSyncConnectionRef};
+use types::PeerId;
+use types::cn::CoreSyncData;
+
+/// A store with only the genesis block id.
+#[derive(Debug, Default)]
+pub struct EmptyStore;
+
+impl BlockChain for EmptyStore {
+    fn insert(&self, _block: IndexedBlock, _difficulty: u64) -> Result<(), String> {
+        Err("the store is read only".to_string())
+    }
+
+    fn canonize(&self, _id: &H256) -> Result<(), String> {
+        Err("the store is read only".to_string())
+    }
+
+    fn decanonize(&self) -> Result<H256, String> {
+        Err("the store is read only".to_string())
+    }
+}
+
+impl BlockProvider for EmptyStore {
+    fn block_id(&self, height: u64) -> Option<H256> {
+        match height {
+            0 => Some(H256::default()),
+            _ => None,
+        }
+    }
+
+    fn cumulative_difficulty(&self, _id: &H256) -> Option<u64> {
+        Some(1)
+    }
+}
+
+impl IndexedBlockProvider for EmptyStore {
+    fn indexed_block(&self, _block_ref: BlockRef) -> Option<IndexedBlock> {
+        None
+    }
+}
+
+impl TransactionProvider for EmptyStore {
+    fn transaction(&self, _id: &H256) -> Option<IndexedTransaction> {
+        None
+    }
+}
+
+impl KeyImageProvider for EmptyStore {
+    fn contains_key_image(&self, _key_image: &KeyImage) -> bool {
+        false
+    }
+}
+
+impl Store for EmptyStore {
+    fn best_block(&self) -> BestBlock {
+        BestBlock::default()
+    }
+
+    fn height(&self) -> u64 {
+        1
+    }
+
+    fn short_chain_history(&self) -> LinkedList<H256> {
+        let mut history = LinkedList::new();
+        history.push_back(H256::default());
+        history
+    }
+}
+
+impl CanonStore for EmptyStore {
+    fn as_store(&self) -> &Store {
+        &*self
+    }
+}
+
+/// A sync node for the tests where no peer may get through the handshake.
+#[derive(Debug, Default)]
+pub struct UnreachableSyncNode;
+
+impl LocalSyncNode for UnreachableSyncNode {
+    fn new_sync_connection(&self,
+                           _sync_data: &CoreSyncData,
+                           _connection: OutboundSyncConnectionRef)
+                           -> InboundSyncConnectionRef {
+        panic!("unexpected sync connection")
+    }
+}
+
+/// The config of a node that doesn't listen nor persist anything.
+pub fn config() -> Config {
+    Config {
+        threads: 1,
+        network: Network::Mainnet,
+        peers: Vec::new(),
+        bind_addrs: Vec::new(),
+        listen_port: None,
+        hide_my_port: true,
+        out_peers: 8,
+        in_peers: 8,
+        peer_id: PeerId::from(1),
+        ban_time: Duration::from_secs(60),
+        ban_list: None,
+        peer_store: None,
+        capture: None,
+        tls: false,
+        proxy: None,
+        rate_limits: Default::default(),
+        peer_rate_limits: Default::default(),
+    }
+}
+
+/// Creates the context of a node with an empty store.
+pub fn context(remote: Remote, config: Config) -> Arc<Context> {
+    Arc::new(Context::new(remote,
+                          CpuPool::new(1),
+                          config,
+                          Arc::new(EmptyStore),
+                          Box::new(UnreachableSyncNode)))
+}
+
+/// An address for the fake peers.
+pub fn peer_addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, 1], port))
+}
@@ -1,5 +1,3 @@
-use portable_storage_utils::stl::StlLinkedList;
-
 use types::{P2P_COMMAND_BASE_ID, BasicNodeData, PeerlistEntry};
 use types::cn::CoreSyncData;
 use levin::Command;
@@ -10,6 +8,8 @@ pub struct Handshake;
 
 impl Command for Handshake {
     const ID: u32 = P2P_COMMAND_BASE_ID + 1;
+
+    type Response = HandshakeResponse;
 }
 
 /// The handshake command request.
@@ -24,5 +24,6 @@ pub struct HandshakeRequest {
 pub struct HandshakeResponse {
     pub node_data: BasicNodeData,
     pub payload_data: CoreSyncData,
-    pub local_peerlist: StlLinkedList<PeerlistEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub local_peerlist_new: Vec<PeerlistEntry>,
 }
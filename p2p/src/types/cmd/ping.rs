@@ -9,6 +9,8 @@ pub struct Ping;
 
 impl Command for Ping {
     const ID: u32 = P2P_COMMAND_BASE_ID + 3;
+
+    type Response = PingResponse;
 }
 
 const PING_RESPONSE_STATUS: &'static [u8] = b"OK\0";
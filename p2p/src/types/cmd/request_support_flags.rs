@@ -14,6 +14,8 @@ pub struct RequestSupportFlags;
 
 impl Command for RequestSupportFlags {
     const ID: u32 = P2P_COMMAND_BASE_ID + 7;
+
+    type Response = SupportFlagsResponse;
 }
 
 /// The support flags of a node.
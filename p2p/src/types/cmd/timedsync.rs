@@ -1,5 +1,3 @@
-use portable_storage_utils::stl::StlLinkedList;
-
 use types::{P2P_COMMAND_BASE_ID, PeerlistEntry};
 use types::cn::CoreSyncData;
 use levin::Command;
@@ -9,6 +7,8 @@ pub struct TimedSync;
 
 impl Command for TimedSync {
     const ID: u32 = P2P_COMMAND_BASE_ID + 2;
+
+    type Response = TimedSyncResponse;
 }
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
@@ -20,5 +20,6 @@ pub struct TimedSyncRequest {
 pub struct TimedSyncResponse {
     pub local_time: u64,
     pub payload_data: CoreSyncData,
-    pub local_peerlist: StlLinkedList<PeerlistEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub local_peerlist_new: Vec<PeerlistEntry>,
 }
@@ -16,7 +16,7 @@
 
 use portable_storage_utils::Blob;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BlockCompleteEntry {
     pub block: Blob,
     pub txs: Vec<Blob>,
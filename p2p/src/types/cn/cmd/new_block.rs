@@ -18,7 +18,7 @@ use levin::Command;
 
 use types::cn::{CN_COMMAND_BASE_ID, BlockCompleteEntry};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct NewBlock {
     pub b: BlockCompleteEntry,
     pub current_blockchain_height: u64,
@@ -26,4 +26,6 @@ pub struct NewBlock {
 
 impl Command for NewBlock {
     const ID: u32 = CN_COMMAND_BASE_ID + 1;
+
+    type Response = ();
 }
@@ -19,11 +19,46 @@ use portable_storage_utils::Blob;
 
 use types::cn::CN_COMMAND_BASE_ID;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct NewTransactions {
     pub txs: Vec<Blob>,
+    /// Whether the transactions are in the Dandelion++ fluff phase, they're
+    /// in the stem phase otherwise.
+    #[serde(default)]
+    pub dandelionpp_fluff: bool,
 }
 
 impl Command for NewTransactions {
     const ID: u32 = CN_COMMAND_BASE_ID + 2;
+
+    type Response = ();
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use portable_storage::{Array, Section, StorageEntry, from_section, to_section};
+
+    #[test]
+    fn dandelionpp_fluff() {
+        let txs = NewTransactions {
+            txs: vec![vec![0x20].into()],
+            dandelionpp_fluff: true,
+        };
+
+        let section = to_section(&txs).unwrap();
+        match &section["dandelionpp_fluff"] {
+            &StorageEntry::Bool(fluff) => assert!(fluff),
+            entry => panic!("invalid entry: {:?}", entry),
+        }
+
+        let txs: NewTransactions = from_section(section).unwrap();
+        assert!(txs.dandelionpp_fluff);
+
+        // Older nodes don't send the flag.
+        let mut section = Section::new();
+        section.insert("txs".to_owned(), StorageEntry::Array(Array::new()));
+        let txs: NewTransactions = from_section(section).unwrap();
+        assert!(!txs.dandelionpp_fluff);
+    }
 }
@@ -20,11 +20,13 @@ use primitives::H256;
 
 use types::cn::CN_COMMAND_BASE_ID;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RequestChain {
     pub block_ids: StlLinkedList<H256>,
 }
 
 impl Command for RequestChain {
     const ID: u32 = CN_COMMAND_BASE_ID + 6;
+
+    type Response = ();
 }
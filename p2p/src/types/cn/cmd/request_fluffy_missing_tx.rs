@@ -21,7 +21,7 @@ use primitives::H256;
 
 use types::cn::CN_COMMAND_BASE_ID;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RequestFluffyMissingTx {
     pub block_hash: H256,
     pub current_blockchain_length: u64,
@@ -30,4 +30,6 @@ pub struct RequestFluffyMissingTx {
 
 impl Command for RequestFluffyMissingTx {
     const ID: u32 = CN_COMMAND_BASE_ID + 9;
+
+    type Response = ();
 }
@@ -19,7 +19,7 @@ use primitives::H256;
 
 use types::cn::CN_COMMAND_BASE_ID;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RequestGetObjects {
     pub txs: Vec<H256>,
     pub blocks: Vec<H256>,
@@ -27,4 +27,6 @@ pub struct RequestGetObjects {
 
 impl Command for RequestGetObjects {
     const ID: u32 = CN_COMMAND_BASE_ID + 3;
+
+    type Response = ();
 }
@@ -20,7 +20,7 @@ use primitives::H256;
 
 use types::cn::CN_COMMAND_BASE_ID;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ResponseChainEntry {
     pub start_height: u64,
     pub total_height: u64,
@@ -31,4 +31,6 @@ pub struct ResponseChainEntry {
 
 impl Command for ResponseChainEntry {
     const ID: u32 = CN_COMMAND_BASE_ID + 7;
+
+    type Response = ();
 }
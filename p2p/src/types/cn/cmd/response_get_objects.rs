@@ -21,7 +21,7 @@ use portable_storage_utils::Blob;
 
 use types::cn::{CN_COMMAND_BASE_ID, BlockCompleteEntry};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ResponseGetObjects {
     pub txs: Vec<Blob>,
     pub blocks: Vec<BlockCompleteEntry>,
@@ -31,4 +31,6 @@ pub struct ResponseGetObjects {
 
 impl Command for ResponseGetObjects {
     const ID: u32 = CN_COMMAND_BASE_ID + 4;
+
+    type Response = ();
 }
@@ -0,0 +1,122 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::fmt::Debug;
+
+use serde::de::DeserializeOwned;
+
+use levin::Command;
+use levin::bucket::Bucket;
+use portable_storage::{Section, from_section};
+
+use types::cmd::{Handshake, HandshakeRequest, HandshakeResponse, Ping, PingResponse,
+                 RequestSupportFlags, SupportFlagsResponse, TimedSync, TimedSyncRequest,
+                 TimedSyncResponse};
+use types::cn::cmd::{NewBlock, NewFluffyBlock, NewTransactions, RequestChain,
+                     RequestFluffyMissingTx, RequestGetObjects, ResponseChainEntry,
+                     ResponseGetObjects};
+
+/// The name of a known command.
+pub fn command_name(id: u32) -> Option<&'static str> {
+    let name = match id {
+        Handshake::ID => "Handshake",
+        TimedSync::ID => "TimedSync",
+        Ping::ID => "Ping",
+        RequestSupportFlags::ID => "RequestSupportFlags",
+        NewBlock::ID => "NewBlock",
+        NewTransactions::ID => "NewTransactions",
+        RequestGetObjects::ID => "RequestGetObjects",
+        ResponseGetObjects::ID => "ResponseGetObjects",
+        RequestChain::ID => "RequestChain",
+        ResponseChainEntry::ID => "ResponseChainEntry",
+        NewFluffyBlock::ID => "NewFluffyBlock",
+        RequestFluffyMissingTx::ID => "RequestFluffyMissingTx",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// Pretty prints a bucket, the sections of the known commands are decoded
+/// to their types.
+pub fn decode(bucket: &Bucket) -> String {
+    let head = &bucket.head;
+    let name = command_name(head.command)
+        .map(|name| name.to_owned())
+        .unwrap_or_else(|| format!("#{}", head.command));
+    let kind = if head.is_request() { "request" } else { "response" };
+
+    if !head.is_request() && head.return_code < 0 {
+        return format!("{} {}, error {}", name, kind, head.return_code);
+    }
+
+    let section = match bucket.into_section() {
+        Ok(section) => section,
+        Err(e) => return format!("{} {}, invalid section: {}", name, kind, e),
+    };
+
+    let body = match (head.command, head.is_request()) {
+        (Handshake::ID, true) => pretty::<HandshakeRequest>(section),
+        (Handshake::ID, false) => pretty::<HandshakeResponse>(section),
+        (TimedSync::ID, true) => pretty::<TimedSyncRequest>(section),
+        (TimedSync::ID, false) => pretty::<TimedSyncResponse>(section),
+        (Ping::ID, false) => pretty::<PingResponse>(section),
+        (RequestSupportFlags::ID, false) => pretty::<SupportFlagsResponse>(section),
+        (NewBlock::ID, true) => pretty::<NewBlock>(section),
+        (NewTransactions::ID, true) => pretty::<NewTransactions>(section),
+        (RequestGetObjects::ID, true) => pretty::<RequestGetObjects>(section),
+        (ResponseGetObjects::ID, true) => pretty::<ResponseGetObjects>(section),
+        (RequestChain::ID, true) => pretty::<RequestChain>(section),
+        (ResponseChainEntry::ID, true) => pretty::<ResponseChainEntry>(section),
+        (NewFluffyBlock::ID, true) => pretty::<NewFluffyBlock>(section),
+        (RequestFluffyMissingTx::ID, true) => pretty::<RequestFluffyMissingTx>(section),
+        _ => format!("{:#?}", section),
+    };
+
+    format!("{} {}\n{}", name, kind, body)
+}
+
+fn pretty<T>(section: Section) -> String
+    where T: DeserializeOwned + Debug
+{
+    match from_section::<T>(section) {
+        Ok(value) => format!("{:#?}", value),
+        Err(e) => format!("invalid section: {}", e),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use portable_storage::to_section;
+
+    use levin::bucket::Bucket;
+
+    use types::cmd::{Ping, PingResponse};
+    use types::PeerId;
+    use super::*;
+
+    #[test]
+    fn known_commands_are_decoded() {
+        let response = PingResponse::new(PeerId::from(7));
+        let bucket = Bucket::invokation_response(Ping::ID, to_section(&response).unwrap());
+
+        let decoded = decode(&bucket);
+        assert!(decoded.starts_with("Ping response\n"));
+        assert!(decoded.contains("PingResponse"));
+        assert_eq!(command_name(1), None);
+    }
+}
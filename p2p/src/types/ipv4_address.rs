@@ -20,16 +20,19 @@ use bytes::{BytesMut, Buf, BufMut, IntoBuf, LittleEndian};
 use portable_storage_utils::stl::{StlElement, Error};
 
 /// An IPv4 address
-#[derive(Debug, Default, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct Ipv4Address {
+    /// The address in network byte order, like monerod keeps it.
+    #[serde(rename = "m_ip")]
     pub ip: u32,
+    #[serde(rename = "m_port")]
     pub port: u16,
 }
 
 impl From<net::SocketAddrV4> for Ipv4Address {
     fn from(addr: net::SocketAddrV4) -> Ipv4Address {
         Ipv4Address {
-            ip: addr.ip().clone().into(),
+            ip: u32::from(*addr.ip()).swap_bytes(),
             port: addr.port(),
         }
     }
@@ -38,12 +41,18 @@ impl From<net::SocketAddrV4> for Ipv4Address {
 impl<'a> From<&'a net::SocketAddrV4> for Ipv4Address {
     fn from(addr: &'a net::SocketAddrV4) -> Ipv4Address {
         Ipv4Address {
-            ip: addr.ip().clone().into(),
+            ip: u32::from(*addr.ip()).swap_bytes(),
             port: addr.port(),
         }
     }
 }
 
+impl<'a> From<&'a Ipv4Address> for net::SocketAddr {
+    fn from(addr: &'a Ipv4Address) -> net::SocketAddr {
+        net::SocketAddrV4::new(addr.ip.swap_bytes().into(), addr.port).into()
+    }
+}
+
 impl StlElement for Ipv4Address {
     const LENGTH: usize = 4 + 2;
 
@@ -22,11 +22,15 @@ pub mod cmd;
 pub mod cn;
 
 mod basic_node_data;
+mod decode;
 mod ipv4_address;
+mod network_address;
 mod peerid;
 mod peerlist_entry;
 
 pub use self::basic_node_data::BasicNodeData;
+pub use self::decode::{command_name, decode};
 pub use self::ipv4_address::Ipv4Address;
+pub use self::network_address::{HostAddress, NetworkAddress};
 pub use self::peerid::PeerId;
 pub use self::peerlist_entry::PeerlistEntry;
@@ -0,0 +1,363 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::fmt::{self, Display, Formatter};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::str::FromStr;
+
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use levin::net::TargetAddr;
+
+use portable_storage_utils::Blob;
+
+use types::Ipv4Address;
+
+/// The address type ids used by monerod.
+const ADDRESS_TYPE_IPV4: u8 = 1;
+const ADDRESS_TYPE_IPV6: u8 = 2;
+const ADDRESS_TYPE_I2P: u8 = 3;
+const ADDRESS_TYPE_TOR: u8 = 4;
+
+/// Length of the base32 part of an onion v3 address.
+const ONION_V3_LENGTH: usize = 56;
+/// Length of the base32 part of an I2P b32 address.
+const I2P_B32_LENGTH: usize = 52;
+
+/// The address of a peer, on the internet or on an anonymity network.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NetworkAddress {
+    Ipv4(SocketAddrV4),
+    Ipv6(SocketAddrV6),
+    Tor(HostAddress),
+    I2p(HostAddress),
+}
+
+/// The hostname and port of a Tor or I2P peer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HostAddress {
+    host: String,
+    port: u16,
+}
+
+impl HostAddress {
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl NetworkAddress {
+    /// A Tor onion v3 address, `None` if `host` isn't one.
+    pub fn tor(host: &str, port: u16) -> Option<NetworkAddress> {
+        let host = host.to_lowercase();
+        if !is_base32_host(&host, ".onion", ONION_V3_LENGTH) {
+            return None;
+        }
+
+        Some(NetworkAddress::Tor(HostAddress { host, port }))
+    }
+
+    /// An I2P b32 address, `None` if `host` isn't one.
+    pub fn i2p(host: &str, port: u16) -> Option<NetworkAddress> {
+        let host = host.to_lowercase();
+        if !is_base32_host(&host, ".b32.i2p", I2P_B32_LENGTH) {
+            return None;
+        }
+
+        Some(NetworkAddress::I2p(HostAddress { host, port }))
+    }
+
+    /// The IP address and port, `None` for anonymity network addresses.
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match *self {
+            NetworkAddress::Ipv4(addr) => Some(SocketAddr::V4(addr)),
+            NetworkAddress::Ipv6(addr) => Some(SocketAddr::V6(addr)),
+            NetworkAddress::Tor(_) |
+            NetworkAddress::I2p(_) => None,
+        }
+    }
+
+    /// The hostname of an anonymity network address.
+    pub fn host(&self) -> Option<&str> {
+        match *self {
+            NetworkAddress::Tor(ref addr) |
+            NetworkAddress::I2p(ref addr) => Some(addr.host()),
+            NetworkAddress::Ipv4(_) |
+            NetworkAddress::Ipv6(_) => None,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match *self {
+            NetworkAddress::Ipv4(addr) => addr.port(),
+            NetworkAddress::Ipv6(addr) => addr.port(),
+            NetworkAddress::Tor(ref addr) |
+            NetworkAddress::I2p(ref addr) => addr.port(),
+        }
+    }
+
+    /// Where to connect to reach the peer, the hostnames of the anonymity
+    /// network addresses are resolved by the proxy.
+    pub fn target(&self) -> TargetAddr {
+        match *self {
+            NetworkAddress::Ipv4(addr) => TargetAddr::Ip(SocketAddr::V4(addr)),
+            NetworkAddress::Ipv6(addr) => TargetAddr::Ip(SocketAddr::V6(addr)),
+            NetworkAddress::Tor(ref addr) |
+            NetworkAddress::I2p(ref addr) => TargetAddr::Domain(addr.host.clone(), addr.port),
+        }
+    }
+
+    /// The id monerod uses for the type of this address.
+    pub fn type_id(&self) -> u8 {
+        match *self {
+            NetworkAddress::Ipv4(_) => ADDRESS_TYPE_IPV4,
+            NetworkAddress::Ipv6(_) => ADDRESS_TYPE_IPV6,
+            NetworkAddress::Tor(_) => ADDRESS_TYPE_TOR,
+            NetworkAddress::I2p(_) => ADDRESS_TYPE_I2P,
+        }
+    }
+}
+
+fn is_base32_host(host: &str, suffix: &str, length: usize) -> bool {
+    host.ends_with(suffix) && host.len() == length + suffix.len() &&
+    host[..length]
+        .bytes()
+        .all(|c| (c >= b'a' && c <= b'z') || (c >= b'2' && c <= b'7'))
+}
+
+impl From<SocketAddr> for NetworkAddress {
+    fn from(addr: SocketAddr) -> NetworkAddress {
+        match addr {
+            SocketAddr::V4(addr) => NetworkAddress::Ipv4(addr),
+            SocketAddr::V6(addr) => NetworkAddress::Ipv6(addr),
+        }
+    }
+}
+
+impl Display for NetworkAddress {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match *self {
+            NetworkAddress::Ipv4(ref addr) => addr.fmt(fmt),
+            NetworkAddress::Ipv6(ref addr) => addr.fmt(fmt),
+            NetworkAddress::Tor(ref addr) |
+            NetworkAddress::I2p(ref addr) => write!(fmt, "{}:{}", addr.host, addr.port),
+        }
+    }
+}
+
+impl FromStr for NetworkAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NetworkAddress, String> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(addr.into());
+        }
+
+        let colon = s.rfind(':')
+            .ok_or_else(|| format!("invalid address {}", s))?;
+        let port = s[colon + 1..]
+            .parse()
+            .map_err(|_| format!("invalid port {}", s))?;
+        let host = &s[..colon];
+        NetworkAddress::tor(host, port)
+            .or_else(|| NetworkAddress::i2p(host, port))
+            .ok_or_else(|| format!("invalid address {}", s))
+    }
+}
+
+#[derive(Serialize)]
+struct Ipv6Repr {
+    addr: Blob,
+    m_port: u16,
+}
+
+#[derive(Serialize)]
+struct HostRepr {
+    host: Blob,
+    port: u16,
+}
+
+/// The fields of all the address types, the type says which ones are
+/// used.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AddrRepr {
+    m_ip: u32,
+    m_port: u16,
+    addr: Blob,
+    host: Blob,
+    port: u16,
+}
+
+#[derive(Deserialize)]
+struct NetworkAddressRepr {
+    #[serde(rename = "type")]
+    type_id: u8,
+    addr: AddrRepr,
+}
+
+impl Serialize for NetworkAddress {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut state = serializer.serialize_struct("NetworkAddress", 2)?;
+        state.serialize_field("type", &self.type_id())?;
+        match *self {
+            NetworkAddress::Ipv4(ref addr) => {
+                state.serialize_field("addr", &Ipv4Address::from(addr))?
+            }
+            NetworkAddress::Ipv6(ref addr) => {
+                let repr = Ipv6Repr {
+                    addr: addr.ip().octets().to_vec().into(),
+                    m_port: addr.port(),
+                };
+                state.serialize_field("addr", &repr)?
+            }
+            NetworkAddress::Tor(ref addr) |
+            NetworkAddress::I2p(ref addr) => {
+                let repr = HostRepr {
+                    host: addr.host.as_bytes().to_vec().into(),
+                    port: addr.port,
+                };
+                state.serialize_field("addr", &repr)?
+            }
+        }
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for NetworkAddress {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let repr = NetworkAddressRepr::deserialize(deserializer)?;
+        let addr = repr.addr;
+        match repr.type_id {
+            ADDRESS_TYPE_IPV4 => {
+                let addr = Ipv4Address {
+                    ip: addr.m_ip,
+                    port: addr.m_port,
+                };
+                Ok(NetworkAddress::from(SocketAddr::from(&addr)))
+            }
+            ADDRESS_TYPE_IPV6 => {
+                if addr.addr.0.len() != 16 {
+                    return Err(D::Error::custom("invalid IPv6 address length"));
+                }
+
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&addr.addr.0);
+                let ip = IpAddr::V6(Ipv6Addr::from(octets));
+                Ok(NetworkAddress::from(SocketAddr::new(ip, addr.m_port)))
+            }
+            ADDRESS_TYPE_TOR | ADDRESS_TYPE_I2P => {
+                let host = String::from_utf8(addr.host.0)
+                    .map_err(|_| D::Error::custom("invalid hostname"))?;
+                let addr = if repr.type_id == ADDRESS_TYPE_TOR {
+                    NetworkAddress::tor(&host, addr.port)
+                } else {
+                    NetworkAddress::i2p(&host, addr.port)
+                };
+                addr.ok_or_else(|| D::Error::custom(format!("invalid hostname {}", host)))
+            }
+            type_id => Err(D::Error::custom(format!("unknown address type {}", type_id))),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use portable_storage::{StorageEntry, from_section, to_section};
+
+    use super::*;
+
+    const ONION: &str = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion";
+    const I2P: &str = "ukeu3k5oycgaauneqgtnvselmt4yemvoilkln7jpvamvfx7dnkdq.b32.i2p";
+
+    #[derive(Serialize, Deserialize)]
+    struct Entry {
+        adr: NetworkAddress,
+    }
+
+    fn roundtrip(addr: NetworkAddress) -> NetworkAddress {
+        let section = to_section(&Entry { adr: addr }).unwrap();
+        from_section::<Entry>(section).unwrap().adr
+    }
+
+    #[test]
+    fn addresses_are_parsed() {
+        let addr: NetworkAddress = format!("{}:18083", ONION).parse().unwrap();
+        assert_eq!(addr.host(), Some(ONION));
+        assert_eq!(addr.port(), 18083);
+        assert_eq!(addr.socket_addr(), None);
+        assert_eq!(addr.to_string(), format!("{}:18083", ONION));
+
+        let addr: NetworkAddress = format!("{}:0", I2P.to_uppercase()).parse().unwrap();
+        assert_eq!(addr, NetworkAddress::i2p(I2P, 0).unwrap());
+
+        let addr: NetworkAddress = "[2001:db8::1]:18080".parse().unwrap();
+        assert_eq!(addr.socket_addr(), Some("[2001:db8::1]:18080".parse().unwrap()));
+
+        assert!("example.com:18080".parse::<NetworkAddress>().is_err());
+        assert!("expyuzz4wqqyqhjn.onion:18080".parse::<NetworkAddress>().is_err());
+    }
+
+    #[test]
+    fn ipv4_is_serialized_like_monerod() {
+        let addr = NetworkAddress::from("1.2.3.4:18080".parse::<SocketAddr>().unwrap());
+        let section = to_section(&Entry { adr: addr.clone() }).unwrap();
+
+        let adr = match section["adr"] {
+            StorageEntry::Section(ref adr) => adr.clone(),
+            _ => panic!("the address isn't a section"),
+        };
+        match adr["type"] {
+            StorageEntry::U8(type_id) => assert_eq!(type_id, ADDRESS_TYPE_IPV4),
+            _ => panic!("the type isn't an u8"),
+        }
+        let inner = match adr["addr"] {
+            StorageEntry::Section(ref inner) => inner.clone(),
+            _ => panic!("the inner address isn't a section"),
+        };
+        match (&inner["m_ip"], &inner["m_port"]) {
+            // the ip is kept in network byte order.
+            (&StorageEntry::U32(ip), &StorageEntry::U16(port)) => {
+                assert_eq!(ip, 0x04030201);
+                assert_eq!(port, 18080);
+            }
+            _ => panic!("invalid inner address"),
+        }
+
+        assert_eq!(roundtrip(addr.clone()), addr);
+    }
+
+    #[test]
+    fn all_types_roundtrip() {
+        let addrs = vec!["[2001:db8::1]:18080".parse().unwrap(),
+                         NetworkAddress::tor(ONION, 18083).unwrap(),
+                         NetworkAddress::i2p(I2P, 0).unwrap()];
+
+        for addr in addrs {
+            assert_eq!(roundtrip(addr.clone()), addr);
+        }
+    }
+}
@@ -0,0 +1,335 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::str::FromStr;
+
+use types::NetworkAddress;
+
+/// An IP subnet, a single address is a subnet with the full prefix length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subnet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    pub fn new(addr: IpAddr, prefix: u8) -> Subnet {
+        let prefix = ::std::cmp::min(prefix, max_prefix(&addr));
+        let addr = match addr {
+            IpAddr::V4(addr) => {
+                let mut octets = addr.octets();
+                mask(&mut octets, prefix);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            IpAddr::V6(addr) => {
+                let mut octets = addr.octets();
+                mask(&mut octets, prefix);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+        };
+
+        Subnet { addr, prefix }
+    }
+
+    /// A subnet containing only the given address.
+    pub fn host(addr: IpAddr) -> Subnet {
+        Subnet::new(addr, max_prefix(&addr))
+    }
+
+    /// The subnet used to group the peers of the same network, /24 for IPv4
+    /// and /64 for IPv6.
+    pub fn of(addr: IpAddr) -> Subnet {
+        match addr {
+            IpAddr::V4(_) => Subnet::new(addr, 24),
+            IpAddr::V6(_) => Subnet::new(addr, 64),
+        }
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, *addr) {
+            (IpAddr::V4(_), IpAddr::V4(_)) |
+            (IpAddr::V6(_), IpAddr::V6(_)) => Subnet::new(*addr, self.prefix) == *self,
+            _ => false,
+        }
+    }
+}
+
+fn max_prefix(addr: &IpAddr) -> u8 {
+    match *addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn mask(octets: &mut [u8], prefix: u8) {
+    let mut bits = prefix as usize;
+    for octet in octets.iter_mut() {
+        if bits >= 8 {
+            bits -= 8;
+        } else {
+            *octet &= !(0xffu8 >> bits);
+            bits = 0;
+        }
+    }
+}
+
+impl Display for Subnet {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Subnet, String> {
+        let mut parts = s.splitn(2, '/');
+        let addr: IpAddr = parts
+            .next()
+            .unwrap_or("")
+            .parse()
+            .map_err(|e| format!("invalid address {}: {}", s, e))?;
+
+        match parts.next() {
+            Some(prefix) => {
+                let prefix = prefix
+                    .parse()
+                    .map_err(|e| format!("invalid prefix {}: {}", s, e))?;
+                if prefix > max_prefix(&addr) {
+                    return Err(format!("invalid prefix {}", s));
+                }
+
+                Ok(Subnet::new(addr, prefix))
+            }
+            None => Ok(Subnet::host(addr)),
+        }
+    }
+}
+
+/// What a ban applies to, an IP subnet or the host of an anonymity network
+/// address.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BanTarget {
+    Subnet(Subnet),
+    Host(String),
+}
+
+impl BanTarget {
+    /// The ban of a single peer.
+    pub fn of(addr: &NetworkAddress) -> BanTarget {
+        match addr.socket_addr() {
+            Some(addr) => BanTarget::Subnet(Subnet::host(addr.ip())),
+            None => BanTarget::Host(addr.host().unwrap_or("").to_string()),
+        }
+    }
+
+    pub fn contains(&self, addr: &NetworkAddress) -> bool {
+        match (self, addr.socket_addr()) {
+            (&BanTarget::Subnet(ref subnet), Some(addr)) => subnet.contains(&addr.ip()),
+            (&BanTarget::Host(ref host), None) => addr.host() == Some(host),
+            _ => false,
+        }
+    }
+
+    pub fn contains_ip(&self, addr: &IpAddr) -> bool {
+        match *self {
+            BanTarget::Subnet(ref subnet) => subnet.contains(addr),
+            BanTarget::Host(_) => false,
+        }
+    }
+}
+
+impl From<Subnet> for BanTarget {
+    fn from(subnet: Subnet) -> BanTarget {
+        BanTarget::Subnet(subnet)
+    }
+}
+
+impl Display for BanTarget {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match *self {
+            BanTarget::Subnet(ref subnet) => subnet.fmt(fmt),
+            BanTarget::Host(ref host) => host.fmt(fmt),
+        }
+    }
+}
+
+impl FromStr for BanTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BanTarget, String> {
+        // the port isn't part of the ban.
+        match format!("{}:0", s).parse::<NetworkAddress>() {
+            Ok(ref addr) if addr.host().is_some() => {
+                Ok(BanTarget::Host(addr.host().unwrap_or("").to_string()))
+            }
+            _ => s.parse().map(BanTarget::Subnet),
+        }
+    }
+}
+
+/// Banned subnets and hosts with the time (in seconds since the unix
+/// epoch) their ban ends.
+#[derive(Debug, Default)]
+pub struct BanList {
+    bans: HashMap<BanTarget, u64>,
+}
+
+impl BanList {
+    pub fn new() -> BanList {
+        BanList::default()
+    }
+
+    /// Reads a ban list, it's empty if the file doesn't exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<BanList> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(BanList::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut ban_list = BanList::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let mut parts = line.split_whitespace();
+            let (target, until) = match (parts.next(), parts.next()) {
+                (Some(target), Some(until)) => (target, until),
+                (None, _) => continue,
+                _ => return Err(invalid_data(format!("invalid ban list line: {}", line))),
+            };
+
+            let target = target.parse().map_err(invalid_data)?;
+            let until = until
+                .parse()
+                .map_err(|_| invalid_data(format!("invalid ban time: {}", line)))?;
+            ban_list.ban(target, until);
+        }
+
+        Ok(ban_list)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        for (target, until) in self.bans.iter() {
+            writeln!(file, "{} {}", target, until)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.bans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bans.is_empty()
+    }
+
+    /// Bans a subnet or host until the given time, an existing ban is only
+    /// extended.
+    pub fn ban(&mut self, target: BanTarget, until: u64) {
+        let entry = self.bans.entry(target).or_insert(until);
+        if *entry < until {
+            *entry = until;
+        }
+    }
+
+    pub fn unban(&mut self, target: &BanTarget) -> bool {
+        self.bans.remove(target).is_some()
+    }
+
+    pub fn is_banned(&self, addr: &NetworkAddress, now: u64) -> bool {
+        self.bans
+            .iter()
+            .any(|(target, until)| *until > now && target.contains(addr))
+    }
+
+    /// Removes the bans that already ended.
+    pub fn remove_expired(&mut self, now: u64) {
+        self.bans.retain(|_, until| *until > now);
+    }
+}
+
+fn invalid_data<E: Into<Box<::std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::env;
+    use std::fs;
+    use std::net::IpAddr;
+
+    use super::*;
+
+    const ONION: &str = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion";
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn addr(s: &str) -> NetworkAddress {
+        format!("{}:18080", s).parse().unwrap()
+    }
+
+    #[test]
+    fn subnet() {
+        let subnet = Subnet::of(ip("192.168.1.20"));
+        assert_eq!(subnet.to_string(), "192.168.1.0/24");
+        assert!(subnet.contains(&ip("192.168.1.1")));
+        assert!(!subnet.contains(&ip("192.168.2.1")));
+        assert!(!subnet.contains(&ip("::1")));
+
+        assert_eq!("10.0.0.0/9".parse(), Ok(Subnet::new(ip("10.127.0.0"), 9)));
+        assert_eq!("2001:db8::1".parse(), Ok(Subnet::host(ip("2001:db8::1"))));
+        assert!(Subnet::of(ip("2001:db8::1")).contains(&ip("2001:db8::ffff:1")));
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn ban_list() {
+        let path = env::temp_dir().join(format!("xmr-ban-list-{}", ::std::process::id()));
+
+        let mut ban_list = BanList::new();
+        ban_list.ban(Subnet::host(ip("10.0.0.1")).into(), 100);
+        ban_list.ban(Subnet::of(ip("192.168.1.1")).into(), 200);
+        ban_list.ban(BanTarget::of(&addr(ONION)), 100);
+        ban_list.save(&path).unwrap();
+
+        let mut ban_list = BanList::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(ban_list.len(), 3);
+
+        assert!(ban_list.is_banned(&addr("10.0.0.1"), 50));
+        assert!(!ban_list.is_banned(&addr("10.0.0.2"), 50));
+        assert!(ban_list.is_banned(&addr("192.168.1.100"), 150));
+        assert!(!ban_list.is_banned(&addr("10.0.0.1"), 150));
+        assert!(ban_list.is_banned(&addr(ONION), 50));
+
+        ban_list.remove_expired(150);
+        assert_eq!(ban_list.len(), 1);
+        assert!(ban_list.unban(&Subnet::of(ip("192.168.1.1")).into()));
+        assert!(ban_list.is_empty());
+
+        assert!(BanList::load(&path).unwrap().is_empty());
+    }
+}
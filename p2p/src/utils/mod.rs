@@ -14,6 +14,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+mod ban_list;
+mod peer_store;
 mod peerlist;
 
-pub use self::peerlist::Peerlist;
+pub use self::ban_list::{BanList, BanTarget, Subnet};
+pub use self::peer_store::{ANCHOR_LIST_LIMIT, GRAY_PEER_MAX_AGE, WHITE_PEER_MAX_AGE,
+                            PeerStore};
+pub use self::peerlist::{GRAY_LIST_LIMIT, MAX_PEERS_IN_PEERLIST, WHITE_LIST_LIMIT, PeerInfo,
+                          Peerlist};
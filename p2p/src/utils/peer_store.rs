@@ -0,0 +1,238 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use types::NetworkAddress;
+use utils::{GRAY_LIST_LIMIT, WHITE_LIST_LIMIT, PeerInfo, Peerlist};
+
+/// Maximum number of anchor peers kept between sessions.
+pub const ANCHOR_LIST_LIMIT: usize = 2;
+/// Seconds a white peer is kept since it was last seen.
+pub const WHITE_PEER_MAX_AGE: i64 = 30 * 24 * 60 * 60;
+/// Seconds a gray peer is kept since it was last seen.
+pub const GRAY_PEER_MAX_AGE: i64 = 7 * 24 * 60 * 60;
+
+/// The peer lists kept between sessions. The anchors are the outbound
+/// peers we were connected to, they are tried first on startup.
+#[derive(Debug)]
+pub struct PeerStore {
+    pub white: Peerlist,
+    pub gray: Peerlist,
+    pub anchors: Peerlist,
+}
+
+impl Default for PeerStore {
+    fn default() -> PeerStore {
+        PeerStore::new()
+    }
+}
+
+impl PeerStore {
+    pub fn new() -> PeerStore {
+        PeerStore {
+            white: Peerlist::new(WHITE_LIST_LIMIT),
+            gray: Peerlist::new(GRAY_LIST_LIMIT),
+            anchors: Peerlist::new(ANCHOR_LIST_LIMIT),
+        }
+    }
+
+    /// Reads a peer store, it's empty if the file doesn't exist. Malformed
+    /// lines, like a truncated last line, are skipped.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<PeerStore> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(PeerStore::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut peer_store = PeerStore::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let (list, addr, entry) = match parse_line(&line) {
+                Ok(Some(peer)) => peer,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("skipping peer store line {:?}: {}", line, e);
+                    continue;
+                }
+            };
+
+            let list = match list {
+                "white" => &mut peer_store.white,
+                "gray" => &mut peer_store.gray,
+                _ => &mut peer_store.anchors,
+            };
+            list.insert(addr, entry);
+        }
+
+        Ok(peer_store)
+    }
+
+    /// Writes the peer store, the file is replaced only once the new one is
+    /// complete so a crash doesn't leave it half written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let mut file = File::create(&tmp_path)?;
+        let lists = [("white", &self.white), ("gray", &self.gray), ("anchor", &self.anchors)];
+        for &(name, list) in lists.iter() {
+            for (addr, entry) in list.list.iter() {
+                writeln!(file, "{} {} {} {}", name, addr, entry.id, entry.last_seen)?;
+            }
+        }
+        file.sync_all()?;
+
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Forgets the peers that weren't seen for too long and the gray peers
+    /// that are already in the white list.
+    pub fn prune(&mut self, now: i64) {
+        self.white.retain(|_, entry| now - entry.last_seen <= WHITE_PEER_MAX_AGE);
+
+        let white = &self.white;
+        self.gray
+            .retain(|addr, entry| {
+                        now - entry.last_seen <= GRAY_PEER_MAX_AGE && !white.contains(addr)
+                    });
+    }
+}
+
+/// Parses a `list address id last_seen` line, `None` if it's empty.
+fn parse_line(line: &str) -> Result<Option<(&str, NetworkAddress, PeerInfo)>, String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.is_empty() {
+        return Ok(None);
+    }
+    if parts.len() != 4 {
+        return Err("wrong number of fields".to_owned());
+    }
+
+    match parts[0] {
+        "white" | "gray" | "anchor" => {}
+        _ => return Err("invalid peer list".to_owned()),
+    }
+    let addr: NetworkAddress = parts[1]
+        .parse()
+        .map_err(|_| "invalid peer address".to_owned())?;
+    let id = u64::from_str_radix(parts[2], 16).map_err(|_| "invalid peer id".to_owned())?;
+    let last_seen = parts[3]
+        .parse()
+        .map_err(|_| "invalid last seen time".to_owned())?;
+
+    let entry = PeerInfo {
+        id: id.into(),
+        last_seen,
+    };
+
+    Ok(Some((parts[0], addr, entry)))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn entry(port: u16, last_seen: i64) -> (NetworkAddress, PeerInfo) {
+        let addr = format!("10.0.0.1:{}", port).parse().unwrap();
+        let entry = PeerInfo {
+            id: (0xabcd0000 + port as u64).into(),
+            last_seen,
+        };
+
+        (addr, entry)
+    }
+
+    #[test]
+    fn peer_store() {
+        let path = env::temp_dir().join(format!("xmr-peer-store-{}", ::std::process::id()));
+
+        let mut peer_store = PeerStore::new();
+        for &(port, last_seen) in [(1, 1000), (2, 10)].iter() {
+            let (addr, entry) = entry(port, last_seen);
+            peer_store.white.insert(addr, entry);
+        }
+        for &(port, last_seen) in [(2, 500), (3, 900), (4, 10)].iter() {
+            let (addr, entry) = entry(port, last_seen);
+            peer_store.gray.insert(addr, entry);
+        }
+        let (addr, anchor) = entry(1, 1000);
+        peer_store.anchors.insert(addr.clone(), anchor.clone());
+        let ipv6_addr: NetworkAddress = "[2001:db8::1]:18080".parse().unwrap();
+        peer_store.anchors.insert(ipv6_addr.clone(), anchor.clone());
+        let onion_addr: NetworkAddress =
+            "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion:18083"
+                .parse()
+                .unwrap();
+        peer_store.gray.insert(onion_addr.clone(), anchor.clone());
+        peer_store.save(&path).unwrap();
+
+        let mut peer_store = PeerStore::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(peer_store.white.len(), 2);
+        assert_eq!(peer_store.gray.len(), 4);
+        assert!(peer_store.gray.contains(&onion_addr));
+        assert_eq!(peer_store.anchors.list.get(&addr), Some(&anchor));
+        assert_eq!(peer_store.anchors.list.get(&ipv6_addr), Some(&anchor));
+
+        peer_store.prune(WHITE_PEER_MAX_AGE + 100);
+        assert!(peer_store.white.contains(&entry(1, 0).0));
+        assert!(!peer_store.white.contains(&entry(2, 0).0));
+        assert_eq!(peer_store.gray.len(), 0);
+
+        assert!(PeerStore::load(&path).unwrap().white.is_empty());
+    }
+
+    #[test]
+    fn truncated_line_is_skipped() {
+        let path = env::temp_dir().join(format!("xmr-truncated-peer-store-{}",
+                                                ::std::process::id()));
+
+        let mut peer_store = PeerStore::new();
+        for port in 1..4 {
+            let (addr, entry) = entry(port, 1000);
+            peer_store.white.insert(addr, entry);
+        }
+        peer_store.save(&path).unwrap();
+
+        let mut contents = fs::read_to_string(&path).unwrap();
+        let len = contents.trim_end().len() - 4;
+        contents.truncate(len);
+        fs::write(&path, contents).unwrap();
+
+        let peer_store = PeerStore::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(peer_store.white.len(), 2);
+    }
+
+    #[test]
+    fn gray_duplicates_are_pruned() {
+        let mut peer_store = PeerStore::new();
+        let (addr, entry) = entry(1, 100);
+        peer_store.white.insert(addr.clone(), entry.clone());
+        peer_store.gray.insert(addr.clone(), entry);
+
+        peer_store.prune(100);
+        assert!(peer_store.white.contains(&addr));
+        assert!(peer_store.gray.is_empty());
+    }
+}
@@ -14,37 +14,171 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::{HashMap, LinkedList};
-use std::net::SocketAddr;
+use std::collections::HashMap;
 
-use portable_storage_utils::stl::StlLinkedList;
+use rand::{Rng, thread_rng};
 
-use types::PeerlistEntry;
+use types::{NetworkAddress, PeerId, PeerlistEntry};
 
+/// Maximum number of peers in the white list.
+pub const WHITE_LIST_LIMIT: usize = 1000;
+/// Maximum number of peers in the gray list.
+pub const GRAY_LIST_LIMIT: usize = 5000;
+/// Maximum number of peers sent or accepted in a peerlist.
+pub const MAX_PEERS_IN_PEERLIST: usize = 250;
+
+/// A peer of a `Peerlist`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub id: PeerId,
+    pub last_seen: i64,
+}
+
+/// A list of peers, the white list has the peers we connected to and the
+/// gray list the ones other peers told us about.
 #[derive(Debug)]
 pub struct Peerlist {
-    pub list: HashMap<SocketAddr, PeerlistEntry>,
+    pub list: HashMap<NetworkAddress, PeerInfo>,
+    limit: usize,
 }
 
 impl Peerlist {
-    pub fn new() -> Peerlist {
-        Peerlist { list: HashMap::new() }
+    pub fn new(limit: usize) -> Peerlist {
+        Peerlist {
+            list: HashMap::new(),
+            limit,
+        }
     }
 
-    pub fn insert(&mut self, address: SocketAddr, entry: PeerlistEntry) {
+    /// Inserts or updates a peer, the least recently seen peer is dropped
+    /// if the list is full.
+    pub fn insert(&mut self, address: NetworkAddress, entry: PeerInfo) {
+        if !self.list.contains_key(&address) && self.list.len() >= self.limit {
+            let oldest = self.list
+                .iter()
+                .min_by_key(|&(_, entry)| entry.last_seen)
+                .map(|(address, _)| address.clone());
+            if let Some(oldest) = oldest {
+                self.list.remove(&oldest);
+            }
+        }
+
         self.list.insert(address, entry);
     }
 
-    pub fn remove(&mut self, addr: &SocketAddr) -> Option<PeerlistEntry> {
+    pub fn remove(&mut self, addr: &NetworkAddress) -> Option<PeerInfo> {
         self.list.remove(&addr)
     }
 
-    pub fn stl_peerlist(&self) -> StlLinkedList<PeerlistEntry> {
-        let mut ll = LinkedList::new();
-        for peer in self.list.iter() {
-            ll.push_back(peer.1.clone())
+    /// Keeps only the peers accepted by `filter`.
+    pub fn retain<F>(&mut self, filter: F)
+        where F: FnMut(&NetworkAddress, &mut PeerInfo) -> bool
+    {
+        self.list.retain(filter)
+    }
+
+    pub fn contains(&self, addr: &NetworkAddress) -> bool {
+        self.list.contains_key(addr)
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// A random peer of the ones accepted by `filter`.
+    pub fn random<F>(&self, filter: F) -> Option<NetworkAddress>
+        where F: Fn(&NetworkAddress) -> bool
+    {
+        let candidates: Vec<NetworkAddress> = self.list
+            .keys()
+            .filter(|addr| filter(addr))
+            .cloned()
+            .collect();
+        thread_rng().choose(&candidates).cloned()
+    }
+
+    /// The most recently seen peers, up to `MAX_PEERS_IN_PEERLIST`.
+    pub fn peerlist(&self) -> Vec<PeerlistEntry> {
+        let mut entries: Vec<PeerlistEntry> = self.list
+            .iter()
+            .map(|(addr, peer)| {
+                     PeerlistEntry {
+                         adr: addr.clone(),
+                         id: peer.id,
+                         last_seen: peer.last_seen,
+                     }
+                 })
+            .collect();
+        entries.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        entries.truncate(MAX_PEERS_IN_PEERLIST);
+        entries
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::net::SocketAddrV4;
+
+    use super::*;
+
+    fn entry(port: u16, last_seen: i64) -> (NetworkAddress, PeerInfo) {
+        let addr: SocketAddrV4 = format!("10.0.0.1:{}", port).parse().unwrap();
+        let entry = PeerInfo {
+            id: (port as u64).into(),
+            last_seen,
+        };
+
+        (NetworkAddress::Ipv4(addr), entry)
+    }
+
+    #[test]
+    fn full_list_drops_least_recently_seen() {
+        let mut peerlist = Peerlist::new(2);
+        for &(port, last_seen) in [(1, 10), (2, 5), (3, 20)].iter() {
+            let (addr, entry) = entry(port, last_seen);
+            peerlist.insert(addr, entry);
         }
 
-        ll.into()
+        assert_eq!(peerlist.len(), 2);
+        assert!(peerlist.contains(&entry(1, 0).0));
+        assert!(!peerlist.contains(&entry(2, 0).0));
+        assert!(peerlist.contains(&entry(3, 0).0));
+    }
+
+    #[test]
+    fn peerlist_is_bounded() {
+        let mut peerlist = Peerlist::new(GRAY_LIST_LIMIT);
+        for port in 0..(MAX_PEERS_IN_PEERLIST as u16 + 10) {
+            let (addr, entry) = entry(port, port as i64);
+            peerlist.insert(addr, entry);
+        }
+
+        let entries = peerlist.peerlist();
+        assert_eq!(entries.len(), MAX_PEERS_IN_PEERLIST);
+        assert_eq!(entries.first().map(|entry| entry.last_seen),
+                   Some(MAX_PEERS_IN_PEERLIST as i64 + 9));
+    }
+
+    #[test]
+    fn peerlist_has_all_networks() {
+        let mut peerlist = Peerlist::new(WHITE_LIST_LIMIT);
+        let (addr, entry) = entry(1, 3);
+        peerlist.insert(addr.clone(), entry.clone());
+        let ipv6: NetworkAddress = "[2001:db8::1]:18080".parse().unwrap();
+        peerlist.insert(ipv6.clone(), PeerInfo { last_seen: 2, ..entry.clone() });
+        let onion = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion:18083";
+        let onion: NetworkAddress = onion.parse().unwrap();
+        peerlist.insert(onion.clone(), PeerInfo { last_seen: 1, ..entry });
+
+        let addrs: Vec<NetworkAddress> = peerlist
+            .peerlist()
+            .into_iter()
+            .map(|entry| entry.adr)
+            .collect();
+        assert_eq!(addrs, vec![addr, ipv6, onion]);
     }
 }
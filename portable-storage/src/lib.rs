@@ -281,7 +281,7 @@ impl Array {
     pub fn push(&mut self, entry: StorageEntry) -> std::result::Result<(), ()> {
         match self.serialize_type {
             Some(serialize_type) => {
-                if serialize_type & SERIALIZE_FLAG_ARRAY != entry.serialize_type() {
+                if serialize_type & !SERIALIZE_FLAG_ARRAY != entry.serialize_type() {
                     return Err(());
                 }
             }
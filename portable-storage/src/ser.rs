@@ -170,7 +170,9 @@ impl SerializeStruct for KvSerializer {
     }
 
     fn skip_field(&mut self, _key: &'static str) -> Result<(), Self::Error> {
-        Err(Error::custom("fields can't be skipped"))
+        // skipped fields are left out of the section, like epee does with
+        // empty containers.
+        Ok(())
     }
 }
 
@@ -230,7 +232,9 @@ impl SerializeStruct for EntryKvSerializer {
     }
 
     fn skip_field(&mut self, _key: &'static str) -> Result<(), Self::Error> {
-        Err(Error::custom("fields can't be skipped"))
+        // skipped fields are left out of the section, like epee does with
+        // empty containers.
+        Ok(())
     }
 }
 
@@ -7,6 +7,7 @@
 // except according to those terms.
 
 use std::fmt::{self, Debug, Formatter};
+use std::str::FromStr;
 
 use bytes::{Buf, IntoBuf, LittleEndian};
 use serde;
@@ -18,7 +19,7 @@ use format::{Deserialize, DeserializerStream, Error, Serialize, SerializerStream
 pub const H256_LENGTH: usize = 32;
 
 /// A 256-bit hash.
-#[derive(Default, Clone, Eq, PartialEq)]
+#[derive(Default, Clone, Eq, PartialEq, Hash)]
 pub struct H256(pub [u8; H256_LENGTH]);
 
 impl H256 {
@@ -148,6 +149,25 @@ impl From<[u8; 32]> for H256 {
     }
 }
 
+impl FromStr for H256 {
+    type Err = String;
+
+    /// Parses a hash from it's hexadecimal representation.
+    fn from_str(s: &str) -> Result<H256, String> {
+        if !s.is_ascii() || s.len() != H256_LENGTH * 2 {
+            return Err(format!("invalid hash {}", s));
+        }
+
+        let mut h = H256::new();
+        for (i, b) in h.0.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)
+                .map_err(|e| format!("invalid hash {}: {}", s, e))?;
+        }
+
+        Ok(h)
+    }
+}
+
 impl AsRef<[u8]> for H256 {
     fn as_ref(&self) -> &[u8] {
         self.as_bytes()
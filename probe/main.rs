@@ -0,0 +1,159 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+//! Asks a Monero node for its height, peer id and peerlist.
+
+#[macro_use]
+extern crate clap;
+
+extern crate env_logger;
+extern crate failure;
+
+extern crate tokio_core;
+
+extern crate xmr_chain as chain;
+extern crate xmr_levin as levin;
+extern crate xmr_network as network;
+extern crate xmr_p2p as p2p;
+
+use std::net::SocketAddr;
+use std::process;
+use std::time::Duration;
+
+use clap::ArgMatches;
+use failure::Error;
+use tokio_core::reactor::Core;
+
+use chain::IndexedBlock;
+use levin::net::{Socks5Auth, Socks5Proxy, TargetAddr};
+use network::Network;
+use p2p::Probe;
+use p2p::types::{BasicNodeData, PeerlistEntry};
+use p2p::types::cn::CoreSyncData;
+
+fn main() {
+    env_logger::init();
+
+    let matches = clap_app!(xmr_probe =>
+        (version: "0.1.0")
+        (author: "Jean Pierre Dudey <jeandudey@hotmail.com>")
+        (about: "Asks a Monero node about itself")
+        (@arg address: +required "Address or hostname of the node")
+        (@arg testnet: --testnet "Use the test network")
+        (@arg timeout: --timeout +takes_value "Seconds to wait for each answer")
+        (@arg ping: --ping "Ping the node before the handshake")
+        (@arg support_flags: --("support-flags") "Ask for the support flags")
+        (@arg timed_sync: --("timed-sync") "Send a TimedSync after the handshake")
+        (@arg proxy: --proxy +takes_value "Connect through a SOCKS5 proxy")
+        (@arg proxy_auth: --("proxy-auth") +takes_value "`username:password` of the proxy")
+    )
+            .get_matches();
+
+    if let Err(e) = run(&matches) {
+        println!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let addr = value_t!(matches.value_of("address"), TargetAddr)?;
+    let network = if matches.is_present("testnet") {
+        Network::Testnet
+    } else {
+        Network::Mainnet
+    };
+    let timeout = Duration::from_secs(value_t!(matches.value_of("timeout"), u64).unwrap_or(10));
+    let proxy = match matches.value_of("proxy") {
+        Some(_) => {
+            let auth = match matches.value_of("proxy_auth") {
+                Some(auth) => Some(auth.parse::<Socks5Auth>()?),
+                None => None,
+            };
+            Some(Socks5Proxy {
+                     addr: value_t!(matches.value_of("proxy"), SocketAddr)?,
+                     auth,
+                 })
+        }
+        None => None,
+    };
+
+    let mut core = Core::new()?;
+    let handle = core.handle();
+    let probe = core.run(Probe::connect(&addr, network, timeout, proxy, &handle))?;
+
+    if matches.is_present("ping") {
+        let response = core.run(probe.ping())?;
+        println!("ping: {}, peer id {}",
+                 if response.is_ok() { "ok" } else { "invalid status" },
+                 response.peer_id);
+    }
+
+    // We claim to only have the genesis block, so the node doesn't ask us
+    // for blocks.
+    let sync_data = genesis_sync_data(network);
+
+    let response = core.run(probe.handshake(sync_data.clone()))?;
+    print_node(&response.node_data, &response.payload_data);
+    print_peerlist(response.local_peerlist_new.iter());
+
+    if matches.is_present("support_flags") {
+        let response = core.run(probe.support_flags())?;
+        println!("support flags: {:#x}", response.support_flags);
+    }
+
+    if matches.is_present("timed_sync") {
+        let response = core.run(probe.timed_sync(sync_data))?;
+        println!("timed sync: height {}, local time {}",
+                 response.payload_data.current_height,
+                 response.local_time);
+        print_peerlist(response.local_peerlist_new.iter());
+    }
+
+    probe.close();
+    Ok(())
+}
+
+fn genesis_sync_data(network: Network) -> CoreSyncData {
+    let genesis_block: IndexedBlock = network.genesis_block().into();
+    CoreSyncData {
+        cumulative_difficulty: 1,
+        current_height: 1,
+        top_id: genesis_block.id().clone(),
+        top_version: 1,
+    }
+}
+
+fn print_node(node_data: &BasicNodeData, sync_data: &CoreSyncData) {
+    println!("peer id: {}", node_data.peer_id);
+    println!("port: {}", node_data.my_port);
+    println!("local time: {}", node_data.local_time);
+    println!("height: {}", sync_data.current_height);
+    println!("cumulative difficulty: {}", sync_data.cumulative_difficulty);
+    println!("top block: {:?} (version {})", sync_data.top_id, sync_data.top_version);
+}
+
+fn print_peerlist<'a, I>(peerlist: I)
+    where I: ExactSizeIterator<Item = &'a PeerlistEntry>
+{
+    println!("peerlist: {} peers", peerlist.len());
+    for entry in peerlist {
+        println!("  {} {} last seen {}",
+                 entry.adr,
+                 entry.id,
+                 entry.last_seen);
+    }
+}
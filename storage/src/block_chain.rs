@@ -19,7 +19,12 @@ use primitives::H256;
 
 /// A BlockChain interface.
 pub trait BlockChain {
-    fn insert(&self, block: IndexedBlock) -> Result<(), String>;
+    /// Inserts a block mined at `difficulty`, the cumulative difficulty of
+    /// the chain up to it is stored too.
+    fn insert(&self, block: IndexedBlock, difficulty: u64) -> Result<(), String>;
 
     fn canonize(&self, id: &H256) -> Result<(), String>;
+
+    /// Removes the best block from the canonical chain, returns it's id.
+    fn decanonize(&self) -> Result<H256, String>;
 }
@@ -20,6 +20,9 @@ use block_ref::BlockRef;
 
 pub trait BlockProvider {
     fn block_id(&self, height: u64) -> Option<H256>;
+
+    /// The cumulative difficulty of the chain up to the block.
+    fn cumulative_difficulty(&self, id: &H256) -> Option<u64>;
 }
 
 pub trait IndexedBlockProvider: BlockProvider {
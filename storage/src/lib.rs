@@ -15,16 +15,21 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 extern crate xmr_chain as chain;
+extern crate xmr_keys as keys;
 extern crate xmr_primitives as primitives;
 
 mod best_block;
 mod block_chain;
 mod block_provider;
 mod block_ref;
+mod key_image_provider;
 mod store;
+mod transaction_provider;
 
 pub use best_block::BestBlock;
 pub use block_chain::BlockChain;
 pub use block_provider::{BlockProvider, IndexedBlockProvider};
 pub use block_ref::BlockRef;
+pub use key_image_provider::KeyImageProvider;
 pub use store::{AsSubstore, CanonStore, Store, SharedStore};
+pub use transaction_provider::TransactionProvider;
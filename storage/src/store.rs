@@ -21,6 +21,8 @@ use primitives::H256;
 use best_block::BestBlock;
 use block_chain::BlockChain;
 use block_provider::{BlockProvider, IndexedBlockProvider};
+use key_image_provider::KeyImageProvider;
+use transaction_provider::TransactionProvider;
 
 pub trait CanonStore: Store {
     fn as_store(&self) -> &Store;
@@ -38,16 +40,29 @@ pub trait Store: AsSubstore {
 }
 
 /// Allows casting Arc<Store> to reference to any substore type
-pub trait AsSubstore: BlockChain + IndexedBlockProvider {
+pub trait AsSubstore
+    : BlockChain + IndexedBlockProvider + TransactionProvider + KeyImageProvider {
     fn as_block_provider(&self) -> &BlockProvider;
+
+    fn as_transaction_provider(&self) -> &TransactionProvider;
+
+    fn as_key_image_provider(&self) -> &KeyImageProvider;
 }
 
 impl<T> AsSubstore for T
-    where T: BlockChain + IndexedBlockProvider
+    where T: BlockChain + IndexedBlockProvider + TransactionProvider + KeyImageProvider
 {
     fn as_block_provider(&self) -> &BlockProvider {
         &*self
     }
+
+    fn as_transaction_provider(&self) -> &TransactionProvider {
+        &*self
+    }
+
+    fn as_key_image_provider(&self) -> &KeyImageProvider {
+        &*self
+    }
 }
 
 pub type SharedStore = Arc<CanonStore + Send + Sync>;
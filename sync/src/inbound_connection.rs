@@ -16,6 +16,7 @@
 
 use p2p::protocol::InboundSyncConnection;
 
+use p2p::types::cn::CoreSyncData;
 use p2p::types::cn::cmd::{NewBlock, NewFluffyBlock, NewTransactions, RequestChain,
                           RequestFluffyMissingTx, RequestGetObjects, ResponseChainEntry,
                           ResponseGetObjects};
@@ -83,4 +84,9 @@ impl InboundSyncConnection for InboundConnection {
         self.local_node
             .on_support_flags(self.peer_index, arg)
     }
+
+    fn on_sync_data(&self, arg: &CoreSyncData) {
+        self.local_node
+            .on_sync_data(self.peer_index, arg)
+    }
 }
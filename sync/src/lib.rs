@@ -14,16 +14,28 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+extern crate failure;
+#[macro_use]
+extern crate failure_derive;
+extern crate futures;
+extern crate futures_cpupool;
 #[macro_use]
 extern crate log;
 extern crate parking_lot;
+extern crate rand;
 
+extern crate xmr_chain as chain;
 extern crate xmr_db as db;
+extern crate xmr_format as format;
 extern crate xmr_network as network;
 extern crate xmr_p2p as p2p;
+extern crate xmr_portable_storage_utils as portable_storage_utils;
 extern crate xmr_primitives as primitives;
 extern crate xmr_storage as storage;
+extern crate xmr_txpool as txpool;
+extern crate xmr_verification as verification;
 
+mod clock;
 mod connection_factory;
 mod inbound_connection;
 mod local_node;
@@ -32,8 +44,15 @@ mod synchronization_client;
 mod synchronization_client_core;
 mod synchronization_executor;
 mod synchronization_peers;
+mod synchronization_relay;
+mod synchronization_status;
+mod synchronization_verifier;
 mod types;
 
+#[cfg(test)]
+mod test_harness;
+
+pub use clock::{Clock, SystemClock, ManualClock};
 pub use connection_factory::ConnectionFactory;
 pub use inbound_connection::InboundConnection;
 pub use local_node::LocalNode;
@@ -42,12 +61,19 @@ pub use synchronization_client::{Client, SynchronizationClient};
 pub use synchronization_client_core::{ClientCore, SynchronizationClientCore};
 pub use synchronization_executor::{TaskExecutor, LocalSynchronizationTaskExecutor};
 pub use synchronization_peers::{Peers, Peer, PeersImpl};
-pub use types::{LocalNodeRef, ClientRef, ClientCoreRef, ExecutorRef, PeersRef, StorageRef, PeerIndex};
+pub use synchronization_relay::{StemGraph, Embargoes};
+pub use synchronization_status::{SyncStatus, SyncStats, SynchronizationState, PeerStatus};
+pub use synchronization_verifier::{BlockVerifier, Error as VerifierError, VerifiedBlock};
+pub use types::{LocalNodeRef, ClientRef, ClientCoreRef, ExecutorRef, PeersRef, MemoryPoolRef,
+                StorageRef, ClockRef, PeerIndex};
 
-pub fn create_local_node(storage: StorageRef, network: network::Network) -> LocalNodeRef {
+pub fn create_local_node(storage: StorageRef,
+                         network: network::Network,
+                         checkpoints: network::Checkpoints)
+                         -> LocalNodeRef {
     use std::sync::Arc;
 
-    Arc::new(LocalNode::new(storage, network))
+    Arc::new(LocalNode::new(storage, network, checkpoints, Arc::new(SystemClock)))
 }
 
 pub fn create_local_sync_node(local_node: LocalNodeRef) -> p2p::protocol::LocalSyncNodeRef {
@@ -15,34 +15,61 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::sync::Arc;
+use futures_cpupool::CpuPool;
 
-use network::Network;
+use chain::IndexedTransaction;
+use network::{Checkpoints, Network};
+use p2p::types::cn::CoreSyncData;
 use p2p::types::cn::cmd::{NewBlock, NewFluffyBlock, NewTransactions, RequestChain,
                           RequestFluffyMissingTx, RequestGetObjects, ResponseChainEntry,
                           ResponseGetObjects};
+use txpool::{Config as MemoryPoolConfig, Error as TxPoolError, TransactionPool};
 
 use synchronization_client::{Client, SynchronizationClient};
+use synchronization_client_core::SynchronizationClientCore;
 use synchronization_executor::LocalSynchronizationTaskExecutor;
 use synchronization_peers::PeersImpl;
-use types::{ClientRef, PeersRef, ExecutorRef, StorageRef, PeerIndex};
+use synchronization_verifier::BlockVerifier;
+use synchronization_status::SyncStatus;
+use types::{ClientRef, ClockRef, PeersRef, ExecutorRef, MemoryPoolRef, StorageRef, PeerIndex};
 
 pub struct LocalNode {
     client: ClientRef,
     executor: ExecutorRef,
     peers: PeersRef,
+    memory_pool: MemoryPoolRef,
+    clock: ClockRef,
 }
 
 impl LocalNode {
-    pub fn new(storage: StorageRef, network: Network) -> LocalNode {
+    pub fn new(storage: StorageRef,
+               network: Network,
+               checkpoints: Checkpoints,
+               clock: ClockRef)
+               -> LocalNode {
         let peers = Arc::new(PeersImpl::new());
+        let memory_pool = Arc::new(TransactionPool::new(storage.clone(),
+                                                        MemoryPoolConfig::default()));
         let executor = Arc::new(LocalSynchronizationTaskExecutor::new(peers.clone()));
-        let client =
-            Arc::new(SynchronizationClient::new(executor.clone(), storage, network, peers.clone()));
+        // Verification has it's own pool, the p2p pool threads block on the
+        // sync client.
+        let verification_pool = CpuPool::new_num_cpus();
+        let verifier = BlockVerifier::new(verification_pool, checkpoints.clone());
+        let core = SynchronizationClientCore::new(executor.clone(),
+                                                  storage,
+                                                  network,
+                                                  peers.clone(),
+                                                  memory_pool.clone(),
+                                                  checkpoints,
+                                                  clock.clone());
+        let client = Arc::new(SynchronizationClient::new(core, verifier));
 
         LocalNode {
             peers,
             executor,
             client,
+            memory_pool,
+            clock,
         }
     }
 
@@ -50,6 +77,10 @@ impl LocalNode {
         self.peers.clone()
     }
 
+    pub fn memory_pool(&self) -> MemoryPoolRef {
+        self.memory_pool.clone()
+    }
+
     pub fn on_connect(&self, peer_index: PeerIndex) {
         self.client.on_connect(peer_index);
     }
@@ -98,4 +129,24 @@ impl LocalNode {
         self.client
             .on_support_flags(peer_index, arg);
     }
+
+    pub fn on_sync_data(&self, peer_index: PeerIndex, arg: &CoreSyncData) {
+        self.client
+            .on_sync_data(peer_index, arg);
+    }
+
+    /// Should be called periodically to drive the timers.
+    pub fn on_timer(&self) {
+        self.client.on_timer(self.clock.now());
+    }
+
+    /// Adds a transaction created by us to the memory pool and relays it.
+    pub fn submit_transaction(&self, tx: IndexedTransaction) -> Result<(), TxPoolError> {
+        self.client.submit_transaction(tx)
+    }
+
+    /// The synchronization progress.
+    pub fn status(&self) -> SyncStatus {
+        self.client.status(self.clock.now())
+    }
 }
@@ -14,17 +14,80 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::VecDeque;
+
+use parking_lot::Mutex;
+
+use chain::{IndexedBlock, IndexedTransaction};
+use network::Checkpoints;
 use primitives::H256;
+use verification::{BlockError, DIFFICULTY_BLOCKS_COUNT, Difficulty, VerificationLevel,
+                   difficulty_target, is_valid_proof_of_work, miner_transaction_height,
+                   next_difficulty, verify_block_context, verify_block_context_free};
+
+use synchronization_verifier::verification_level;
+use types::{MemoryPoolRef, StorageRef};
+
+/// The timestamps and cumulative difficulties of the last blocks of a
+/// chain, oldest first.
+#[derive(Default)]
+struct DifficultyWindow {
+    /// The id of the last block.
+    top: H256,
+    /// The major version of the last block.
+    top_version: u8,
+    timestamps: VecDeque<u64>,
+    cumulative_difficulties: VecDeque<u64>,
+}
+
+impl DifficultyWindow {
+    fn push(&mut self, block: &IndexedBlock, cumulative_difficulty: u64) {
+        self.top = block.id().clone();
+        self.top_version = block.raw.header.major_version;
+        self.timestamps.push_back(block.raw.header.timestamp);
+        self.cumulative_difficulties.push_back(cumulative_difficulty);
+
+        if self.timestamps.len() > DIFFICULTY_BLOCKS_COUNT {
+            self.timestamps.pop_front();
+            self.cumulative_difficulties.pop_front();
+        }
+    }
 
-use types::StorageRef;
+    /// The difficulty of a block on top of this chain.
+    fn next_difficulty(&self) -> Difficulty {
+        let timestamps: Vec<u64> = self.timestamps.iter().cloned().collect();
+        let cumulative_difficulties: Vec<u64> =
+            self.cumulative_difficulties.iter().cloned().collect();
+
+        next_difficulty(&timestamps,
+                        &cumulative_difficulties,
+                        difficulty_target(self.top_version))
+    }
+}
 
 pub struct Chain {
     storage: StorageRef,
+    memory_pool: MemoryPoolRef,
+    checkpoints: Checkpoints,
+    /// The difficulty window of the best block.
+    window: Mutex<DifficultyWindow>,
 }
 
 impl Chain {
-    pub fn new(storage: StorageRef) -> Chain {
-        Chain { storage }
+    pub fn new(storage: StorageRef,
+               memory_pool: MemoryPoolRef,
+               checkpoints: Checkpoints)
+               -> Chain {
+        Chain {
+            storage,
+            memory_pool,
+            checkpoints,
+            window: Mutex::new(DifficultyWindow::default()),
+        }
+    }
+
+    pub fn checkpoints(&self) -> &Checkpoints {
+        &self.checkpoints
     }
 
     pub fn storage(&self) -> StorageRef {
@@ -38,4 +101,214 @@ impl Chain {
     pub fn height(&self) -> u64 {
         self.storage.height()
     }
+
+    pub fn block(&self, id: H256) -> Option<IndexedBlock> {
+        self.storage.indexed_block(id.into())
+    }
+
+    pub fn transaction(&self, id: &H256) -> Option<IndexedTransaction> {
+        self.storage.as_transaction_provider().transaction(id)
+    }
+
+    /// The cumulative difficulty of the best chain.
+    pub fn cumulative_difficulty(&self) -> u64 {
+        self.block_cumulative_difficulty(&self.storage.best_block().id)
+            .unwrap_or(0)
+    }
+
+    /// The cumulative difficulty of the chain up to the block.
+    pub fn block_cumulative_difficulty(&self, id: &H256) -> Option<u64> {
+        self.storage.cumulative_difficulty(id)
+    }
+
+    /// The difficulty of the block on top of our best block.
+    pub fn next_difficulty(&self) -> Difficulty {
+        let best_block = self.storage.best_block();
+        self.difficulty_for(&best_block.id)
+            .unwrap_or(Difficulty(1))
+    }
+
+    /// The difficulty of a block on top of `prev_id`, `None` if we don't
+    /// know that block.
+    fn difficulty_for(&self, prev_id: &H256) -> Option<Difficulty> {
+        let mut window = self.window.lock();
+        if window.top == *prev_id && !window.timestamps.is_empty() {
+            return Some(window.next_difficulty());
+        }
+
+        let new_window = self.load_window(prev_id)?;
+        let difficulty = new_window.next_difficulty();
+        if *prev_id == self.storage.best_block().id {
+            *window = new_window;
+        }
+
+        Some(difficulty)
+    }
+
+    /// Reads the difficulty window of `top` from the storage.
+    fn load_window(&self, top: &H256) -> Option<DifficultyWindow> {
+        let mut blocks = Vec::new();
+        let mut id = top.clone();
+        while blocks.len() < DIFFICULTY_BLOCKS_COUNT && !id.is_zero() {
+            let block = self.block(id.clone())?;
+            let cumulative_difficulty = self.block_cumulative_difficulty(&id)?;
+            id = block.raw.header.prev_id.clone();
+            blocks.push((block, cumulative_difficulty));
+        }
+
+        let mut window = DifficultyWindow::default();
+        for (block, cumulative_difficulty) in blocks.into_iter().rev() {
+            window.push(&block, cumulative_difficulty);
+        }
+
+        Some(window)
+    }
+
+    /// Verifies a block against the chain of it's parent, the expensive
+    /// checks are skipped below the last checkpoint. Blocks whose parent we
+    /// don't have can't be inserted and aren't checked.
+    pub fn verify_block(&self, block: &IndexedBlock) -> Result<(), BlockError> {
+        let height = match self.block_height(block) {
+            Some(height) => height,
+            None => return Ok(()),
+        };
+        let difficulty = match self.difficulty_for(&block.raw.header.prev_id) {
+            Some(difficulty) => difficulty,
+            None => return Ok(()),
+        };
+
+        verify_block_context_free(block,
+                                  difficulty,
+                                  verification_level(&self.checkpoints, height))?;
+        self.verify_height(block, height)
+    }
+
+    /// The checks of a block that depend on the chain of it's parent, the
+    /// context free ones must be already done. `pow_hash` is the proof of
+    /// work hash if it was computed by the verifier.
+    pub fn verify_block_context(&self,
+                                block: &IndexedBlock,
+                                pow_hash: Option<&H256>)
+                                -> Result<(), BlockError> {
+        let height = match self.block_height(block) {
+            Some(height) => height,
+            None => return Ok(()),
+        };
+
+        if verification_level(&self.checkpoints, height) == VerificationLevel::Full {
+            let difficulty = self.difficulty_for(&block.raw.header.prev_id)
+                .unwrap_or(Difficulty(1));
+            let pow_hash = pow_hash.cloned().unwrap_or_else(|| block.raw.hash());
+            if !is_valid_proof_of_work(pow_hash, difficulty) {
+                return Err(BlockError::InvalidProofOfWork);
+            }
+        }
+
+        self.verify_height(block, height)
+    }
+
+    /// The checks of a block at `height` on top of it's parent.
+    fn verify_height(&self, block: &IndexedBlock, height: u64) -> Result<(), BlockError> {
+        if !self.checkpoints.check(height, block.id()) {
+            return Err(BlockError::Checkpoint(height));
+        }
+
+        // The expensive checks of the blocks below the last checkpoint are
+        // skipped, only the checkpointed chain can have them.
+        let extends_best = block.raw.header.prev_id == self.storage.best_block().id;
+        if !extends_best && self.checkpoints.is_in_checkpoint_zone(height) {
+            return Err(BlockError::Checkpoint(height));
+        }
+
+        verify_block_context(block, height)
+    }
+
+    /// The height of a block on top of it's parent, `None` if we don't have
+    /// the parent.
+    fn block_height(&self, block: &IndexedBlock) -> Option<u64> {
+        let best_block = self.storage.best_block();
+        let prev_id = &block.raw.header.prev_id;
+        if *prev_id == best_block.id {
+            return Some(best_block.height + 1);
+        }
+
+        // Side chain blocks don't have a canonical height, the height of
+        // their miner transaction was checked when they were inserted.
+        self.block(prev_id.clone())
+            .and_then(|parent| miner_transaction_height(&parent))
+            .map(|height| height + 1)
+    }
+
+    /// Inserts the block and canonizes it if it extends our best block, the
+    /// chain is reorganized if the block makes a side chain heavier than
+    /// ours.
+    pub fn insert_block(&self, block: IndexedBlock) -> Result<(), String> {
+        let id = block.id().clone();
+        let prev_id = block.raw.header.prev_id.clone();
+        let extends_best = prev_id == self.storage.best_block().id;
+
+        let difficulty = self.difficulty_for(&prev_id)
+            .ok_or_else(|| "unknown block parent".to_owned())?;
+        self.storage.insert(block.clone(), difficulty.0)?;
+
+        let cumulative_difficulty = self.block_cumulative_difficulty(&id)
+            .ok_or_else(|| "block inserted without difficulty".to_owned())?;
+
+        if extends_best {
+            self.storage.canonize(&id)?;
+            self.memory_pool.on_canonize(&block);
+
+            let mut window = self.window.lock();
+            if window.top == prev_id {
+                window.push(&block, cumulative_difficulty);
+            }
+        } else if cumulative_difficulty > self.cumulative_difficulty() {
+            self.reorganize(&id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes the side chain ending at `id` the best chain.
+    fn reorganize(&self, id: &H256) -> Result<(), String> {
+        // The side chain blocks, the newest first.
+        let mut side_chain = Vec::new();
+        let mut fork_id = id.clone();
+        loop {
+            let block = self.block(fork_id.clone())
+                .ok_or_else(|| "unknown side chain block".to_owned())?;
+            let height = miner_transaction_height(&block)
+                .ok_or_else(|| "side chain block without height".to_owned())?;
+            if self.storage.block_id(height).as_ref() == Some(&fork_id) {
+                break;
+            }
+
+            fork_id = block.raw.header.prev_id.clone();
+            side_chain.push(block);
+        }
+
+        info!("Reorganizing the chain from {:?} to {:?}, {} blocks on the new branch",
+              self.storage.best_block().id,
+              id,
+              side_chain.len());
+
+        let mut disconnected = Vec::new();
+        while self.storage.best_block().id != fork_id {
+            let id = self.storage.decanonize()?;
+            disconnected.extend(self.block(id));
+        }
+
+        for block in side_chain.into_iter().rev() {
+            self.storage.canonize(block.id())?;
+            self.memory_pool.on_canonize(&block);
+        }
+
+        // The transactions of the old branch go back to the memory pool once
+        // the new one is canonized, the ones it double spends are dropped.
+        for block in disconnected.iter().rev() {
+            self.memory_pool.on_decanonize(block);
+        }
+
+        Ok(())
+    }
 }
@@ -15,16 +15,21 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::sync::Arc;
+use std::time::Instant;
 
 use parking_lot::Mutex;
 
-use network::Network;
+use chain::IndexedTransaction;
+use p2p::types::cn::CoreSyncData;
 use p2p::types::cn::cmd::{NewBlock, NewFluffyBlock, NewTransactions, RequestChain,
                           RequestFluffyMissingTx, RequestGetObjects, ResponseChainEntry,
                           ResponseGetObjects};
+use txpool::Error as TxPoolError;
 
 use synchronization_client_core::{ClientCore, SynchronizationClientCore};
-use types::{ClientCoreRef, ExecutorRef, PeersRef, StorageRef, PeerIndex};
+use synchronization_status::SyncStatus;
+use synchronization_verifier::BlockVerifier;
+use types::{ClientCoreRef, PeerIndex};
 
 /// 1.) Verify peer synchronization data.
 /// 1.1.) Send a RequestChain notification to the peer.
@@ -39,23 +44,22 @@ pub trait Client: Send + Sync + 'static {
     fn on_response_chain_entry(&self, peer_index: PeerIndex, arg: &ResponseChainEntry);
     fn on_response_get_objects(&self, peer_index: PeerIndex, arg: &ResponseGetObjects);
     fn on_support_flags(&self, peer_index: PeerIndex, arg: u32);
+    fn on_sync_data(&self, peer_index: PeerIndex, arg: &CoreSyncData);
+    fn on_timer(&self, now: Instant);
+    fn submit_transaction(&self, tx: IndexedTransaction) -> Result<(), TxPoolError>;
+    fn status(&self, now: Instant) -> SyncStatus;
 }
 
 pub struct SynchronizationClient {
     core: ClientCoreRef,
+    verifier: BlockVerifier,
 }
 
 impl SynchronizationClient {
-    pub fn new(executor: ExecutorRef,
-               storage: StorageRef,
-               network: Network,
-               peers: PeersRef)
-               -> SynchronizationClient {
+    pub fn new(core: SynchronizationClientCore, verifier: BlockVerifier) -> SynchronizationClient {
         SynchronizationClient {
-            core: Arc::new(Mutex::new(SynchronizationClientCore::new(executor,
-                                                                     storage,
-                                                                     network,
-                                                                     peers))),
+            core: Arc::new(Mutex::new(core)),
+            verifier,
         }
     }
 }
@@ -108,9 +112,12 @@ impl Client for SynchronizationClient {
     }
 
     fn on_response_get_objects(&self, peer_index: PeerIndex, arg: &ResponseGetObjects) {
+        // The context free checks don't need the core lock.
+        let blocks = self.verifier.verify(arg.blocks.clone());
+
         self.core
             .lock()
-            .on_response_get_objects(peer_index, arg);
+            .on_response_get_objects(peer_index, arg, blocks);
     }
 
     fn on_support_flags(&self, peer_index: PeerIndex, arg: u32) {
@@ -118,4 +125,22 @@ impl Client for SynchronizationClient {
             .lock()
             .on_support_flags(peer_index, arg);
     }
+
+    fn on_sync_data(&self, peer_index: PeerIndex, arg: &CoreSyncData) {
+        self.core
+            .lock()
+            .on_sync_data(peer_index, arg);
+    }
+
+    fn on_timer(&self, now: Instant) {
+        self.core.lock().on_timer(now);
+    }
+
+    fn submit_transaction(&self, tx: IndexedTransaction) -> Result<(), TxPoolError> {
+        self.core.lock().submit_transaction(tx)
+    }
+
+    fn status(&self, now: Instant) -> SyncStatus {
+        self.core.lock().status(now)
+    }
 }
@@ -15,18 +15,38 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use rand::thread_rng;
 
-use network::Network;
+use chain::{Block, IndexedBlock, IndexedTransaction};
+use format::{from_binary, to_binary};
+use network::{Checkpoints, Network};
+use primitives::H256;
 
+use p2p::protocol::Misbehaviour;
+use p2p::types::cn::{BlockCompleteEntry, CoreSyncData};
 use p2p::types::cn::cmd::{NewBlock, NewFluffyBlock, NewTransactions, RequestChain,
                           RequestFluffyMissingTx, RequestGetObjects, ResponseChainEntry,
                           ResponseGetObjects};
+use portable_storage_utils::Blob;
+use portable_storage_utils::stl::StlVector;
+use txpool::Error as TxPoolError;
 
 use synchronization_chain::Chain;
 use synchronization_executor::{Task, TaskExecutor};
-use types::{ExecutorRef, PeersRef, StorageRef, PeerIndex};
+use synchronization_relay::{Embargoes, StemGraph};
+use synchronization_status::{SyncStats, SyncStatus};
+use synchronization_verifier::{Error as VerifierError, VerifiedBlock};
+use types::{ClockRef, ExecutorRef, PeersRef, MemoryPoolRef, StorageRef, PeerIndex};
+
+/// Number of blocks requested at once during synchronization.
+pub const BLOCKS_SYNCHRONIZING_DEFAULT_COUNT: usize = 20;
+/// Seconds a peer has to answer a `RequestChain`.
+pub const REQUEST_CHAIN_TIMEOUT: u64 = 30;
+/// Seconds a peer has to answer a `RequestGetObjects`.
+pub const REQUEST_BLOCKS_TIMEOUT: u64 = 60;
 
 pub trait ClientCore: Send + Sync + 'static {
     fn on_connect(&self, peer_index: PeerIndex);
@@ -37,8 +57,19 @@ pub trait ClientCore: Send + Sync + 'static {
     fn on_request_fluffy_missing_tx(&self, peer_index: PeerIndex, arg: &RequestFluffyMissingTx);
     fn on_request_get_objects(&self, peer_index: PeerIndex, arg: &RequestGetObjects);
     fn on_response_chain_entry(&self, peer_index: PeerIndex, arg: &ResponseChainEntry);
-    fn on_response_get_objects(&self, peer_index: PeerIndex, arg: &ResponseGetObjects);
+    /// Commits the blocks of a `ResponseGetObjects`, they're in the same order
+    /// as `arg.blocks` and already passed the context free checks.
+    fn on_response_get_objects(&self,
+                               peer_index: PeerIndex,
+                               arg: &ResponseGetObjects,
+                               blocks: Vec<Result<VerifiedBlock, VerifierError>>);
     fn on_support_flags(&self, peer_index: PeerIndex, arg: u32);
+    /// Updates the peer's claim, synchronization starts if the peer got
+    /// ahead of us.
+    fn on_sync_data(&self, peer_index: PeerIndex, arg: &CoreSyncData);
+    fn on_timer(&self, now: Instant);
+    fn submit_transaction(&self, tx: IndexedTransaction) -> Result<(), TxPoolError>;
+    fn status(&self, now: Instant) -> SyncStatus;
 }
 
 pub struct SynchronizationClientCore {
@@ -46,35 +77,276 @@ pub struct SynchronizationClientCore {
     chain: Chain,
     network: Network,
     peers: PeersRef,
+    memory_pool: MemoryPoolRef,
     contexes: RwLock<HashMap<PeerIndex, Context>>,
+    fluffy_blocks: RwLock<HashMap<H256, FluffyBlock>>,
+    stem_graph: Mutex<StemGraph>,
+    embargoes: Mutex<Embargoes>,
+    stats: Mutex<SyncStats>,
+    clock: ClockRef,
 }
 
 impl SynchronizationClientCore {
     pub fn new(executor: ExecutorRef,
                storage: StorageRef,
                network: Network,
-               peers: PeersRef)
+               peers: PeersRef,
+               memory_pool: MemoryPoolRef,
+               checkpoints: Checkpoints,
+               clock: ClockRef)
                -> SynchronizationClientCore {
         SynchronizationClientCore {
             executor,
-            chain: Chain::new(storage),
+            chain: Chain::new(storage, memory_pool.clone(), checkpoints),
             network,
             peers,
+            memory_pool,
             contexes: RwLock::new(HashMap::new()),
+            fluffy_blocks: RwLock::new(HashMap::new()),
+            stem_graph: Mutex::new(StemGraph::new()),
+            embargoes: Mutex::new(Embargoes::new()),
+            stats: Mutex::new(SyncStats::new()),
+            clock,
         }
     }
 
-    fn context_write<F>(&self, peer_index: &PeerIndex, f: F)
-        where F: FnOnce(&mut Context)
+    /// Runs `f` on the context of the peer, `None` if the peer isn't known,
+    /// e.g. because it failed the verification of its sync data.
+    fn context_write<F, R>(&self, peer_index: &PeerIndex, f: F) -> Option<R>
+        where F: FnOnce(&mut Context) -> R
     {
-        let mut contexes = self.contexes.write();
-        let context = contexes.get_mut(peer_index).expect("context should exist");
-        f(context)
+        self.contexes.write().get_mut(peer_index).map(f)
     }
 
-    fn misbehaving(&self, peer_index: PeerIndex, reason: &str) {
-        self.peers.misbehaving(peer_index, reason);
+    fn misbehaving(&self, peer_index: PeerIndex, misbehaviour: Misbehaviour, reason: &str) {
+        if self.peers.misbehaving(peer_index, misbehaviour, reason) {
+            self.forget(peer_index);
+        }
+    }
+
+    /// Disconnects from the peer.
+    fn disconnect(&self, peer_index: PeerIndex) {
+        self.peers.close(peer_index);
+        self.forget(peer_index);
+    }
+
+    fn forget(&self, peer_index: PeerIndex) {
         self.contexes.write().remove(&peer_index);
+        self.fluffy_blocks
+            .write()
+            .retain(|_, fluffy_block| fluffy_block.peer_index != peer_index);
+    }
+
+    /// Takes the request we were waiting the peer to answer.
+    fn take_pending_request(&self, peer_index: PeerIndex) -> Option<Request> {
+        self.contexes
+            .write()
+            .get_mut(&peer_index)
+            .and_then(|context| context.pending_request.take())
+            .map(|pending| pending.request)
+    }
+
+    fn set_pending_request(&self, peer_index: PeerIndex, request: Request, timeout: u64) {
+        let deadline = self.clock.now() + Duration::from_secs(timeout);
+        if let Some(context) = self.contexes.write().get_mut(&peer_index) {
+            context.pending_request = Some(PendingRequest { request, deadline });
+        }
+    }
+
+    /// The peers that aren't busy and claim a chain heavier than ours, the
+    /// heaviest first.
+    fn best_peers(&self) -> Vec<PeerIndex> {
+        let cumulative_difficulty = self.chain.cumulative_difficulty();
+        let mut peers: Vec<(PeerIndex, u64)> = self.contexes
+            .read()
+            .iter()
+            .filter(|&(_, context)| {
+                        context.pending_request.is_none() &&
+                        context.cumulative_difficulty > cumulative_difficulty
+                    })
+            .map(|(peer_index, context)| (*peer_index, context.cumulative_difficulty))
+            .collect();
+
+        // The lowest index on ties.
+        peers.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        peers.into_iter().map(|(peer_index, _)| peer_index).collect()
+    }
+
+    /// Checks the cumulative difficulty the peer claims against the one we
+    /// calculated, if we have the peer's top block.
+    ///
+    /// Returns `false` if the peer overclaimed.
+    fn check_claim(&self, peer_index: PeerIndex) -> bool {
+        let claim = self.contexes
+            .read()
+            .get(&peer_index)
+            .map(|context| (context.top_id.clone(), context.cumulative_difficulty));
+        let (top_id, claimed) = match claim {
+            Some(claim) => claim,
+            None => return true,
+        };
+
+        match self.chain.block_cumulative_difficulty(&top_id) {
+            Some(actual) if claimed > actual => {
+                warn!("Peer #{} claimed cumulative difficulty {} for {:?}, it's {}",
+                      peer_index,
+                      claimed,
+                      top_id,
+                      actual);
+                let reason = "peer overclaimed it's cumulative difficulty";
+                self.misbehaving(peer_index, Misbehaviour::OverclaimedDifficulty, reason);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Gives the request of a stalled peer to the peer with the heaviest
+    /// chain that isn't busy.
+    fn reassign_request(&self, stalled: PeerIndex, request: Request) {
+        let peer_index = self.best_peers()
+            .into_iter()
+            .find(|peer_index| *peer_index != stalled);
+
+        let peer_index = match peer_index {
+            Some(peer_index) => peer_index,
+            None => {
+                debug!("No peer can take over the {:?} request of peer #{}",
+                       request,
+                       stalled);
+                return;
+            }
+        };
+
+        info!("Moving the {:?} request of peer #{} to peer #{}",
+              request,
+              stalled,
+              peer_index);
+
+        match request {
+            Request::Chain => self.request_chain(peer_index),
+            Request::Blocks(blocks) => {
+                let blocks: Vec<H256> = blocks
+                    .into_iter()
+                    .filter(|id| !self.chain.have_block(id.clone()))
+                    .collect();
+                if blocks.is_empty() {
+                    self.request_chain(peer_index);
+                } else {
+                    self.request_blocks(peer_index, blocks);
+                }
+            }
+        }
+    }
+
+    /// Finds a transaction of a fluffy block that we may already have.
+    fn find_transaction(&self, id: &H256) -> Option<IndexedTransaction> {
+        self.memory_pool
+            .transaction(id)
+            .or_else(|| self.chain.transaction(id))
+    }
+
+    fn insert_fluffy_block(&self, peer_index: PeerIndex, block: IndexedBlock) {
+        let id = block.id().clone();
+
+        if let Err(e) = self.chain.verify_block(&block) {
+            warn!("Peer #{} sent an invalid block {:?}: {}", peer_index, id, e);
+            self.misbehaving(peer_index, Misbehaviour::InvalidBlock, "peer sent an invalid block");
+            return;
+        }
+
+        match self.chain.insert_block(block) {
+            Ok(()) => {
+                info!("Block {:?} reconstructed from fluffy block", id);
+                self.stats.lock().on_block(peer_index, self.clock.now());
+            }
+            Err(e) => warn!("Couldn't insert fluffy block {:?}: {}", id, e),
+        }
+    }
+
+    /// Sends the transactions in the stem phase to the stem peer of
+    /// `source`, they're fluffed if we don't have any outbound peer.
+    fn stem_transactions(&self, source: Option<PeerIndex>, txs: Vec<IndexedTransaction>) {
+        let mut rng = thread_rng();
+        let outbound = self.peers.outbound();
+        let stem = self.stem_graph
+            .lock()
+            .route(&mut rng, source, &outbound);
+
+        let stem = match stem {
+            Some(stem) => stem,
+            None => {
+                debug!("No outbound peers to stem the transactions, fluffing them");
+                self.fluff_transactions(source, txs);
+                return;
+            }
+        };
+
+        let now = self.clock.now();
+        let mut embargoes = self.embargoes.lock();
+        for tx in txs.iter() {
+            embargoes.insert(&mut rng, tx.id().clone(), now);
+        }
+
+        let request = NewTransactions {
+            txs: txs.iter().map(|tx| Blob(to_binary(&tx.raw).to_vec())).collect(),
+            dandelionpp_fluff: false,
+        };
+
+        self.executor
+            .execute(Task::NewTransactions(stem, request));
+    }
+
+    /// Broadcasts the transactions in the fluff phase to all peers except
+    /// `source`.
+    fn fluff_transactions(&self, source: Option<PeerIndex>, txs: Vec<IndexedTransaction>) {
+        {
+            let mut embargoes = self.embargoes.lock();
+            for tx in txs.iter() {
+                embargoes.remove(tx.id());
+            }
+        }
+
+        let blobs: Vec<Blob> = txs.iter().map(|tx| Blob(to_binary(&tx.raw).to_vec())).collect();
+
+        for peer_index in self.peers.enumerate() {
+            if Some(peer_index) == source {
+                continue;
+            }
+
+            let request = NewTransactions {
+                txs: blobs.clone(),
+                dandelionpp_fluff: true,
+            };
+
+            self.executor
+                .execute(Task::NewTransactions(peer_index, request));
+        }
+    }
+
+    /// Requests the chain of the peer starting from our best block.
+    fn request_chain(&self, peer_index: PeerIndex) {
+        self.set_pending_request(peer_index, Request::Chain, REQUEST_CHAIN_TIMEOUT);
+
+        let block_ids = self.chain.storage().short_chain_history();
+        let request = RequestChain { block_ids: block_ids.into() };
+        self.executor
+            .execute(Task::RequestChain(peer_index, request));
+    }
+
+    fn request_blocks(&self, peer_index: PeerIndex, blocks: Vec<H256>) {
+        debug!("Requesting {} blocks from peer #{}", blocks.len(), peer_index);
+
+        self.set_pending_request(peer_index,
+                                 Request::Blocks(blocks.clone()),
+                                 REQUEST_BLOCKS_TIMEOUT);
+
+        let request = RequestGetObjects {
+            txs: Vec::new(),
+            blocks,
+        };
+        self.executor
+            .execute(Task::RequestGetObjects(peer_index, request));
     }
 
     fn verify_sync_data(&self, peer_index: PeerIndex) -> Option<SyncState> {
@@ -98,7 +370,9 @@ impl SynchronizationClientCore {
                       sync_data.top_version,
                       peer_top);
 
-                self.misbehaving(peer_index, "peer uses different version than us");
+                let reason = "peer uses different version than us";
+                self.misbehaving(peer_index, Misbehaviour::ProtocolError, reason);
+                self.disconnect(peer_index);
 
                 return None;
             }
@@ -108,18 +382,21 @@ impl SynchronizationClientCore {
             remote_blockchain_height: sync_data.current_height,
             last_response_height: None,
             support_flags: None,
+            pending_request: None,
+            top_id: sync_data.top_id.clone(),
+            cumulative_difficulty: sync_data.cumulative_difficulty,
         };
 
         self.contexes.write().insert(peer_index, context);
 
-        if self.chain.have_block(sync_data.top_id) {
-            let our_height = self.chain.height();
-            if our_height == sync_data.current_height {
-                info!("Peer #{} is synchronized with us.", peer_index);
-                Some(SyncState::Synchronized)
-            } else {
-                Some(SyncState::Synchronizing)
-            }
+        if !self.check_claim(peer_index) {
+            return None;
+        }
+
+        if self.chain.have_block(sync_data.top_id) ||
+           sync_data.cumulative_difficulty <= self.chain.cumulative_difficulty() {
+            info!("Peer #{} is synchronized with us.", peer_index);
+            Some(SyncState::Synchronized)
         } else {
             Some(SyncState::Synchronizing)
         }
@@ -128,18 +405,11 @@ impl SynchronizationClientCore {
 
 impl ClientCore for SynchronizationClientCore {
     fn on_connect(&self, peer_index: PeerIndex) {
-        use p2p::types::cn::cmd::RequestChain;
-
         info!("Synchronizing with peer #{}", peer_index);
 
         let state = self.verify_sync_data(peer_index);
         match state {
-            Some(SyncState::Synchronizing) => {
-                let block_ids = self.chain.storage().short_chain_history();
-                let request = RequestChain { block_ids: block_ids.into() };
-                self.executor
-                    .execute(Task::RequestChain(peer_index, request));
-            }
+            Some(SyncState::Synchronizing) => self.request_chain(peer_index),
             Some(SyncState::Synchronized) => {}
             None => { /* not valid sync info */ }
         }
@@ -148,49 +418,349 @@ impl ClientCore for SynchronizationClientCore {
     fn on_new_block(&self, _peer_index: PeerIndex, _arg: &NewBlock) {
     }
 
-    fn on_new_fluffy_block(&self, _peer_index: PeerIndex, _arg: &NewFluffyBlock) {
+    fn on_new_fluffy_block(&self, peer_index: PeerIndex, arg: &NewFluffyBlock) {
+        let block = match from_binary::<Block>(&arg.b.block.0) {
+            Ok(block) => block,
+            Err(_) => {
+                let reason = "peer sent an invalid fluffy block";
+                self.misbehaving(peer_index, Misbehaviour::InvalidBlock, reason);
+                return;
+            }
+        };
+
+        self.stats
+            .lock()
+            .on_bytes(peer_index, entry_size(&arg.b), self.clock.now());
+
+        let id = block.id();
+        if self.chain.have_block(id.clone()) {
+            self.fluffy_blocks.write().remove(&id);
+            return;
+        }
+
+        let current_blockchain_height = arg.current_blockchain_height;
+        let known = self.context_write(&peer_index, move |context| {
+            if current_blockchain_height > context.remote_blockchain_height {
+                context.remote_blockchain_height = current_blockchain_height;
+            }
+        });
+        if known.is_none() {
+            debug!("Ignoring fluffy block from unknown peer #{}", peer_index);
+            return;
+        }
+
+        // If we have it pending we already requested the missing transactions,
+        // the request is the one of this peer only if we asked it.
+        let pending = self.fluffy_blocks.write().remove(&id);
+        let was_requested = pending
+            .as_ref()
+            .map_or(false, |fluffy_block| fluffy_block.peer_index == peer_index);
+        let mut transactions = match pending {
+            Some(fluffy_block) => fluffy_block.transactions,
+            None => vec![None; block.tx_hashes.len()],
+        };
+
+        for blob in arg.b.txs.iter() {
+            let tx = match IndexedTransaction::from_bytes(&blob.0) {
+                Ok(tx) => tx,
+                Err(_) => {
+                    let reason = "peer sent an invalid fluffy block transaction";
+                    self.misbehaving(peer_index, Misbehaviour::InvalidBlock, reason);
+                    return;
+                }
+            };
+
+            match block.tx_hashes.iter().position(|id| *id == tx.id) {
+                Some(index) => transactions[index] = Some(tx),
+                None => {
+                    let reason = "peer sent a transaction that isn't in the fluffy block";
+                    self.misbehaving(peer_index, Misbehaviour::InvalidBlock, reason);
+                    return;
+                }
+            }
+        }
+
+        let mut missing_tx_indices = Vec::new();
+        for (index, tx_id) in block.tx_hashes.iter().enumerate() {
+            if transactions[index].is_none() {
+                transactions[index] = self.find_transaction(tx_id);
+            }
+
+            if transactions[index].is_none() {
+                missing_tx_indices.push(index as u64);
+            }
+        }
+
+        if missing_tx_indices.is_empty() {
+            let transactions = transactions.into_iter().map(Option::unwrap).collect();
+            let block = IndexedBlock::with_transactions(block, id, transactions);
+            self.insert_fluffy_block(peer_index, block);
+            return;
+        }
+
+        if was_requested {
+            let reason = "peer didn't send the requested fluffy block transactions";
+            self.misbehaving(peer_index, Misbehaviour::ProtocolError, reason);
+            return;
+        }
+
+        debug!("Requesting {} missing transactions of fluffy block {:?} from peer #{}",
+               missing_tx_indices.len(),
+               id,
+               peer_index);
+
+        let request = RequestFluffyMissingTx {
+            block_hash: id.clone(),
+            current_blockchain_length: self.chain.height(),
+            missing_tx_indices: StlVector(missing_tx_indices),
+        };
+
+        self.fluffy_blocks.write().insert(id,
+                                          FluffyBlock {
+                                              peer_index,
+                                              transactions,
+                                          });
+
+        self.executor
+            .execute(Task::RequestFluffyMissingTx(peer_index, request));
     }
 
-    fn on_new_transactions(&self, _peer_index: PeerIndex, _arg: &NewTransactions) {
+    fn on_new_transactions(&self, peer_index: PeerIndex, arg: &NewTransactions) {
+        let mut accepted = Vec::with_capacity(arg.txs.len());
+        for blob in arg.txs.iter() {
+            let tx = match IndexedTransaction::from_bytes(&blob.0) {
+                Ok(tx) => tx,
+                Err(_) => {
+                    let reason = "peer sent an invalid transaction";
+                    self.misbehaving(peer_index, Misbehaviour::InvalidTransaction, reason);
+                    return;
+                }
+            };
+
+            let id = tx.id().clone();
+            match self.memory_pool.insert(tx.clone()) {
+                Ok(()) => {
+                    debug!("Transaction {:?} added to the memory pool", id);
+                    accepted.push(tx);
+                }
+                Err(TxPoolError::Verification(e)) => {
+                    warn!("Peer #{} sent an invalid transaction {:?}: {}", peer_index, id, e);
+                    let reason = "peer sent an invalid transaction";
+                    self.misbehaving(peer_index, Misbehaviour::InvalidTransaction, reason);
+                    return;
+                }
+                Err(TxPoolError::AlreadyInPool) if arg.dandelionpp_fluff => {
+                    // One of our stem transactions was fluffed, stop it's
+                    // embargo.
+                    self.embargoes.lock().remove(&id);
+                }
+                Err(e) => debug!("Transaction {:?} rejected: {}", id, e),
+            }
+        }
+
+        if accepted.is_empty() {
+            return;
+        }
+
+        if arg.dandelionpp_fluff || self.stem_graph.lock().is_fluff() {
+            self.fluff_transactions(Some(peer_index), accepted);
+        } else {
+            self.stem_transactions(Some(peer_index), accepted);
+        }
     }
 
     fn on_request_chain(&self, _peer_index: PeerIndex, _arg: &RequestChain) {
     }
 
-    fn on_request_fluffy_missing_tx(&self, _peer_index: PeerIndex, _arg: &RequestFluffyMissingTx) {
+    fn on_request_fluffy_missing_tx(&self, peer_index: PeerIndex, arg: &RequestFluffyMissingTx) {
+        let block = match self.chain.block(arg.block_hash.clone()) {
+            Some(block) => block,
+            None => {
+                let reason = "peer requested transactions of an unknown block";
+                self.misbehaving(peer_index, Misbehaviour::ProtocolError, reason);
+                return;
+            }
+        };
+
+        let mut txs = Vec::with_capacity(arg.missing_tx_indices.0.len());
+        for index in arg.missing_tx_indices.0.iter() {
+            let tx_id = match block.raw.tx_hashes.get(*index as usize) {
+                Some(tx_id) => tx_id,
+                None => {
+                    let reason = "peer requested an out of bounds transaction";
+                    self.misbehaving(peer_index, Misbehaviour::ProtocolError, reason);
+                    return;
+                }
+            };
+
+            match self.find_transaction(tx_id) {
+                Some(tx) => txs.push(Blob(to_binary(&tx.raw).to_vec())),
+                None => {
+                    warn!("Couldn't find transaction {:?} of block {:?} requested by peer #{}",
+                          tx_id,
+                          block.id(),
+                          peer_index);
+                    return;
+                }
+            }
+        }
+
+        let response = NewFluffyBlock {
+            b: BlockCompleteEntry {
+                block: Blob(to_binary(&block.raw).to_vec()),
+                txs,
+            },
+            current_blockchain_height: self.chain.height(),
+        };
+
+        self.executor
+            .execute(Task::NewFluffyBlock(peer_index, response));
     }
 
     fn on_request_get_objects(&self, _peer_index: PeerIndex, _arg: &RequestGetObjects) {
     }
 
     fn on_response_chain_entry(&self, peer_index: PeerIndex, arg: &ResponseChainEntry) {
+        if self.take_pending_request(peer_index) != Some(Request::Chain) {
+            let reason = "peer sent a `ResponseChainEntry` we didn't request";
+            self.misbehaving(peer_index, Misbehaviour::ProtocolError, reason);
+            return;
+        }
+
         if arg.block_ids.len() == 0 {
-            self.misbehaving(peer_index, "peer sent empty `block_ids` field");
+            let reason = "peer sent empty `block_ids` field";
+            self.misbehaving(peer_index, Misbehaviour::BadChainEntry, reason);
             return;
         }
 
         if arg.total_height < arg.block_ids.len() as u64 ||
            arg.start_height > arg.total_height - arg.block_ids.len() as u64 {
-            self.misbehaving(peer_index, "peer sent invalid start/nblocks/height.");
+            let reason = "peer sent invalid start/nblocks/height.";
+            self.misbehaving(peer_index, Misbehaviour::BadChainEntry, reason);
+            return;
+        }
+
+        let contradicts_checkpoint = arg.block_ids
+            .0
+            .iter()
+            .enumerate()
+            .any(|(i, id)| !self.chain.checkpoints().check(arg.start_height + i as u64, id));
+        if contradicts_checkpoint {
+            let reason = "peer sent a chain that contradicts a checkpoint";
+            self.misbehaving(peer_index, Misbehaviour::BadChainEntry, reason);
             return;
         }
 
         let remote_blockchain_height = arg.total_height;
         let last_response_height = arg.start_height + arg.block_ids.len() as u64 - 1;
+        // The entry reaches the peer's top block.
+        let top = if last_response_height + 1 == remote_blockchain_height {
+            arg.block_ids.0.back().cloned()
+        } else {
+            None
+        };
+        let cumulative_difficulty = arg.cummulative_difficulty;
 
-        self.context_write(&peer_index, move |context| {
+        let known = self.context_write(&peer_index, move |context| {
             context.remote_blockchain_height = remote_blockchain_height;
             context.last_response_height = Some(last_response_height);
+            if let Some(top_id) = top {
+                context.top_id = top_id;
+                context.cumulative_difficulty = cumulative_difficulty;
+            }
         });
+        if known.is_none() {
+            return;
+        }
 
         if last_response_height > remote_blockchain_height {
             let reason = "peer sent `ResponseChainEntry` with invalid height information.";
-            self.misbehaving(peer_index, reason);
+            self.misbehaving(peer_index, Misbehaviour::BadChainEntry, reason);
             return;
         }
+
+        let blocks: Vec<H256> = arg.block_ids
+            .0
+            .iter()
+            .filter(|id| !self.chain.have_block((*id).clone()))
+            .take(BLOCKS_SYNCHRONIZING_DEFAULT_COUNT)
+            .cloned()
+            .collect();
+
+        if !blocks.is_empty() {
+            self.request_blocks(peer_index, blocks);
+        } else {
+            self.check_claim(peer_index);
+        }
     }
 
-    fn on_response_get_objects(&self, _peer_index: PeerIndex, _arg: &ResponseGetObjects) {
+    fn on_response_get_objects(&self,
+                               peer_index: PeerIndex,
+                               arg: &ResponseGetObjects,
+                               blocks: Vec<Result<VerifiedBlock, VerifierError>>) {
+        match self.take_pending_request(peer_index) {
+            Some(Request::Blocks(_)) => {}
+            _ => {
+                let reason = "peer sent a `ResponseGetObjects` we didn't request";
+                self.misbehaving(peer_index, Misbehaviour::ProtocolError, reason);
+                return;
+            }
+        }
+
+        let bytes = arg.blocks.iter().map(entry_size).sum::<u64>() +
+                    arg.txs.iter().map(|blob| blob.0.len() as u64).sum::<u64>();
+        self.stats
+            .lock()
+            .on_bytes(peer_index, bytes, self.clock.now());
+
+        for verified in blocks {
+            let VerifiedBlock { block, pow_hash } = match verified {
+                Ok(verified) => verified,
+                Err(e) => {
+                    warn!("Peer #{} sent an invalid block: {}", peer_index, e);
+                    let reason = "peer sent an invalid block";
+                    self.misbehaving(peer_index, Misbehaviour::InvalidBlock, reason);
+                    return;
+                }
+            };
+
+            let id = block.id().clone();
+            if self.chain.have_block(id.clone()) {
+                continue;
+            }
+
+            if let Err(e) = self.chain.verify_block_context(&block, pow_hash.as_ref()) {
+                warn!("Peer #{} sent an invalid block {:?}: {}", peer_index, id, e);
+                let reason = "peer sent an invalid block";
+                self.misbehaving(peer_index, Misbehaviour::InvalidBlock, reason);
+                return;
+            }
+
+            match self.chain.insert_block(block) {
+                Ok(()) => self.stats.lock().on_block(peer_index, self.clock.now()),
+                Err(e) => {
+                    warn!("Couldn't insert block {:?}: {}", id, e);
+                    return;
+                }
+            }
+        }
+
+        if !self.check_claim(peer_index) {
+            return;
+        }
+
+        let cumulative_difficulty = self.contexes
+            .read()
+            .get(&peer_index)
+            .map(|context| context.cumulative_difficulty);
+
+        match cumulative_difficulty {
+            Some(difficulty) if difficulty > self.chain.cumulative_difficulty() => {
+                self.request_chain(peer_index)
+            }
+            Some(_) => info!("Synchronized with peer #{}", peer_index),
+            None => {}
+        }
     }
 
     fn on_support_flags(&self, peer_index: PeerIndex, arg: u32) {
@@ -198,6 +768,113 @@ impl ClientCore for SynchronizationClientCore {
             context.support_flags = Some(arg);
         });
     }
+
+    fn on_sync_data(&self, peer_index: PeerIndex, arg: &CoreSyncData) {
+        if !self.contexes.read().contains_key(&peer_index) {
+            return;
+        }
+
+        debug!("Peer #{} timed sync data: {:?}", peer_index, arg);
+
+        self.peers.set_last_sync_data(peer_index, arg);
+        let idle = self.context_write(&peer_index, |context| {
+                context.remote_blockchain_height = arg.current_height;
+                context.top_id = arg.top_id.clone();
+                context.cumulative_difficulty = arg.cumulative_difficulty;
+                context.pending_request.is_none()
+            })
+            .unwrap_or(false);
+
+        if !self.check_claim(peer_index) {
+            return;
+        }
+
+        if idle && !self.chain.have_block(arg.top_id.clone()) &&
+           arg.cumulative_difficulty > self.chain.cumulative_difficulty() {
+            info!("Peer #{} got ahead of us, synchronizing", peer_index);
+            self.request_chain(peer_index);
+        }
+    }
+
+    fn on_timer(&self, now: Instant) {
+        let stalled: Vec<(PeerIndex, Request)> = self.contexes
+            .write()
+            .iter_mut()
+            .filter_map(|(peer_index, context)| {
+                let expired = context
+                    .pending_request
+                    .as_ref()
+                    .map(|pending| pending.deadline <= now)
+                    .unwrap_or(false);
+                if expired {
+                    context
+                        .pending_request
+                        .take()
+                        .map(|pending| (*peer_index, pending.request))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        for (peer_index, request) in stalled {
+            warn!("Peer #{} didn't answer the {:?} request in time, disconnecting",
+                  peer_index,
+                  request);
+            self.misbehaving(peer_index, Misbehaviour::Timeout, "peer didn't answer in time");
+            self.disconnect(peer_index);
+            self.reassign_request(peer_index, request);
+        }
+
+        {
+            let mut stem_graph = self.stem_graph.lock();
+            if stem_graph.is_expired(now) {
+                stem_graph.new_epoch(&mut thread_rng(), &self.peers.outbound(), now);
+            }
+        }
+
+        for tx in self.memory_pool.remove_expired() {
+            debug!("Transaction {:?} expired", tx.id());
+        }
+
+        let txs: Vec<IndexedTransaction> = self.embargoes
+            .lock()
+            .expired(now)
+            .iter()
+            .filter_map(|id| self.memory_pool.transaction(id))
+            .collect();
+
+        if !txs.is_empty() {
+            info!("Embargo timer expired for {} transactions, fluffing them",
+                  txs.len());
+            self.fluff_transactions(None, txs);
+        }
+    }
+
+    fn submit_transaction(&self, tx: IndexedTransaction) -> Result<(), TxPoolError> {
+        self.memory_pool.insert(tx.clone())?;
+
+        info!("Relaying local transaction {:?}", tx.id());
+        self.stem_transactions(None, vec![tx]);
+        Ok(())
+    }
+
+    fn status(&self, now: Instant) -> SyncStatus {
+        let peers: Vec<(PeerIndex, u64)> = self.contexes
+            .read()
+            .iter()
+            .map(|(peer_index, context)| (*peer_index, context.remote_blockchain_height))
+            .collect();
+
+        self.stats
+            .lock()
+            .status(self.chain.height(), &peers, now)
+    }
+}
+
+/// Size in bytes of a block and its transactions.
+fn entry_size(entry: &BlockCompleteEntry) -> u64 {
+    entry.block.0.len() as u64 + entry.txs.iter().map(|blob| blob.0.len() as u64).sum::<u64>()
 }
 
 #[derive(PartialEq, Eq)]
@@ -210,4 +887,253 @@ pub struct Context {
     pub remote_blockchain_height: u64,
     pub last_response_height: Option<u64>,
     pub support_flags: Option<u32>,
+    /// The request we're waiting the peer to answer.
+    pub pending_request: Option<PendingRequest>,
+    /// The best block the peer told us about.
+    pub top_id: H256,
+    /// The cumulative difficulty the peer claims for `top_id`.
+    pub cumulative_difficulty: u64,
+}
+
+/// A request sent to a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Chain,
+    Blocks(Vec<H256>),
+}
+
+pub struct PendingRequest {
+    pub request: Request,
+    pub deadline: Instant,
+}
+
+/// A fluffy block waiting for the transactions we requested.
+struct FluffyBlock {
+    peer_index: PeerIndex,
+    transactions: Vec<Option<IndexedTransaction>>,
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::time::Duration;
+
+    use chain::transaction::TxInGen;
+    use network::Network;
+    use p2p::protocol::Misbehaviour;
+
+    use test_harness::{FakeChain, Harness, Message};
+    use super::*;
+
+    #[test]
+    fn synchronizes_with_peer_ahead() {
+        let local = FakeChain::new(Network::Mainnet);
+        let remote = local.clone().extend(5);
+
+        let harness = Harness::new(&local);
+        let peer = harness.connect(&remote.sync_data());
+        peer.serve(&remote);
+
+        let best_block = harness.storage.best_block();
+        assert_eq!(best_block.id, remote.top_id());
+        assert_eq!(best_block.height, 5);
+        assert!(peer.connection.misbehaviours().is_empty());
+    }
+
+    #[test]
+    fn synchronized_peer_isnt_asked() {
+        let chain = FakeChain::new(Network::Mainnet).extend(3);
+
+        let harness = Harness::new(&chain);
+        let peer = harness.connect(&chain.sync_data());
+
+        assert!(peer.connection.take_sent().is_empty());
+    }
+
+    #[test]
+    fn reorganizes_to_heavier_fork() {
+        let local = FakeChain::new(Network::Mainnet).extend(3);
+        let remote = local.fork(2).extend(4);
+
+        let harness = Harness::new(&local);
+        let peer = harness.connect(&remote.sync_data());
+        peer.serve(&remote);
+
+        assert_eq!(harness.storage.best_block().id, remote.top_id());
+        assert_eq!(harness.storage.block_id(2), Some(remote.id(2)));
+    }
+
+    #[test]
+    fn lighter_fork_isnt_synchronized() {
+        let local = FakeChain::new(Network::Mainnet).extend(4);
+        let remote = local.fork(2).extend(1);
+
+        let harness = Harness::new(&local);
+        let peer = harness.connect(&remote.sync_data());
+
+        assert!(peer.connection.take_sent().is_empty());
+        assert_eq!(harness.storage.best_block().id, local.top_id());
+    }
+
+    #[test]
+    fn synchronizes_when_timed_sync_shows_peer_ahead() {
+        let local = FakeChain::new(Network::Mainnet).extend(2);
+        let remote = local.clone().extend(3);
+
+        let harness = Harness::new(&local);
+        let peer = harness.connect(&local.sync_data());
+        assert!(peer.connection.take_sent().is_empty());
+
+        peer.inbound.on_sync_data(&remote.sync_data());
+        peer.serve(&remote);
+
+        assert_eq!(harness.storage.best_block().id, remote.top_id());
+        assert!(peer.connection.misbehaviours().is_empty());
+    }
+
+    #[test]
+    fn peer_with_wrong_version_is_disconnected() {
+        let chain = FakeChain::new(Network::Mainnet);
+        let remote = chain.clone().extend(1);
+
+        let harness = Harness::new(&chain);
+        let mut sync_data = chain.sync_data();
+        sync_data.current_height = 2_000_000;
+        sync_data.top_version = 1;
+        let peer = harness.connect(&sync_data);
+
+        assert!(peer.connection.is_closed());
+        assert_eq!(peer.connection.misbehaviours(), vec![Misbehaviour::ProtocolError]);
+
+        // The messages it sent before noticing are ignored.
+        peer.inbound.on_support_flags(1);
+        peer.inbound
+            .on_new_fluffy_block(&NewFluffyBlock {
+                                     b: remote.complete_entry(1),
+                                     current_blockchain_height: 2,
+                                 });
+        assert_eq!(harness.storage.best_block().id, chain.top_id());
+    }
+
+    #[test]
+    fn fluffy_block_requested_from_another_peer_isnt_penalised() {
+        let chain = FakeChain::new(Network::Mainnet);
+        let mut block = chain.clone().extend(1).blocks()[1].raw.clone();
+        block.tx_hashes.push(H256::from([1u8; 32]));
+        let fluffy_block = NewFluffyBlock {
+            b: BlockCompleteEntry {
+                block: Blob(to_binary(&block).to_vec()),
+                txs: vec![],
+            },
+            current_blockchain_height: 2,
+        };
+
+        let harness = Harness::new(&chain);
+        let first = harness.connect(&chain.sync_data());
+        let second = harness.connect(&chain.sync_data());
+
+        first.inbound.on_new_fluffy_block(&fluffy_block);
+        second.inbound.on_new_fluffy_block(&fluffy_block);
+
+        assert!(second.connection.misbehaviours().is_empty());
+        match second.connection.take_sent().as_slice() {
+            [Message::RequestFluffyMissingTx(ref request)] => {
+                assert_eq!(request.missing_tx_indices.0, vec![0]);
+            }
+            sent => panic!("unexpected messages {:?}", sent),
+        }
+
+        // Now it was asked, sending it again without them is penalised.
+        second.inbound.on_new_fluffy_block(&fluffy_block);
+        assert_eq!(second.connection.misbehaviours(), vec![Misbehaviour::ProtocolError]);
+    }
+
+    #[test]
+    fn invalid_side_chain_block_is_rejected() {
+        let local = FakeChain::new(Network::Mainnet).extend(3);
+        let mut block = local.fork(2).extend(1).blocks()[2].raw.clone();
+        block.miner_tx.prefix.vin = vec![TxInGen { height: 5 }.into()];
+        let id = block.id();
+        let fluffy_block = NewFluffyBlock {
+            b: BlockCompleteEntry {
+                block: Blob(to_binary(&block).to_vec()),
+                txs: vec![],
+            },
+            current_blockchain_height: 3,
+        };
+
+        let harness = Harness::new(&local);
+        let peer = harness.connect(&local.sync_data());
+        peer.inbound.on_new_fluffy_block(&fluffy_block);
+
+        assert_eq!(peer.connection.misbehaviours(), vec![Misbehaviour::InvalidBlock]);
+        assert_eq!(harness.storage.cumulative_difficulty(&id), None);
+    }
+
+    #[test]
+    fn block_without_enough_work_is_rejected() {
+        let local = FakeChain::new(Network::Mainnet)
+            .with_block_time(0)
+            .extend(10);
+        let remote = local.clone().extend(1);
+
+        let harness = Harness::new(&local);
+        let peer = harness.connect(&remote.sync_data());
+        peer.serve(&remote);
+
+        assert_eq!(peer.connection.misbehaviours(), vec![Misbehaviour::InvalidBlock]);
+        assert_eq!(harness.storage.cumulative_difficulty(&remote.top_id()), None);
+    }
+
+    #[test]
+    fn unrequested_response_is_penalised() {
+        let chain = FakeChain::new(Network::Mainnet).extend(2);
+
+        let harness = Harness::new(&chain);
+        let peer = harness.connect(&chain.sync_data());
+        let request = RequestChain { block_ids: Default::default() };
+        peer.inbound.on_response_chain_entry(&chain.chain_entry(&request));
+
+        assert_eq!(peer.connection.misbehaviours(), vec![Misbehaviour::ProtocolError]);
+    }
+
+    #[test]
+    fn overclaiming_peer_is_penalised() {
+        let chain = FakeChain::new(Network::Mainnet).extend(2);
+        let mut sync_data = chain.sync_data();
+        sync_data.cumulative_difficulty += 100;
+
+        let harness = Harness::new(&chain);
+        let peer = harness.connect(&sync_data);
+
+        assert_eq!(peer.connection.misbehaviours(),
+                   vec![Misbehaviour::OverclaimedDifficulty]);
+        assert!(peer.connection.take_sent().is_empty());
+    }
+
+    #[test]
+    fn stalled_peer_is_dropped() {
+        let local = FakeChain::new(Network::Mainnet);
+        let remote = local.clone().extend(3);
+
+        let harness = Harness::new(&local);
+        let stalled = harness.connect(&remote.sync_data());
+        let other = harness.connect(&remote.sync_data());
+        match stalled.connection.take_sent().as_slice() {
+            &[Message::RequestChain(_)] => {}
+            sent => panic!("unexpected messages {:?}", sent),
+        }
+
+        // Only the other peer answers.
+        assert_eq!(other.respond(&remote), 1);
+        harness.clock.advance(Duration::from_secs(REQUEST_CHAIN_TIMEOUT));
+        harness.local_node.on_timer();
+
+        assert_eq!(stalled.connection.misbehaviours(), vec![Misbehaviour::Timeout]);
+        assert!(stalled.connection.is_closed());
+        assert!(!other.connection.is_closed());
+
+        // The synchronization goes on with the other peer.
+        other.serve(&remote);
+        assert_eq!(harness.storage.best_block().id, remote.top_id());
+    }
 }
@@ -24,6 +24,10 @@ pub trait TaskExecutor: Send + Sync + 'static {
 
 pub enum Task {
     RequestChain(PeerIndex, cn::cmd::RequestChain),
+    NewFluffyBlock(PeerIndex, cn::cmd::NewFluffyBlock),
+    NewTransactions(PeerIndex, cn::cmd::NewTransactions),
+    RequestFluffyMissingTx(PeerIndex, cn::cmd::RequestFluffyMissingTx),
+    RequestGetObjects(PeerIndex, cn::cmd::RequestGetObjects),
 }
 
 pub struct LocalSynchronizationTaskExecutor {
@@ -44,12 +48,65 @@ impl LocalSynchronizationTaskExecutor {
             .connection(peer_index)
             .map(|connection| { connection.notify_request_chain(&request); });
     }
+
+    fn execute_newfluffyblock(&self, peer_index: PeerIndex, request: cn::cmd::NewFluffyBlock) {
+        debug!("Executing NewFluffyBlock request for peer #{} - {:?}",
+               peer_index,
+               request);
+
+        if let Some(connection) = self.peers.connection(peer_index) {
+            connection.notify_new_fluffy_block(&request);
+        }
+    }
+
+    fn execute_newtransactions(&self, peer_index: PeerIndex, request: cn::cmd::NewTransactions) {
+        debug!("Executing NewTransactions request for peer #{} - {} transactions (fluff: {})",
+               peer_index,
+               request.txs.len(),
+               request.dandelionpp_fluff);
+
+        if let Some(connection) = self.peers.connection(peer_index) {
+            connection.notify_new_transactions(&request);
+        }
+    }
+
+    fn execute_requestfluffymissingtx(&self,
+                                      peer_index: PeerIndex,
+                                      request: cn::cmd::RequestFluffyMissingTx) {
+        debug!("Executing RequestFluffyMissingTx request for peer #{} - {:?}",
+               peer_index,
+               request);
+
+        if let Some(connection) = self.peers.connection(peer_index) {
+            connection.notify_request_fluffy_missing_tx(&request);
+        }
+    }
+
+    fn execute_requestgetobjects(&self,
+                                 peer_index: PeerIndex,
+                                 request: cn::cmd::RequestGetObjects) {
+        debug!("Executing RequestGetObjects request for peer #{} - {} blocks",
+               peer_index,
+               request.blocks.len());
+
+        if let Some(connection) = self.peers.connection(peer_index) {
+            connection.notify_request_get_objects(&request);
+        }
+    }
 }
 
 impl TaskExecutor for LocalSynchronizationTaskExecutor {
     fn execute(&self, task: Task) {
         match task {
             Task::RequestChain(peer_index, req) => self.execute_requestchain(peer_index, req),
+            Task::NewFluffyBlock(peer_index, req) => self.execute_newfluffyblock(peer_index, req),
+            Task::NewTransactions(peer_index, req) => self.execute_newtransactions(peer_index, req),
+            Task::RequestFluffyMissingTx(peer_index, req) => {
+                self.execute_requestfluffymissingtx(peer_index, req)
+            }
+            Task::RequestGetObjects(peer_index, req) => {
+                self.execute_requestgetobjects(peer_index, req)
+            }
         }
     }
 }
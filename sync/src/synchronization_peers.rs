@@ -19,7 +19,7 @@ use std::collections::HashMap;
 use parking_lot::RwLock;
 
 use p2p::types::cn::CoreSyncData;
-use p2p::protocol::OutboundSyncConnectionRef;
+use p2p::protocol::{Misbehaviour, OutboundSyncConnectionRef};
 
 use types::PeerIndex;
 
@@ -29,9 +29,25 @@ pub trait Peers: Send + Sync {
               sync_data: &CoreSyncData,
               connection: OutboundSyncConnectionRef);
     fn last_sync_data(&self, peer_index: PeerIndex) -> Option<CoreSyncData>;
+    /// Replaces the synchronization data of the peer.
+    fn set_last_sync_data(&self, peer_index: PeerIndex, sync_data: &CoreSyncData);
     fn connection(&self, peer_index: PeerIndex) -> Option<OutboundSyncConnectionRef>;
+    /// All the connected peers.
+    fn enumerate(&self) -> Vec<PeerIndex>;
+    /// The peers we connected to.
+    fn outbound(&self) -> Vec<PeerIndex>;
 
-    fn misbehaving(&self, peer_index: PeerIndex, reason: &str);
+    /// Forgets the peer and closes it's connection.
+    fn close(&self, peer_index: PeerIndex);
+
+    /// Penalises the peer, it's forgotten if the penalty got it banned.
+    ///
+    /// Returns `true` if the peer was banned.
+    fn misbehaving(&self,
+                   peer_index: PeerIndex,
+                   misbehaviour: Misbehaviour,
+                   reason: &str)
+                   -> bool;
 }
 
 pub struct Peer {
@@ -72,6 +88,12 @@ impl Peers for PeersImpl {
             .map(|peer| peer.last_sync_data.clone())
     }
 
+    fn set_last_sync_data(&self, peer_index: PeerIndex, sync_data: &CoreSyncData) {
+        if let Some(peer) = self.peers.write().get_mut(&peer_index) {
+            peer.last_sync_data = sync_data.clone();
+        }
+    }
+
     fn connection(&self, peer_index: PeerIndex) -> Option<OutboundSyncConnectionRef> {
         self.peers
             .read()
@@ -79,12 +101,47 @@ impl Peers for PeersImpl {
             .map(|peer| peer.connection.clone())
     }
 
-    fn misbehaving(&self, peer_index: PeerIndex, reason: &str) {
+    fn enumerate(&self) -> Vec<PeerIndex> {
+        let mut peers: Vec<PeerIndex> = self.peers.read().keys().cloned().collect();
+        peers.sort();
+        peers
+    }
+
+    fn outbound(&self) -> Vec<PeerIndex> {
+        let mut peers: Vec<PeerIndex> = self.peers
+            .read()
+            .iter()
+            .filter(|&(_, peer)| peer.connection.is_outbound())
+            .map(|(peer_index, _)| *peer_index)
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    fn close(&self, peer_index: PeerIndex) {
         if let Some(peer) = self.peers.write().remove(&peer_index) {
-            warn!("Disconnecting from peer #{} due to misbehaviour: {}",
-                  peer_index,
-                  reason);
             peer.connection.close();
         }
     }
+
+    fn misbehaving(&self,
+                   peer_index: PeerIndex,
+                   misbehaviour: Misbehaviour,
+                   reason: &str)
+                   -> bool {
+        let connection = match self.connection(peer_index) {
+            Some(connection) => connection,
+            None => return false,
+        };
+
+        warn!("Peer #{} misbehaving: {}", peer_index, reason);
+
+        if connection.misbehaving(misbehaviour) {
+            warn!("Peer #{} got banned", peer_index);
+            self.peers.write().remove(&peer_index);
+            true
+        } else {
+            false
+        }
+    }
 }
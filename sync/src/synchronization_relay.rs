@@ -0,0 +1,269 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Dandelion++ transaction relay.
+//!
+//! Transactions start in the stem phase, where each node forwards them to a
+//! single outbound peer, after a few hops a node switches them to the fluff
+//! phase and they get broadcasted to all peers. A node that stems a
+//! transaction keeps an embargo timer, if the transaction wasn't seen being
+//! fluffed when the timer fires the node fluffs it itself.
+
+use std::cmp::min;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use rand::distributions::{Exp, IndependentSample};
+use rand::seq::sample_slice;
+
+use primitives::H256;
+
+use types::PeerIndex;
+
+/// Number of outbound peers used as stems during an epoch.
+pub const DANDELIONPP_STEMS: usize = 2;
+/// Probability (in percent) of being in fluff mode during an epoch.
+pub const DANDELIONPP_FLUFF_PROBABILITY: u32 = 20;
+/// Minimum duration of an epoch in seconds.
+pub const DANDELIONPP_MIN_EPOCH: u64 = 10 * 60;
+/// Maximum random duration added to an epoch in seconds.
+pub const DANDELIONPP_EPOCH_RANGE: u64 = 30;
+/// Average embargo timeout in seconds.
+pub const DANDELIONPP_EMBARGO_AVERAGE: f64 = 39.0;
+
+/// The stem routes of the current epoch.
+#[derive(Debug, Default)]
+pub struct StemGraph {
+    fluff: bool,
+    stems: Vec<PeerIndex>,
+    /// Maps the peer a transaction came from to the stem it's forwarded to,
+    /// `None` is used for the transactions created by us.
+    routes: HashMap<Option<PeerIndex>, PeerIndex>,
+    epoch_end: Option<Instant>,
+}
+
+impl StemGraph {
+    pub fn new() -> StemGraph {
+        StemGraph::default()
+    }
+
+    /// Whether the current epoch has ended.
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.epoch_end.map(|end| now >= end).unwrap_or(true)
+    }
+
+    /// Whether we fluff the stem transactions of other peers in this epoch.
+    pub fn is_fluff(&self) -> bool {
+        self.fluff
+    }
+
+    pub fn stems(&self) -> &[PeerIndex] {
+        &self.stems
+    }
+
+    /// Starts a new epoch, choosing the stems from the given outbound peers.
+    pub fn new_epoch<R: Rng>(&mut self, rng: &mut R, outbound: &[PeerIndex], now: Instant) {
+        let length = DANDELIONPP_MIN_EPOCH + rng.gen_range(0, DANDELIONPP_EPOCH_RANGE + 1);
+
+        self.fluff = rng.gen_range(0, 100) < DANDELIONPP_FLUFF_PROBABILITY;
+        self.stems = sample_slice(rng, outbound, min(DANDELIONPP_STEMS, outbound.len()));
+        self.routes.clear();
+        self.epoch_end = Some(now + Duration::from_secs(length));
+
+        debug!("New Dandelion++ epoch, fluff: {}, stems: {:?}",
+               self.fluff,
+               self.stems);
+    }
+
+    /// Returns the stem for the transactions that came from `source`,
+    /// `None` if there isn't any outbound peer to use.
+    pub fn route<R: Rng>(&mut self,
+                         rng: &mut R,
+                         source: Option<PeerIndex>,
+                         outbound: &[PeerIndex])
+                         -> Option<PeerIndex> {
+        self.update_stems(rng, outbound);
+
+        if self.stems.is_empty() {
+            return None;
+        }
+
+        if let Some(stem) = self.routes.get(&source) {
+            return Some(*stem);
+        }
+
+        let stem = self.stems[rng.gen_range(0, self.stems.len())];
+        self.routes.insert(source, stem);
+        Some(stem)
+    }
+
+    /// Replaces the stems that aren't connected anymore.
+    fn update_stems<R: Rng>(&mut self, rng: &mut R, outbound: &[PeerIndex]) {
+        let len = self.stems.len();
+        self.stems.retain(|stem| outbound.contains(stem));
+
+        let stems = &self.stems;
+        self.routes.retain(|_, stem| stems.contains(stem));
+
+        if self.stems.len() == len && len == DANDELIONPP_STEMS {
+            return;
+        }
+
+        let candidates: Vec<PeerIndex> = outbound
+            .iter()
+            .filter(|peer| !self.stems.contains(peer))
+            .cloned()
+            .collect();
+        let needed = min(DANDELIONPP_STEMS - self.stems.len(), candidates.len());
+        self.stems
+            .extend(sample_slice(rng, &candidates, needed));
+    }
+}
+
+/// Stem transactions waiting to be seen in the fluff phase.
+#[derive(Debug, Default)]
+pub struct Embargoes {
+    timeouts: HashMap<H256, Instant>,
+}
+
+impl Embargoes {
+    pub fn new() -> Embargoes {
+        Embargoes::default()
+    }
+
+    /// Starts the embargo timer of a transaction.
+    pub fn insert<R: Rng>(&mut self, rng: &mut R, id: H256, now: Instant) {
+        let secs = Exp::new(1.0 / DANDELIONPP_EMBARGO_AVERAGE).ind_sample(rng);
+        let timeout = now + Duration::from_millis((secs * 1000.0) as u64);
+
+        self.timeouts.entry(id).or_insert(timeout);
+    }
+
+    /// The transaction was fluffed, stops it's timer.
+    pub fn remove(&mut self, id: &H256) -> bool {
+        self.timeouts.remove(id).is_some()
+    }
+
+    /// Removes and returns the transactions whose embargo expired.
+    pub fn expired(&mut self, now: Instant) -> Vec<H256> {
+        let expired: Vec<H256> = self.timeouts
+            .iter()
+            .filter(|&(_, timeout)| now >= *timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in expired.iter() {
+            self.timeouts.remove(id);
+        }
+
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use rand::{SeedableRng, XorShiftRng};
+
+    use primitives::H256;
+
+    use super::*;
+
+    fn rng() -> XorShiftRng {
+        XorShiftRng::from_seed([1, 2, 3, 4])
+    }
+
+    #[test]
+    fn stems_are_outbound_peers() {
+        let mut rng = rng();
+        let outbound = [1, 2, 3, 4, 5];
+        let mut graph = StemGraph::new();
+        let now = Instant::now();
+
+        assert!(graph.is_expired(now));
+        graph.new_epoch(&mut rng, &outbound, now);
+        assert!(!graph.is_expired(now));
+        assert!(graph.is_expired(now + Duration::from_secs(DANDELIONPP_MIN_EPOCH +
+                                                          DANDELIONPP_EPOCH_RANGE)));
+
+        assert_eq!(graph.stems().len(), DANDELIONPP_STEMS);
+        assert!(graph.stems().iter().all(|stem| outbound.contains(stem)));
+
+        // The routes stay the same during the epoch.
+        let local = graph.route(&mut rng, None, &outbound).unwrap();
+        let from_10 = graph.route(&mut rng, Some(10), &outbound).unwrap();
+        for _ in 0..10 {
+            assert_eq!(graph.route(&mut rng, None, &outbound), Some(local));
+            assert_eq!(graph.route(&mut rng, Some(10), &outbound), Some(from_10));
+        }
+    }
+
+    #[test]
+    fn stems_are_replaced_on_disconnect() {
+        let mut rng = rng();
+        let mut graph = StemGraph::new();
+        graph.new_epoch(&mut rng, &[1, 2, 3], Instant::now());
+
+        let stem = graph.route(&mut rng, None, &[1, 2, 3]).unwrap();
+        let outbound: Vec<PeerIndex> = vec![1, 2, 3]
+            .into_iter()
+            .filter(|peer| *peer != stem)
+            .collect();
+
+        let new_stem = graph.route(&mut rng, None, &outbound).unwrap();
+        assert!(new_stem != stem);
+        assert_eq!(graph.stems().len(), 2);
+        assert!(graph.stems().iter().all(|stem| outbound.contains(stem)));
+
+        assert_eq!(graph.route(&mut rng, None, &[]), None);
+        assert!(graph.stems().is_empty());
+    }
+
+    #[test]
+    fn epoch_is_deterministic() {
+        let outbound = [1, 2, 3, 4, 5, 6, 7, 8];
+        let now = Instant::now();
+        let (mut graph1, mut graph2) = (StemGraph::new(), StemGraph::new());
+        let (mut rng1, mut rng2) = (rng(), rng());
+
+        for _ in 0..10 {
+            graph1.new_epoch(&mut rng1, &outbound, now);
+            graph2.new_epoch(&mut rng2, &outbound, now);
+
+            assert_eq!(graph1.is_fluff(), graph2.is_fluff());
+            assert_eq!(graph1.stems(), graph2.stems());
+            assert_eq!(graph1.route(&mut rng1, Some(1), &outbound),
+                       graph2.route(&mut rng2, Some(1), &outbound));
+        }
+    }
+
+    #[test]
+    fn embargo_expires() {
+        let mut rng = rng();
+        let mut embargoes = Embargoes::new();
+        let now = Instant::now();
+
+        let (id1, id2) = (H256([1; 32]), H256([2; 32]));
+        embargoes.insert(&mut rng, id1.clone(), now);
+        embargoes.insert(&mut rng, id2.clone(), now);
+        assert!(embargoes.remove(&id2));
+
+        assert_eq!(embargoes.expired(now + Duration::from_secs(3600)), vec![id1]);
+        assert!(embargoes.expired(now + Duration::from_secs(3600)).is_empty());
+    }
+}
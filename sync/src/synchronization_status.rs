@@ -0,0 +1,263 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Synchronization progress tracking.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+use types::PeerIndex;
+
+/// Window in seconds used to compute the block download rate.
+pub const RATE_WINDOW: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SynchronizationState {
+    /// We don't have any peer to synchronize with.
+    Idle,
+    /// A peer claims a higher chain than ours.
+    Synchronizing,
+    /// We are at the height of the best known peer.
+    Synchronized,
+}
+
+/// What a peer contributed to the synchronization.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerStatus {
+    pub peer_index: PeerIndex,
+    /// The height the peer claims to have.
+    pub remote_height: u64,
+    /// Blocks received from the peer that we inserted.
+    pub blocks: u64,
+    /// Bytes of blocks and transactions received from the peer.
+    pub bytes: u64,
+}
+
+/// A snapshot of the synchronization progress.
+#[derive(Debug, Clone)]
+pub struct SyncStatus {
+    pub state: SynchronizationState,
+    pub local_height: u64,
+    /// The highest height claimed by a connected peer.
+    pub best_peer_height: u64,
+    pub blocks_per_second: f64,
+    pub bytes_downloaded: u64,
+    /// Estimated time until we're synchronized, unknown if we aren't
+    /// receiving blocks.
+    pub eta: Option<Duration>,
+    pub peers: Vec<PeerStatus>,
+}
+
+impl SyncStatus {
+    /// Percentage of the best known chain we have.
+    pub fn progress(&self) -> f64 {
+        if self.best_peer_height == 0 || self.local_height >= self.best_peer_height {
+            100.0
+        } else {
+            self.local_height as f64 * 100.0 / self.best_peer_height as f64
+        }
+    }
+}
+
+impl Display for SyncStatus {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt,
+               "{:?} {}/{} ({:.2}%), {:.2} blocks/s, {} bytes downloaded",
+               self.state,
+               self.local_height,
+               self.best_peer_height,
+               self.progress(),
+               self.blocks_per_second,
+               self.bytes_downloaded)?;
+
+        if let Some(eta) = self.eta {
+            write!(fmt, ", ETA {}s", eta.as_secs())?;
+        }
+
+        write!(fmt, ", {} peers", self.peers.len())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct PeerStats {
+    blocks: u64,
+    bytes: u64,
+}
+
+/// Collects the statistics used to build a `SyncStatus`.
+#[derive(Debug, Default)]
+pub struct SyncStats {
+    started: Option<Instant>,
+    bytes_downloaded: u64,
+    recent_blocks: VecDeque<Instant>,
+    peers: HashMap<PeerIndex, PeerStats>,
+}
+
+impl SyncStats {
+    pub fn new() -> SyncStats {
+        SyncStats::default()
+    }
+
+    /// Notes bytes of blocks or transactions received from a peer.
+    pub fn on_bytes(&mut self, peer_index: PeerIndex, bytes: u64, now: Instant) {
+        self.start(now);
+        self.bytes_downloaded += bytes;
+        self.peers.entry(peer_index).or_default().bytes += bytes;
+    }
+
+    /// Notes a block received from a peer that got inserted.
+    pub fn on_block(&mut self, peer_index: PeerIndex, now: Instant) {
+        self.start(now);
+        self.recent_blocks.push_back(now);
+        self.peers.entry(peer_index).or_default().blocks += 1;
+    }
+
+    pub fn blocks_per_second(&mut self, now: Instant) -> f64 {
+        let window = Duration::from_secs(RATE_WINDOW);
+        while self.recent_blocks
+                  .front()
+                  .map(|t| now.duration_since(*t) > window)
+                  .unwrap_or(false) {
+            self.recent_blocks.pop_front();
+        }
+
+        let elapsed = match self.started {
+            Some(started) => ::std::cmp::min(now.duration_since(started), window),
+            None => return 0.0,
+        };
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+        if elapsed == 0.0 {
+            0.0
+        } else {
+            self.recent_blocks.len() as f64 / elapsed
+        }
+    }
+
+    /// Builds the status given our height and the heights claimed by the
+    /// connected peers.
+    pub fn status(&mut self,
+                  local_height: u64,
+                  peers: &[(PeerIndex, u64)],
+                  now: Instant)
+                  -> SyncStatus {
+        let best_peer_height = peers.iter().map(|&(_, height)| height).max().unwrap_or(0);
+
+        let state = if peers.is_empty() {
+            SynchronizationState::Idle
+        } else if best_peer_height > local_height {
+            SynchronizationState::Synchronizing
+        } else {
+            SynchronizationState::Synchronized
+        };
+
+        let blocks_per_second = self.blocks_per_second(now);
+        let eta = if state != SynchronizationState::Synchronizing {
+            Some(Duration::from_secs(0))
+        } else if blocks_per_second > 0.0 {
+            let remaining = (best_peer_height - local_height) as f64;
+            Some(Duration::from_secs((remaining / blocks_per_second).ceil() as u64))
+        } else {
+            None
+        };
+
+        let mut peers: Vec<PeerStatus> = peers
+            .iter()
+            .map(|&(peer_index, remote_height)| {
+                let stats = self.peers.get(&peer_index).cloned().unwrap_or_default();
+                PeerStatus {
+                    peer_index,
+                    remote_height,
+                    blocks: stats.blocks,
+                    bytes: stats.bytes,
+                }
+            })
+            .collect();
+        peers.sort_by_key(|peer| peer.peer_index);
+
+        SyncStatus {
+            state,
+            local_height,
+            best_peer_height,
+            blocks_per_second,
+            bytes_downloaded: self.bytes_downloaded,
+            eta,
+            peers,
+        }
+    }
+
+    fn start(&mut self, now: Instant) {
+        if self.started.is_none() {
+            self.started = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn state() {
+        let now = Instant::now();
+        let mut stats = SyncStats::new();
+
+        assert_eq!(stats.status(10, &[], now).state, SynchronizationState::Idle);
+        assert_eq!(stats.status(10, &[(0, 10)], now).state,
+                   SynchronizationState::Synchronized);
+
+        let status = stats.status(10, &[(0, 10), (1, 20)], now);
+        assert_eq!(status.state, SynchronizationState::Synchronizing);
+        assert_eq!(status.best_peer_height, 20);
+        assert_eq!(status.eta, None);
+    }
+
+    #[test]
+    fn rate_and_contribution() {
+        let start = Instant::now();
+        let mut stats = SyncStats::new();
+
+        for i in 0..20usize {
+            let now = start + Duration::from_secs(i as u64);
+            stats.on_bytes(i % 2, 100, now);
+            stats.on_block(i % 2, now);
+        }
+
+        let now = start + Duration::from_secs(20);
+        let status = stats.status(20, &[(0, 120), (1, 100)], now);
+        assert_eq!(status.blocks_per_second, 1.0);
+        assert_eq!(status.eta, Some(Duration::from_secs(100)));
+        assert_eq!(status.bytes_downloaded, 2000);
+        assert_eq!(status.peers,
+                   vec![PeerStatus {
+                            peer_index: 0,
+                            remote_height: 120,
+                            blocks: 10,
+                            bytes: 1000,
+                        },
+                        PeerStatus {
+                            peer_index: 1,
+                            remote_height: 100,
+                            blocks: 10,
+                            bytes: 1000,
+                        }]);
+
+        // Blocks out of the window aren't counted.
+        let now = start + Duration::from_secs(20 + RATE_WINDOW);
+        assert_eq!(stats.blocks_per_second(now), 0.0);
+    }
+}
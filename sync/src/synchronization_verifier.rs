@@ -0,0 +1,199 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parallel block verification.
+//!
+//! The blocks are verified in two stages, the context free checks
+//! (transaction structure, PoW hash...) run in parallel on a worker pool,
+//! then the checks that depend on the chain, like the difficulty, are done
+//! in height order when the blocks are committed.
+
+use std::collections::HashMap;
+
+use futures::Future;
+use futures::future::join_all;
+use futures_cpupool::CpuPool;
+
+use chain::{Block, IndexedBlock, IndexedTransaction};
+use format::from_binary;
+use network::Checkpoints;
+use p2p::types::cn::BlockCompleteEntry;
+use primitives::H256;
+use verification::{BlockError, Difficulty, VerificationLevel, miner_transaction_height,
+                   verify_block_context_free};
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "couldn't parse the block or it's transactions")]
+    InvalidBlob,
+    #[fail(display = "transaction {:?} of the block is missing", _0)]
+    MissingTransaction(H256),
+    #[fail(display = "{}", _0)]
+    Verification(#[cause]
+                 BlockError),
+}
+
+/// The expensive checks are skipped for the blocks below the last
+/// checkpoint.
+pub fn verification_level(checkpoints: &Checkpoints, height: u64) -> VerificationLevel {
+    if checkpoints.is_in_checkpoint_zone(height) {
+        VerificationLevel::NoExpensive
+    } else {
+        VerificationLevel::Full
+    }
+}
+
+/// A block that passed the context free checks.
+pub struct VerifiedBlock {
+    pub block: IndexedBlock,
+    /// The proof of work hash, `None` when the expensive checks are skipped.
+    pub pow_hash: Option<H256>,
+}
+
+/// Runs the context free checks of blocks on a worker pool.
+pub struct BlockVerifier {
+    pool: CpuPool,
+    checkpoints: Checkpoints,
+}
+
+impl BlockVerifier {
+    pub fn new(pool: CpuPool, checkpoints: Checkpoints) -> BlockVerifier {
+        BlockVerifier { pool, checkpoints }
+    }
+
+    /// Parses and verifies the entries in parallel, the results are in the
+    /// same order as the entries.
+    pub fn verify(&self, entries: Vec<BlockCompleteEntry>) -> Vec<Result<VerifiedBlock, Error>> {
+        let jobs: Vec<_> = entries
+            .into_iter()
+            .map(|entry| {
+                let checkpoints = self.checkpoints.clone();
+                self.pool
+                    .spawn_fn(move || -> Result<_, ()> { Ok(verify_entry(&checkpoints, entry)) })
+            })
+            .collect();
+
+        join_all(jobs)
+            .wait()
+            .expect("verification jobs don't fail")
+    }
+}
+
+fn verify_entry(checkpoints: &Checkpoints,
+                entry: BlockCompleteEntry)
+                -> Result<VerifiedBlock, Error> {
+    let block = parse_entry(entry)?;
+
+    // The claimed height is checked against the real one when the block is
+    // committed.
+    let level = miner_transaction_height(&block)
+        .map(|height| verification_level(checkpoints, height))
+        .unwrap_or(VerificationLevel::Full);
+
+    // The difficulty depends on the parent, only the hash is computed here
+    // and it's checked when the block is committed.
+    verify_block_context_free(&block, Difficulty(1), VerificationLevel::NoExpensive)
+        .map_err(Error::Verification)?;
+    let pow_hash = match level {
+        VerificationLevel::Full => Some(block.raw.hash()),
+        VerificationLevel::NoExpensive => None,
+    };
+
+    Ok(VerifiedBlock { block, pow_hash })
+}
+
+/// Parses a block and orders it's transactions like `tx_hashes`.
+pub fn parse_entry(entry: BlockCompleteEntry) -> Result<IndexedBlock, Error> {
+    let block: Block = from_binary(&entry.block.0).map_err(|_| Error::InvalidBlob)?;
+
+    let mut txs = HashMap::with_capacity(entry.txs.len());
+    for blob in entry.txs.iter() {
+        let tx = IndexedTransaction::from_bytes(&blob.0).map_err(|_| Error::InvalidBlob)?;
+        txs.insert(tx.id.clone(), tx);
+    }
+
+    let transactions = block
+        .tx_hashes
+        .iter()
+        .map(|id| {
+                 txs.remove(id)
+                     .ok_or_else(|| Error::MissingTransaction(id.clone()))
+             })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let id = block.id();
+    Ok(IndexedBlock::with_transactions(block, id, transactions))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use chain::BlockHeader;
+    use chain::transaction::{SignatureType, Transaction, TransactionPrefix, TxInGen, TxOut,
+                             TxOutToKey};
+    use format::to_binary;
+    use portable_storage_utils::Blob;
+
+    use super::*;
+
+    fn entry(height: u64) -> BlockCompleteEntry {
+        let block = Block {
+            header: BlockHeader::default(),
+            miner_tx: Transaction {
+                prefix: TransactionPrefix {
+                    version: 1,
+                    unlock_time: 0,
+                    vin: vec![TxInGen { height }.into()],
+                    vout: vec![TxOut {
+                                   amount: 1000,
+                                   target: TxOutToKey { key: Default::default() }.into(),
+                               }],
+                    extra: vec![],
+                },
+                signature_type: SignatureType::Normal(vec![]),
+            },
+            tx_hashes: vec![],
+        };
+
+        BlockCompleteEntry {
+            block: Blob(to_binary(&block).to_vec()),
+            txs: vec![],
+        }
+    }
+
+    #[test]
+    fn results_keep_order() {
+        let mut checkpoints = Checkpoints::new();
+        checkpoints.insert(16, H256::new()).unwrap();
+
+        let verifier = BlockVerifier::new(CpuPool::new(4), checkpoints);
+
+        let mut entries: Vec<BlockCompleteEntry> = (1..33).map(entry).collect();
+        entries[10].block.0.truncate(10);
+
+        let results = verifier.verify(entries);
+        assert_eq!(results.len(), 32);
+        for (i, result) in results.iter().enumerate() {
+            match *result {
+                Ok(ref verified) => {
+                    assert_eq!(miner_transaction_height(&verified.block), Some(i as u64 + 1));
+                    assert_eq!(verified.pow_hash.is_some(), i + 1 > 16);
+                }
+                Err(Error::InvalidBlob) => assert_eq!(i, 10),
+                Err(ref e) => panic!("unexpected error {}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,387 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Mock peers and chains of fake blocks to test the synchronization
+//! without sockets.
+
+use std::collections::LinkedList;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::Mutex;
+
+use chain::{Block, BlockHeader, IndexedBlock};
+use chain::transaction::{SignatureType, Transaction, TransactionPrefix, TxInGen, TxOut,
+                         TxOutToKey};
+use db::BlockChainDatabase;
+use format::to_binary;
+use network::{Checkpoints, Network};
+use p2p::protocol::{BAN_SCORE, InboundSyncConnectionRef, LocalSyncNode, Misbehaviour,
+                    OutboundSyncConnection};
+use p2p::types::cn::{BlockCompleteEntry, CoreSyncData};
+use p2p::types::cn::cmd::{NewBlock, NewFluffyBlock, NewTransactions, RequestChain,
+                          RequestFluffyMissingTx, RequestGetObjects, ResponseChainEntry,
+                          ResponseGetObjects};
+use portable_storage_utils::Blob;
+use primitives::H256;
+use verification::DIFFICULTY_TARGET_V1;
+
+use clock::ManualClock;
+use connection_factory::ConnectionFactory;
+use local_node::LocalNode;
+use types::{LocalNodeRef, StorageRef};
+
+/// A message sent to a mock peer.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum Message {
+    NewBlock(NewBlock),
+    NewFluffyBlock(NewFluffyBlock),
+    NewTransactions(NewTransactions),
+    RequestChain(RequestChain),
+    RequestFluffyMissingTx(RequestFluffyMissingTx),
+    RequestGetObjects(RequestGetObjects),
+    ResponseChainEntry(ResponseChainEntry),
+    ResponseGetObjects(ResponseGetObjects),
+}
+
+/// An `OutboundSyncConnection` that records what's sent to the peer.
+#[derive(Debug, Default)]
+pub struct MockConnection {
+    outbound: bool,
+    sent: Mutex<Vec<Message>>,
+    misbehaviours: Mutex<Vec<Misbehaviour>>,
+    closed: AtomicBool,
+}
+
+impl MockConnection {
+    pub fn new(outbound: bool) -> MockConnection {
+        MockConnection {
+            outbound,
+            ..MockConnection::default()
+        }
+    }
+
+    /// Takes the messages sent since the last call.
+    pub fn take_sent(&self) -> Vec<Message> {
+        self.sent.lock().drain(..).collect()
+    }
+
+    /// The penalties given to the peer.
+    pub fn misbehaviours(&self) -> Vec<Misbehaviour> {
+        self.misbehaviours.lock().clone()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    fn send(&self, message: Message) {
+        self.sent.lock().push(message);
+    }
+}
+
+impl OutboundSyncConnection for MockConnection {
+    fn notify_new_block(&self, arg: &NewBlock) {
+        self.send(Message::NewBlock(arg.clone()));
+    }
+
+    fn notify_new_fluffy_block(&self, arg: &NewFluffyBlock) {
+        self.send(Message::NewFluffyBlock(arg.clone()));
+    }
+
+    fn notify_new_transactions(&self, arg: &NewTransactions) {
+        self.send(Message::NewTransactions(arg.clone()));
+    }
+
+    fn notify_request_chain(&self, arg: &RequestChain) {
+        self.send(Message::RequestChain(arg.clone()));
+    }
+
+    fn notify_request_fluffy_missing_tx(&self, arg: &RequestFluffyMissingTx) {
+        self.send(Message::RequestFluffyMissingTx(arg.clone()));
+    }
+
+    fn notify_request_get_objects(&self, arg: &RequestGetObjects) {
+        self.send(Message::RequestGetObjects(arg.clone()));
+    }
+
+    fn notify_response_chain_entry(&self, arg: &ResponseChainEntry) {
+        self.send(Message::ResponseChainEntry(arg.clone()));
+    }
+
+    fn notify_response_get_objects(&self, arg: &ResponseGetObjects) {
+        self.send(Message::ResponseGetObjects(arg.clone()));
+    }
+
+    fn is_outbound(&self) -> bool {
+        self.outbound
+    }
+
+    fn misbehaving(&self, misbehaviour: Misbehaviour) -> bool {
+        let mut misbehaviours = self.misbehaviours.lock();
+        misbehaviours.push(misbehaviour);
+
+        let score: u32 = misbehaviours.iter().map(Misbehaviour::penalty).sum();
+        if score >= BAN_SCORE {
+            self.close();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A chain of fake blocks on top of the network genesis block.
+///
+/// By default the blocks are one target apart so every block has
+/// difficulty 1, and with it any proof of work is valid.
+#[derive(Clone)]
+pub struct FakeChain {
+    blocks: Vec<IndexedBlock>,
+    /// Makes the blocks of forks different from the ones they forked from.
+    salt: u32,
+    /// Seconds between the blocks.
+    block_time: u64,
+}
+
+impl FakeChain {
+    pub fn new(network: Network) -> FakeChain {
+        FakeChain {
+            blocks: vec![network.genesis_block().into()],
+            salt: 0,
+            block_time: DIFFICULTY_TARGET_V1,
+        }
+    }
+
+    /// Sets the seconds between the blocks, blocks faster than the target
+    /// raise the difficulty.
+    pub fn with_block_time(mut self, block_time: u64) -> FakeChain {
+        self.block_time = block_time;
+        self
+    }
+
+    /// Adds `count` blocks on top of the chain.
+    pub fn extend(mut self, count: usize) -> FakeChain {
+        for _ in 0..count {
+            let height = self.blocks.len() as u64;
+            let block = Block {
+                header: BlockHeader {
+                    major_version: 1,
+                    minor_version: 0,
+                    timestamp: height * self.block_time,
+                    prev_id: self.top_id(),
+                    nonce: self.salt,
+                },
+                miner_tx: Transaction {
+                    prefix: TransactionPrefix {
+                        version: 1,
+                        unlock_time: height + 60,
+                        vin: vec![TxInGen { height }.into()],
+                        vout: vec![TxOut {
+                                       amount: 1000,
+                                       target: TxOutToKey { key: Default::default() }.into(),
+                                   }],
+                        extra: vec![],
+                    },
+                    signature_type: SignatureType::Normal(vec![]),
+                },
+                tx_hashes: vec![],
+            };
+
+            self.blocks.push(block.into());
+        }
+
+        self
+    }
+
+    /// A chain with the first `height` blocks of this one, the blocks added
+    /// to it are different from the ones of this chain.
+    pub fn fork(&self, height: u64) -> FakeChain {
+        FakeChain {
+            blocks: self.blocks[..height as usize].to_vec(),
+            salt: self.salt + 1,
+            block_time: self.block_time,
+        }
+    }
+
+    /// Number of blocks, counting the genesis block.
+    pub fn height(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    pub fn blocks(&self) -> &[IndexedBlock] {
+        &self.blocks
+    }
+
+    pub fn id(&self, height: u64) -> H256 {
+        self.blocks[height as usize].id().clone()
+    }
+
+    pub fn top_id(&self) -> H256 {
+        self.id(self.height() - 1)
+    }
+
+    /// The height of the block, `None` if it isn't in the chain.
+    pub fn find(&self, id: &H256) -> Option<u64> {
+        self.blocks
+            .iter()
+            .position(|block| block.id() == id)
+            .map(|height| height as u64)
+    }
+
+    pub fn cumulative_difficulty(&self) -> u64 {
+        self.height()
+    }
+
+    pub fn sync_data(&self) -> CoreSyncData {
+        CoreSyncData {
+            cumulative_difficulty: self.cumulative_difficulty(),
+            current_height: self.height(),
+            top_id: self.top_id(),
+            top_version: 1,
+        }
+    }
+
+    /// Answers a `RequestChain` like monerod, with the ids from the newest
+    /// block of the request that we have.
+    pub fn chain_entry(&self, request: &RequestChain) -> ResponseChainEntry {
+        let start_height = request
+            .block_ids
+            .0
+            .iter()
+            .filter_map(|id| self.find(id))
+            .next()
+            .unwrap_or(0);
+
+        ResponseChainEntry {
+            start_height,
+            total_height: self.height(),
+            cummulative_difficulty: self.cumulative_difficulty(),
+            block_ids: self.blocks[start_height as usize..]
+                .iter()
+                .map(|block| block.id().clone())
+                .collect::<LinkedList<H256>>()
+                .into(),
+        }
+    }
+
+    pub fn complete_entry(&self, height: u64) -> BlockCompleteEntry {
+        BlockCompleteEntry {
+            block: Blob(to_binary(&self.blocks[height as usize].raw).to_vec()),
+            txs: vec![],
+        }
+    }
+
+    /// Answers a `RequestGetObjects`.
+    pub fn get_objects(&self, request: &RequestGetObjects) -> ResponseGetObjects {
+        let mut blocks = Vec::new();
+        let mut missed_ids = Vec::new();
+        for id in request.blocks.iter() {
+            match self.find(id) {
+                Some(height) => blocks.push(self.complete_entry(height)),
+                None => missed_ids.push(id.clone()),
+            }
+        }
+
+        ResponseGetObjects {
+            txs: vec![],
+            blocks,
+            missed_ids,
+            current_blockchain_height: self.height(),
+        }
+    }
+}
+
+/// A peer connected to the node of a `Harness`.
+pub struct MockPeer {
+    pub connection: Arc<MockConnection>,
+    pub inbound: InboundSyncConnectionRef,
+}
+
+impl MockPeer {
+    /// Answers the requests the peer got with blocks of `chain`, returns the
+    /// number of answered requests.
+    pub fn respond(&self, chain: &FakeChain) -> usize {
+        let mut answered = 0;
+        for message in self.connection.take_sent() {
+            match message {
+                Message::RequestChain(ref request) => {
+                    self.inbound.on_response_chain_entry(&chain.chain_entry(request));
+                }
+                Message::RequestGetObjects(ref request) => {
+                    self.inbound.on_response_get_objects(&chain.get_objects(request));
+                }
+                _ => continue,
+            }
+
+            answered += 1;
+        }
+
+        answered
+    }
+
+    /// Answers the requests until the peer stops asking.
+    pub fn serve(&self, chain: &FakeChain) {
+        while self.respond(chain) != 0 {}
+    }
+}
+
+/// A node with an in memory database and a manual clock.
+pub struct Harness {
+    pub storage: StorageRef,
+    pub clock: Arc<ManualClock>,
+    pub local_node: LocalNodeRef,
+    sync_node: ConnectionFactory,
+}
+
+impl Harness {
+    /// A node whose database has the blocks of `chain`.
+    pub fn new(chain: &FakeChain) -> Harness {
+        let db = BlockChainDatabase::in_memory();
+        for block in chain.blocks() {
+            db.insert(block.clone(), 1).unwrap();
+            db.canonize(block.id()).unwrap();
+        }
+
+        let storage: StorageRef = Arc::new(db);
+        let clock = Arc::new(ManualClock::new());
+        let local_node = Arc::new(LocalNode::new(storage.clone(),
+                                                 Network::Mainnet,
+                                                 Checkpoints::new(),
+                                                 clock.clone()));
+
+        Harness {
+            storage,
+            clock,
+            sync_node: ConnectionFactory::new(local_node.clone()),
+            local_node,
+        }
+    }
+
+    /// Connects an outbound peer that announces `sync_data`.
+    pub fn connect(&self, sync_data: &CoreSyncData) -> MockPeer {
+        let connection = Arc::new(MockConnection::new(true));
+        let inbound = self.sync_node
+            .new_sync_connection(sync_data, connection.clone());
+
+        MockPeer { connection, inbound }
+    }
+}
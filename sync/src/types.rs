@@ -19,7 +19,9 @@ use std::sync::Arc;
 use parking_lot::Mutex;
 
 use storage::SharedStore;
+use txpool::TransactionPool;
 
+use clock::Clock;
 use local_node::LocalNode;
 use synchronization_client::SynchronizationClient;
 use synchronization_client_core::SynchronizationClientCore;
@@ -30,6 +32,8 @@ pub type LocalNodeRef = Arc<LocalNode>;
 pub type ClientRef = Arc<SynchronizationClient>;
 pub type ClientCoreRef = Arc<Mutex<SynchronizationClientCore>>;
 pub type PeersRef = Arc<Peers>;
+pub type MemoryPoolRef = Arc<TransactionPool>;
 pub type ExecutorRef = Arc<LocalSynchronizationTaskExecutor>;
 pub type StorageRef = SharedStore;
+pub type ClockRef = Arc<Clock>;
 pub type PeerIndex = usize;
@@ -0,0 +1,41 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use keys::KeyImage;
+use verification::TransactionError;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "invalid transaction: {}", _0)]
+    Verification(#[cause]
+                 TransactionError),
+    #[fail(display = "transaction is already in the pool")]
+    AlreadyInPool,
+    #[fail(display = "transaction is already in the blockchain")]
+    AlreadyInChain,
+    #[fail(display = "key image {:?} is already spent in the pool", _0)]
+    DoubleSpendInPool(KeyImage),
+    #[fail(display = "key image {:?} is already spent in the blockchain", _0)]
+    DoubleSpendInChain(KeyImage),
+    #[fail(display = "the pool is full")]
+    PoolFull,
+}
+
+impl From<TransactionError> for Error {
+    fn from(e: TransactionError) -> Error {
+        Error::Verification(e)
+    }
+}
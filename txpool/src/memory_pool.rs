@@ -0,0 +1,335 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use chain::IndexedTransaction;
+use chain::transaction::TxIn;
+use format::to_binary;
+use keys::KeyImage;
+use primitives::H256;
+
+use error::Error;
+
+/// Default maximum size of the pool in bytes.
+pub const DEFAULT_MAX_SIZE: usize = 648000000;
+/// Default time a transaction can stay in the pool.
+pub const DEFAULT_MAX_AGE: u64 = 86400 * 3;
+
+/// Memory pool limits.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Maximum size of all the transactions in bytes.
+    pub max_size: usize,
+    /// Maximum time a transaction can stay in the pool.
+    pub max_age: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            max_size: DEFAULT_MAX_SIZE,
+            max_age: Duration::from_secs(DEFAULT_MAX_AGE),
+        }
+    }
+}
+
+/// A transaction in the pool.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub transaction: IndexedTransaction,
+    /// Size of the transaction blob.
+    pub size: usize,
+    pub fee: u64,
+    /// When the transaction was added to the pool.
+    pub received: Instant,
+    /// Insertion order, used to break fee ties.
+    sequence: u64,
+}
+
+impl Entry {
+    pub fn fee_per_byte(&self) -> u64 {
+        self.fee / self.size as u64
+    }
+
+    fn fee_key(&self) -> (u64, u64) {
+        (self.fee_per_byte(), self.sequence)
+    }
+}
+
+/// Transactions indexed by id, key image and fee per byte.
+#[derive(Debug)]
+pub struct MemoryPool {
+    config: Config,
+    by_id: HashMap<H256, Entry>,
+    by_key_image: HashMap<KeyImage, H256>,
+    by_fee_per_byte: BTreeMap<(u64, u64), H256>,
+    size: usize,
+    sequence: u64,
+}
+
+impl MemoryPool {
+    pub fn new(config: Config) -> MemoryPool {
+        MemoryPool {
+            config,
+            by_id: HashMap::new(),
+            by_key_image: HashMap::new(),
+            by_fee_per_byte: BTreeMap::new(),
+            size: 0,
+            sequence: 0,
+        }
+    }
+
+    /// Number of transactions in the pool.
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    /// Size of the transactions in the pool in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn contains(&self, id: &H256) -> bool {
+        self.by_id.contains_key(id)
+    }
+
+    pub fn contains_key_image(&self, key_image: &KeyImage) -> bool {
+        self.by_key_image.contains_key(key_image)
+    }
+
+    pub fn entry(&self, id: &H256) -> Option<&Entry> {
+        self.by_id.get(id)
+    }
+
+    pub fn transaction(&self, id: &H256) -> Option<IndexedTransaction> {
+        self.by_id.get(id).map(|entry| entry.transaction.clone())
+    }
+
+    /// Transaction ids sorted from the highest to the lowest fee per byte.
+    pub fn by_fee_per_byte(&self) -> Vec<H256> {
+        self.by_fee_per_byte.values().rev().cloned().collect()
+    }
+
+    /// Inserts a transaction, evicting the ones with the lowest fee per byte
+    /// if the pool is full.
+    pub fn insert(&mut self,
+                  transaction: IndexedTransaction,
+                  fee: u64,
+                  received: Instant)
+                  -> Result<(), Error> {
+        if self.contains(transaction.id()) {
+            return Err(Error::AlreadyInPool);
+        }
+
+        if let Some(key_image) = key_images(&transaction)
+               .find(|key_image| self.contains_key_image(key_image)) {
+            return Err(Error::DoubleSpendInPool(key_image.clone()));
+        }
+
+        let size = to_binary(&transaction.raw).len();
+        let entry = Entry {
+            transaction,
+            size,
+            fee,
+            received,
+            sequence: self.sequence,
+        };
+
+        while self.size + entry.size > self.config.max_size {
+            let lowest = match self.by_fee_per_byte.iter().next() {
+                Some((&(fee_per_byte, _), id)) if fee_per_byte < entry.fee_per_byte() => {
+                    id.clone()
+                }
+                _ => return Err(Error::PoolFull),
+            };
+
+            debug!("Evicting transaction {:?}, the pool is full", lowest);
+            self.remove(&lowest);
+        }
+
+        let id = entry.transaction.id().clone();
+        for key_image in key_images(&entry.transaction) {
+            self.by_key_image.insert(key_image.clone(), id.clone());
+        }
+
+        self.by_fee_per_byte.insert(entry.fee_key(), id.clone());
+        self.size += entry.size;
+        self.sequence += 1;
+        self.by_id.insert(id, entry);
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &H256) -> Option<IndexedTransaction> {
+        let entry = self.by_id.remove(id)?;
+
+        for key_image in key_images(&entry.transaction) {
+            self.by_key_image.remove(key_image);
+        }
+
+        self.by_fee_per_byte.remove(&entry.fee_key());
+        self.size -= entry.size;
+
+        Some(entry.transaction)
+    }
+
+    /// Removes the transaction spending the given key image.
+    pub fn remove_by_key_image(&mut self, key_image: &KeyImage) -> Option<IndexedTransaction> {
+        let id = self.by_key_image.get(key_image)?.clone();
+        self.remove(&id)
+    }
+
+    /// Removes the transactions that are older than the maximum age.
+    pub fn remove_expired(&mut self, now: Instant) -> Vec<IndexedTransaction> {
+        let max_age = self.config.max_age;
+        let expired: Vec<H256> = self.by_id
+            .values()
+            .filter(|entry| now > entry.received && now - entry.received > max_age)
+            .map(|entry| entry.transaction.id().clone())
+            .collect();
+
+        expired.iter().filter_map(|id| self.remove(id)).collect()
+    }
+}
+
+/// Key images spent by a transaction.
+pub fn key_images<'a>(tx: &'a IndexedTransaction) -> Box<Iterator<Item = &'a KeyImage> + 'a> {
+    Box::new(tx.raw
+                 .prefix
+                 .vin
+                 .iter()
+                 .filter_map(|txin| match *txin {
+                                 TxIn::ToKey(ref txin) => Some(&txin.k_image),
+                                 _ => None,
+                             }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::time::{Duration, Instant};
+
+    use chain::IndexedTransaction;
+    use chain::transaction::{SignatureType, Transaction, TransactionPrefix, TxInToKey, TxOut,
+                             TxOutToKey};
+    use error::Error;
+    use keys::Signature;
+    use super::*;
+
+    /// Creates a transaction with the given fee, all of them have the same
+    /// size as long as the fee is small.
+    pub fn transaction(k_image: u8, fee: u64) -> IndexedTransaction {
+        let mut txin = TxInToKey {
+            amount: 1000000 + fee,
+            key_offsets: vec![1],
+            k_image: Default::default(),
+        };
+        txin.k_image.0[0] = k_image;
+
+        Transaction {
+                prefix: TransactionPrefix {
+                    version: 1,
+                    unlock_time: 0,
+                    vin: vec![txin.into()],
+                    vout: vec![TxOut {
+                                   amount: 1000000,
+                                   target: TxOutToKey { key: Default::default() }.into(),
+                               }],
+                    extra: vec![],
+                },
+                signature_type: SignatureType::Normal(vec![vec![Signature::new()]]),
+            }
+            .into()
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut pool = MemoryPool::new(Config::default());
+        let tx = transaction(1, 100);
+        let id = tx.id().clone();
+        let key_image = key_images(&tx).next().cloned().unwrap();
+
+        pool.insert(tx.clone(), 100, Instant::now()).unwrap();
+        assert!(pool.contains(&id));
+        assert!(pool.contains_key_image(&key_image));
+        assert_eq!(pool.size(), to_binary(&tx.raw).len());
+
+        match pool.insert(tx, 100, Instant::now()) {
+            Err(Error::AlreadyInPool) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        match pool.insert(transaction(1, 200), 200, Instant::now()) {
+            Err(Error::DoubleSpendInPool(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        assert_eq!(pool.remove_by_key_image(&key_image).map(|tx| tx.id), Some(id));
+        assert!(pool.is_empty());
+        assert!(!pool.contains_key_image(&key_image));
+        assert_eq!(pool.size(), 0);
+    }
+
+    #[test]
+    fn evicts_lowest_fee_per_byte() {
+        let size = to_binary(&transaction(1, 100).raw).len();
+        let mut pool = MemoryPool::new(Config {
+                                           max_size: size * 2,
+                                           max_age: Duration::from_secs(60),
+                                       });
+
+        let (low, mid, high) = (transaction(1, 100 * size as u64),
+                                transaction(2, 200 * size as u64),
+                                transaction(3, 300 * size as u64));
+        let now = Instant::now();
+
+        pool.insert(mid.clone(), 200 * size as u64, now).unwrap();
+        pool.insert(high.clone(), 300 * size as u64, now).unwrap();
+        assert_eq!(pool.by_fee_per_byte(), vec![high.id.clone(), mid.id.clone()]);
+
+        match pool.insert(low.clone(), 100 * size as u64, now) {
+            Err(Error::PoolFull) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let higher = transaction(4, 400 * size as u64);
+        pool.insert(higher.clone(), 400 * size as u64, now).unwrap();
+        assert_eq!(pool.by_fee_per_byte(), vec![higher.id, high.id]);
+        assert!(!pool.contains(&mid.id));
+    }
+
+    #[test]
+    fn evicts_expired() {
+        let mut pool = MemoryPool::new(Config {
+                                           max_size: DEFAULT_MAX_SIZE,
+                                           max_age: Duration::from_secs(60),
+                                       });
+
+        let now = Instant::now();
+        let (old, new) = (transaction(1, 100), transaction(2, 100));
+        pool.insert(old.clone(), 100, now).unwrap();
+        pool.insert(new.clone(), 100, now + Duration::from_secs(30)).unwrap();
+
+        let expired = pool.remove_expired(now + Duration::from_secs(61));
+        assert_eq!(expired, vec![old]);
+        assert!(pool.contains(&new.id));
+    }
+}
@@ -0,0 +1,226 @@
+// Xmr, Monero node.
+// Copyright (C) 2018  Jean Pierre Dudey
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::time::Instant;
+
+use parking_lot::RwLock;
+
+use chain::{IndexedBlock, IndexedTransaction};
+use keys::KeyImage;
+use primitives::H256;
+use storage::SharedStore;
+use verification::{transaction_fee, verify_transaction};
+
+use error::Error;
+use memory_pool::{Config, MemoryPool, key_images};
+
+/// The memory pool of the node, it verifies the transactions against the
+/// blockchain before accepting them.
+pub struct TransactionPool {
+    storage: SharedStore,
+    pool: RwLock<MemoryPool>,
+}
+
+impl TransactionPool {
+    pub fn new(storage: SharedStore, config: Config) -> TransactionPool {
+        TransactionPool {
+            storage,
+            pool: RwLock::new(MemoryPool::new(config)),
+        }
+    }
+
+    /// Number of transactions in the pool.
+    pub fn len(&self) -> usize {
+        self.pool.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pool.read().is_empty()
+    }
+
+    /// Size of the transactions in the pool in bytes.
+    pub fn size(&self) -> usize {
+        self.pool.read().size()
+    }
+
+    pub fn contains(&self, id: &H256) -> bool {
+        self.pool.read().contains(id)
+    }
+
+    pub fn contains_key_image(&self, key_image: &KeyImage) -> bool {
+        self.pool.read().contains_key_image(key_image)
+    }
+
+    pub fn transaction(&self, id: &H256) -> Option<IndexedTransaction> {
+        self.pool.read().transaction(id)
+    }
+
+    /// Transaction ids sorted from the highest to the lowest fee per byte.
+    pub fn by_fee_per_byte(&self) -> Vec<H256> {
+        self.pool.read().by_fee_per_byte()
+    }
+
+    /// Verifies and inserts a transaction, either received from a peer or
+    /// submitted locally.
+    pub fn insert(&self, transaction: IndexedTransaction) -> Result<(), Error> {
+        verify_transaction(&transaction.raw)?;
+        let fee = transaction_fee(&transaction.raw)?;
+
+        if self.storage
+               .as_transaction_provider()
+               .transaction(transaction.id())
+               .is_some() {
+            return Err(Error::AlreadyInChain);
+        }
+
+        self.check_key_images(&transaction)?;
+
+        let now = Instant::now();
+        let mut pool = self.pool.write();
+        for tx in pool.remove_expired(now) {
+            debug!("Transaction {:?} expired", tx.id());
+        }
+
+        pool.insert(transaction, fee, now)
+    }
+
+    /// Fails if the transaction spends a key image that is already spent in
+    /// the blockchain.
+    fn check_key_images(&self, transaction: &IndexedTransaction) -> Result<(), Error> {
+        let key_image_provider = self.storage.as_key_image_provider();
+        let mut key_images = key_images(transaction);
+        match key_images.find(|key_image| key_image_provider.contains_key_image(key_image)) {
+            Some(key_image) => Err(Error::DoubleSpendInChain(key_image.clone())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn remove(&self, id: &H256) -> Option<IndexedTransaction> {
+        self.pool.write().remove(id)
+    }
+
+    /// Removes the transactions that stayed too long in the pool.
+    pub fn remove_expired(&self) -> Vec<IndexedTransaction> {
+        self.pool.write().remove_expired(Instant::now())
+    }
+
+    /// Removes the transactions of a block that was canonized and the ones
+    /// that spend the same key images.
+    pub fn on_canonize(&self, block: &IndexedBlock) {
+        let mut pool = self.pool.write();
+        for id in block.raw.tx_hashes.iter() {
+            pool.remove(id);
+
+            let tx = match self.storage.as_transaction_provider().transaction(id) {
+                Some(tx) => tx,
+                None => continue,
+            };
+
+            for key_image in key_images(&tx) {
+                if let Some(conflict) = pool.remove_by_key_image(key_image) {
+                    debug!("Transaction {:?} double spends {:?} of block {:?}",
+                           conflict.id(),
+                           key_image,
+                           block.id());
+                }
+            }
+        }
+    }
+
+    /// Returns the transactions of a decanonized block to the pool, except
+    /// the ones that are double spent in the chain we reorganized to.
+    pub fn on_decanonize(&self, block: &IndexedBlock) {
+        for id in block.raw.tx_hashes.iter() {
+            let tx = match self.storage.as_transaction_provider().transaction(id) {
+                Some(tx) => tx,
+                None => continue,
+            };
+
+            let result = self.check_key_images(&tx)
+                .and_then(|()| transaction_fee(&tx.raw).map_err(Error::from))
+                .and_then(|fee| self.pool.write().insert(tx, fee, Instant::now()));
+
+            if let Err(e) = result {
+                debug!("Couldn't return transaction {:?} to the pool: {}", id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Arc;
+
+    use chain::{Block, BlockHeader};
+    use chain::transaction::{SignatureType, Transaction, TransactionPrefix, TxInGen};
+    use db::BlockChainDatabase;
+    use memory_pool::tests::transaction;
+    use super::*;
+
+    fn block(prev_id: H256, height: u64, transactions: Vec<IndexedTransaction>) -> IndexedBlock {
+        let block = Block {
+            header: BlockHeader {
+                major_version: 1,
+                minor_version: 0,
+                timestamp: height * 120,
+                prev_id,
+                nonce: 0,
+            },
+            miner_tx: Transaction {
+                prefix: TransactionPrefix {
+                    version: 1,
+                    unlock_time: height + 60,
+                    vin: vec![TxInGen { height }.into()],
+                    vout: vec![],
+                    extra: vec![],
+                },
+                signature_type: SignatureType::Normal(vec![]),
+            },
+            tx_hashes: transactions.iter().map(|tx| tx.id().clone()).collect(),
+        };
+
+        let id = block.id();
+        IndexedBlock::with_transactions(block, id, transactions)
+    }
+
+    #[test]
+    fn double_spent_transactions_arent_returned_on_reorganization() {
+        let (spent, other, double_spend) = (transaction(1, 100),
+                                            transaction(2, 100),
+                                            transaction(1, 200));
+        let genesis = block(H256::default(), 0, vec![]);
+        let old = block(genesis.id().clone(), 1, vec![spent.clone(), other.clone()]);
+        let new = block(genesis.id().clone(), 1, vec![double_spend.clone()]);
+
+        let db = Arc::new(BlockChainDatabase::in_memory());
+        for block in [&genesis, &old].iter() {
+            db.insert((*block).clone(), 1).unwrap();
+            db.canonize(block.id()).unwrap();
+        }
+        db.insert(new.clone(), 2).unwrap();
+
+        let pool = TransactionPool::new(db.clone(), Config::default());
+
+        // The new branch is canonized before the old one goes back to the pool.
+        db.decanonize().unwrap();
+        db.canonize(new.id()).unwrap();
+        pool.on_decanonize(&old);
+
+        assert!(!pool.contains(spent.id()));
+        assert!(pool.contains(other.id()));
+        assert!(!pool.contains(double_spend.id()));
+    }
+}
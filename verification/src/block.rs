@@ -0,0 +1,167 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use chain::IndexedBlock;
+use chain::transaction::TxIn;
+
+use pow::{Difficulty, is_valid_proof_of_work};
+use transaction::{TransactionError, verify_transaction};
+
+#[derive(Debug, Fail, PartialEq, Eq)]
+pub enum BlockError {
+    #[fail(display = "block has {} transaction hashes but {} transactions", _0, _1)]
+    TransactionCount(usize, usize),
+    #[fail(display = "transaction #{} doesn't match the block transaction hash", _0)]
+    TransactionMismatch(usize),
+    #[fail(display = "miner transaction doesn't have a single generation input")]
+    InvalidMinerTransaction,
+    #[fail(display = "miner transaction height {} isn't the block height {}", _0, _1)]
+    MinerTransactionHeight(u64, u64),
+    #[fail(display = "invalid transaction #{}: {}", _0, _1)]
+    Transaction(usize, TransactionError),
+    #[fail(display = "block doesn't match the checkpoint at height {}", _0)]
+    Checkpoint(u64),
+    #[fail(display = "invalid proof of work")]
+    InvalidProofOfWork,
+}
+
+/// How much of a block is verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerificationLevel {
+    /// All the checks.
+    Full,
+    /// Only the structural checks, used for the blocks below the last
+    /// checkpoint since those are already known to be valid.
+    NoExpensive,
+}
+
+/// Verifies a block with it's transactions loaded, `height` is the height
+/// the block would have in the chain.
+pub fn verify_block(block: &IndexedBlock,
+                    height: u64,
+                    difficulty: Difficulty,
+                    level: VerificationLevel)
+                    -> Result<(), BlockError> {
+    verify_block_context_free(block, difficulty, level)?;
+    verify_block_context(block, height)
+}
+
+/// The checks that don't depend on the chain, they can be done in parallel
+/// for many blocks.
+pub fn verify_block_context_free(block: &IndexedBlock,
+                                 difficulty: Difficulty,
+                                 level: VerificationLevel)
+                                 -> Result<(), BlockError> {
+    let raw = &block.raw;
+    if raw.tx_hashes.len() != block.transactions.len() {
+        return Err(BlockError::TransactionCount(raw.tx_hashes.len(), block.transactions.len()));
+    }
+
+    if miner_transaction_height(block).is_none() {
+        return Err(BlockError::InvalidMinerTransaction);
+    }
+
+    for (i, (id, tx)) in raw.tx_hashes.iter().zip(block.transactions.iter()).enumerate() {
+        if *id != tx.id {
+            return Err(BlockError::TransactionMismatch(i));
+        }
+
+        verify_transaction(&tx.raw).map_err(|e| BlockError::Transaction(i, e))?;
+    }
+
+    if level == VerificationLevel::NoExpensive {
+        return Ok(());
+    }
+
+    // TODO: ring signatures and RingCT proofs, they aren't verified by
+    // `verify_transaction` yet.
+
+    if !is_valid_proof_of_work(raw.hash(), difficulty) {
+        return Err(BlockError::InvalidProofOfWork);
+    }
+
+    Ok(())
+}
+
+/// The checks that need to know where the block goes in the chain.
+pub fn verify_block_context(block: &IndexedBlock, height: u64) -> Result<(), BlockError> {
+    match miner_transaction_height(block) {
+        Some(miner_height) if miner_height == height => Ok(()),
+        Some(miner_height) => Err(BlockError::MinerTransactionHeight(miner_height, height)),
+        None => Err(BlockError::InvalidMinerTransaction),
+    }
+}
+
+/// The height in the generation input of the miner transaction, `None` if
+/// it doesn't have a single generation input.
+pub fn miner_transaction_height(block: &IndexedBlock) -> Option<u64> {
+    let vin = &block.raw.miner_tx.prefix.vin;
+    match (vin.len(), vin.first()) {
+        (1, Some(TxIn::Gen(txin))) => Some(txin.height),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chain::{Block, BlockHeader, IndexedBlock, IndexedTransaction};
+    use chain::transaction::{SignatureType, Transaction, TransactionPrefix, TxIn, TxInGen,
+                             TxInToKey, TxOut, TxOutToKey};
+    use super::*;
+
+    fn transaction(vin: Vec<TxIn>, amount: u64) -> Transaction {
+        Transaction {
+            prefix: TransactionPrefix {
+                version: 1,
+                unlock_time: 0,
+                vin,
+                vout: vec![TxOut {
+                               amount,
+                               target: TxOutToKey { key: Default::default() }.into(),
+                           }],
+                extra: vec![],
+            },
+            signature_type: SignatureType::Normal(vec![]),
+        }
+    }
+
+    fn block(height: u64, transactions: Vec<IndexedTransaction>) -> IndexedBlock {
+        let block = Block {
+            header: BlockHeader::default(),
+            miner_tx: transaction(vec![TxInGen { height }.into()], 1000),
+            tx_hashes: transactions.iter().map(|tx| tx.id.clone()).collect(),
+        };
+        let id = block.id();
+
+        IndexedBlock::with_transactions(block, id, transactions)
+    }
+
+    #[test]
+    fn structural_checks() {
+        let input = TxInToKey {
+            amount: 100,
+            key_offsets: vec![1],
+            k_image: Default::default(),
+        };
+        let tx: IndexedTransaction = transaction(vec![input.into()], 90).into();
+
+        let level = VerificationLevel::NoExpensive;
+        let mut b = block(5, vec![tx]);
+        assert_eq!(verify_block(&b, 5, Difficulty(1), level), Ok(()));
+        assert_eq!(verify_block(&b, 6, Difficulty(1), level),
+                   Err(BlockError::MinerTransactionHeight(5, 6)));
+
+        b.transactions[0].raw.prefix.vout[0].amount = 0;
+        assert_eq!(verify_block(&b, 5, Difficulty(1), level),
+                   Err(BlockError::Transaction(0, TransactionError::ZeroAmountOutput(0))));
+
+        b.transactions.clear();
+        assert_eq!(verify_block(&b, 5, Difficulty(1), level),
+                   Err(BlockError::TransactionCount(1, 0)));
+    }
+}
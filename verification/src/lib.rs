@@ -6,8 +6,22 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+extern crate failure;
+#[macro_use]
+extern crate failure_derive;
+
+extern crate xmr_chain as chain;
+extern crate xmr_format as format;
 extern crate xmr_primitives as primitives;
 
+mod block;
 mod pow;
+mod transaction;
 
-pub use pow::{Difficulty, is_valid_proof_of_work};
+pub use block::{BlockError, VerificationLevel, miner_transaction_height, verify_block,
+                verify_block_context, verify_block_context_free};
+pub use pow::{DIFFICULTY_BLOCKS_COUNT, DIFFICULTY_CUT, DIFFICULTY_LAG, DIFFICULTY_TARGET_V1,
+              DIFFICULTY_TARGET_V2, DIFFICULTY_WINDOW, Difficulty, difficulty_target,
+              is_valid_proof_of_work, next_difficulty};
+pub use transaction::{MAX_TRANSACTION_SIZE, TransactionError, transaction_fee,
+                      verify_transaction};
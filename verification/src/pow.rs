@@ -9,9 +9,73 @@
 use primitives::H256;
 use std::u64;
 
-#[derive(Debug)]
+/// Number of blocks used to calculate the difficulty.
+pub const DIFFICULTY_WINDOW: usize = 720;
+/// Number of most recent blocks left out of the window.
+pub const DIFFICULTY_LAG: usize = 15;
+/// Number of outlier timestamps removed from each side of the window.
+pub const DIFFICULTY_CUT: usize = 60;
+/// Number of blocks needed to calculate the difficulty of the next block.
+pub const DIFFICULTY_BLOCKS_COUNT: usize = DIFFICULTY_WINDOW + DIFFICULTY_LAG;
+/// Target seconds between blocks before the second hard fork.
+pub const DIFFICULTY_TARGET_V1: u64 = 60;
+/// Target seconds between blocks since the second hard fork.
+pub const DIFFICULTY_TARGET_V2: u64 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Difficulty(pub u64);
 
+/// The target seconds between blocks for a chain whose best block has
+/// the given major version.
+pub fn difficulty_target(major_version: u8) -> u64 {
+    if major_version < 2 {
+        DIFFICULTY_TARGET_V1
+    } else {
+        DIFFICULTY_TARGET_V2
+    }
+}
+
+/// Calculates the difficulty of the next block from the timestamps and
+/// cumulative difficulties of the last `DIFFICULTY_BLOCKS_COUNT` blocks,
+/// oldest first.
+///
+/// Returns `Difficulty(0)` on overflow, no block can be mined with it.
+pub fn next_difficulty(timestamps: &[u64],
+                       cumulative_difficulties: &[u64],
+                       target_seconds: u64)
+                       -> Difficulty {
+    assert_eq!(timestamps.len(), cumulative_difficulties.len());
+
+    // The most recent blocks are left out.
+    let length = timestamps.len().min(DIFFICULTY_WINDOW);
+    if length <= 1 {
+        return Difficulty(1);
+    }
+
+    let mut timestamps = timestamps[..length].to_vec();
+    timestamps.sort();
+
+    let (cut_begin, cut_end) = if length <= DIFFICULTY_WINDOW - 2 * DIFFICULTY_CUT {
+        (0, length)
+    } else {
+        let cut_begin = (length - (DIFFICULTY_WINDOW - 2 * DIFFICULTY_CUT) + 1) / 2;
+        (cut_begin, cut_begin + (DIFFICULTY_WINDOW - 2 * DIFFICULTY_CUT))
+    };
+
+    let time_span = match timestamps[cut_end - 1] - timestamps[cut_begin] {
+        0 => 1,
+        time_span => time_span,
+    };
+    let total_work = cumulative_difficulties[cut_end - 1] - cumulative_difficulties[cut_begin];
+
+    let (low, high) = mul(total_work, target_seconds);
+    if high != 0 || low.checked_add(time_span - 1).is_none() {
+        return Difficulty(0);
+    }
+
+    Difficulty((low + time_span - 1) / time_span)
+}
+
 pub fn is_valid_proof_of_work(hash: H256, Difficulty(difficulty): Difficulty) -> bool {
     let comps = hash.u64_components();
 
@@ -27,11 +91,12 @@ pub fn is_valid_proof_of_work(hash: H256, Difficulty(difficulty): Difficulty) ->
 }
 
 fn cadd(a: u64, b: u64) -> bool {
-    a + b < a
+    a.overflowing_add(b).1
 }
 
 fn cadc(a: u64, b: u64, c: bool) -> bool {
-    a + b < a || (c && a + b == u64::MAX)
+    let (sum, carry) = a.overflowing_add(b);
+    carry || (c && sum == u64::MAX)
 }
 
 fn mul(a: u64, b: u64) -> (u64, u64) {
@@ -67,3 +132,60 @@ fn mul(a: u64, b: u64) -> (u64, u64) {
 
     (low, high)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_difficulty_of_short_chains() {
+        assert_eq!(next_difficulty(&[], &[], DIFFICULTY_TARGET_V2), Difficulty(1));
+        assert_eq!(next_difficulty(&[0], &[1], DIFFICULTY_TARGET_V2), Difficulty(1));
+    }
+
+    #[test]
+    fn next_difficulty_keeps_constant_rate() {
+        let target = DIFFICULTY_TARGET_V2;
+        let timestamps: Vec<u64> = (0..DIFFICULTY_BLOCKS_COUNT as u64)
+            .map(|i| i * target)
+            .collect();
+        let cumulative_difficulties: Vec<u64> = (1..DIFFICULTY_BLOCKS_COUNT as u64 + 1)
+            .map(|i| i * 1000)
+            .collect();
+
+        assert_eq!(next_difficulty(&timestamps, &cumulative_difficulties, target),
+                   Difficulty(1000));
+    }
+
+    #[test]
+    fn next_difficulty_follows_hash_rate() {
+        let target = DIFFICULTY_TARGET_V2;
+        // Blocks found twice as fast as the target.
+        let timestamps: Vec<u64> = (0..DIFFICULTY_BLOCKS_COUNT as u64)
+            .map(|i| i * target / 2)
+            .collect();
+        let cumulative_difficulties: Vec<u64> = (1..DIFFICULTY_BLOCKS_COUNT as u64 + 1)
+            .map(|i| i * 1000)
+            .collect();
+
+        assert_eq!(next_difficulty(&timestamps, &cumulative_difficulties, target),
+                   Difficulty(2000));
+    }
+
+    #[test]
+    fn proof_of_work_with_carries() {
+        let mut hash = [0u8; 32];
+        hash[..8].copy_from_slice(&[0xff; 8]);
+        hash[8] = 1;
+
+        assert!(is_valid_proof_of_work(H256::from(hash), Difficulty(u64::MAX)));
+    }
+
+    #[test]
+    fn next_difficulty_overflow() {
+        let timestamps = [0, 1];
+        let cumulative_difficulties = [0, u64::MAX];
+        assert_eq!(next_difficulty(&timestamps, &cumulative_difficulties, DIFFICULTY_TARGET_V2),
+                   Difficulty(0));
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright 2018 Jean Pierre Dudey <jeandudey@hotmail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashSet;
+
+use chain::transaction::{Transaction, TxIn, TxOutTarget};
+use format::to_binary;
+
+/// Maximum size of a transaction blob.
+pub const MAX_TRANSACTION_SIZE: usize = 1000000;
+
+#[derive(Debug, Fail, PartialEq, Eq)]
+pub enum TransactionError {
+    #[fail(display = "transaction is too big ({} bytes)", _0)]
+    TooBig(usize),
+    #[fail(display = "unsupported transaction version ({})", _0)]
+    UnsupportedVersion(u8),
+    #[fail(display = "transaction has no inputs")]
+    NoInputs,
+    #[fail(display = "transaction has no outputs")]
+    NoOutputs,
+    #[fail(display = "input #{} isn't a key input", _0)]
+    InvalidInputType(usize),
+    #[fail(display = "input #{} has an empty ring", _0)]
+    EmptyRing(usize),
+    #[fail(display = "output #{} isn't a key output", _0)]
+    InvalidOutputType(usize),
+    #[fail(display = "output #{} has a zero amount", _0)]
+    ZeroAmountOutput(usize),
+    #[fail(display = "input #{} key image is duplicated", _0)]
+    DuplicatedKeyImage(usize),
+    #[fail(display = "transaction amounts overflow")]
+    AmountOverflow,
+    #[fail(display = "transaction outputs exceed it's inputs")]
+    OutputsExceedInputs,
+}
+
+/// Verifies the transaction without looking at the blockchain or other
+/// transactions.
+pub fn verify_transaction(tx: &Transaction) -> Result<(), TransactionError> {
+    let size = to_binary(tx).len();
+    if size > MAX_TRANSACTION_SIZE {
+        return Err(TransactionError::TooBig(size));
+    }
+
+    if tx.prefix.version != 1 {
+        return Err(TransactionError::UnsupportedVersion(tx.prefix.version));
+    }
+
+    if tx.prefix.vin.is_empty() {
+        return Err(TransactionError::NoInputs);
+    }
+
+    if tx.prefix.vout.is_empty() {
+        return Err(TransactionError::NoOutputs);
+    }
+
+    let mut key_images = HashSet::new();
+    for (i, txin) in tx.prefix.vin.iter().enumerate() {
+        let txin = match *txin {
+            TxIn::ToKey(ref txin) => txin,
+            _ => return Err(TransactionError::InvalidInputType(i)),
+        };
+
+        if txin.key_offsets.is_empty() {
+            return Err(TransactionError::EmptyRing(i));
+        }
+
+        if !key_images.insert(&txin.k_image) {
+            return Err(TransactionError::DuplicatedKeyImage(i));
+        }
+    }
+
+    for (i, txout) in tx.prefix.vout.iter().enumerate() {
+        match txout.target {
+            TxOutTarget::ToKey(_) => {}
+            _ => return Err(TransactionError::InvalidOutputType(i)),
+        }
+
+        if txout.amount == 0 {
+            return Err(TransactionError::ZeroAmountOutput(i));
+        }
+    }
+
+    transaction_fee(tx).map(|_| ())
+}
+
+/// Calculates the fee of a transaction, the transaction inputs must be
+/// key inputs.
+pub fn transaction_fee(tx: &Transaction) -> Result<u64, TransactionError> {
+    let mut inputs = 0u64;
+    for txin in tx.prefix.vin.iter() {
+        if let TxIn::ToKey(ref txin) = *txin {
+            inputs = inputs
+                .checked_add(txin.amount)
+                .ok_or(TransactionError::AmountOverflow)?;
+        }
+    }
+
+    let mut outputs = 0u64;
+    for txout in tx.prefix.vout.iter() {
+        outputs = outputs
+            .checked_add(txout.amount)
+            .ok_or(TransactionError::AmountOverflow)?;
+    }
+
+    inputs
+        .checked_sub(outputs)
+        .ok_or(TransactionError::OutputsExceedInputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use chain::transaction::{SignatureType, Transaction, TransactionPrefix, TxIn, TxInGen,
+                             TxInToKey, TxOut, TxOutToKey};
+    use super::*;
+
+    fn transaction(vin: Vec<TxIn>, amounts: &[u64]) -> Transaction {
+        Transaction {
+            prefix: TransactionPrefix {
+                version: 1,
+                unlock_time: 0,
+                vin,
+                vout: amounts
+                    .iter()
+                    .map(|amount| {
+                             TxOut {
+                                 amount: *amount,
+                                 target: TxOutToKey { key: Default::default() }.into(),
+                             }
+                         })
+                    .collect(),
+                extra: vec![],
+            },
+            signature_type: SignatureType::Normal(vec![]),
+        }
+    }
+
+    fn input(amount: u64, k_image: u8) -> TxIn {
+        let mut txin = TxInToKey {
+            amount,
+            key_offsets: vec![1],
+            k_image: Default::default(),
+        };
+        txin.k_image.0[0] = k_image;
+        txin.into()
+    }
+
+    #[test]
+    fn valid_transaction() {
+        let tx = transaction(vec![input(100, 1), input(50, 2)], &[120]);
+        assert_eq!(verify_transaction(&tx), Ok(()));
+        assert_eq!(transaction_fee(&tx), Ok(30));
+    }
+
+    #[test]
+    fn invalid_transactions() {
+        let tx = transaction(vec![TxInGen { height: 0 }.into()], &[120]);
+        assert_eq!(verify_transaction(&tx),
+                   Err(TransactionError::InvalidInputType(0)));
+
+        let tx = transaction(vec![input(100, 1), input(50, 1)], &[120]);
+        assert_eq!(verify_transaction(&tx),
+                   Err(TransactionError::DuplicatedKeyImage(1)));
+
+        let tx = transaction(vec![input(100, 1)], &[120]);
+        assert_eq!(verify_transaction(&tx),
+                   Err(TransactionError::OutputsExceedInputs));
+
+        let tx = transaction(vec![input(100, 1)], &[0]);
+        assert_eq!(verify_transaction(&tx),
+                   Err(TransactionError::ZeroAmountOutput(0)));
+    }
+}